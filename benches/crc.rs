@@ -0,0 +1,49 @@
+// Compares the byte at a time and slicing-by-8 CRC implementations. Run with `cargo bench`
+use std::hint::black_box;
+use std::time::Instant;
+use viu_rs::crc::CRCHandler;
+
+const SIZE: usize = 16 * 1024 * 1024;
+const ROUNDS: u32 = 10;
+
+fn bench(name: &str, buf: &[u8], update: impl Fn(u32, &[u8]) -> u32) -> u32 {
+    let start = Instant::now();
+    let mut crc = 0;
+    for _ in 0..ROUNDS {
+        // Stops the compiler from computing the CRC only once
+        crc = black_box(update(0xFFFFFFFF, black_box(buf))) ^ 0xFFFFFFFF;
+    }
+    let seconds = start.elapsed().as_secs_f64();
+    let megabytes = (SIZE as f64 * ROUNDS as f64) / (1024.0 * 1024.0);
+
+    println!(
+        "{:<12}{:>10.1} MB/s  crc: {:08x}",
+        name,
+        megabytes / seconds,
+        crc
+    );
+    crc
+}
+
+fn main() {
+    // Pseudo random data, so that the table lookups are spread out like they are for image data
+    let mut state = 0x12345678u32;
+    let buf: Vec<u8> = (0..SIZE)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect();
+
+    let handler = CRCHandler::new();
+    let bytewise = bench("bytewise", &buf, |crc, buf| {
+        handler.update_crc_bytewise(crc, buf)
+    });
+    let sliced = bench("slicing-by-8", &buf, |crc, buf| {
+        handler.update_crc(crc, buf)
+    });
+
+    assert_eq!(bytewise, sliced, "implementations disagree");
+}
@@ -1,15 +1,15 @@
-use crate::png::chunks::ihdr;
+use crate::exif::Exif;
+pub use crate::image_buffer::{ImageBuffer, ImageView};
+use crate::png::chunks::{ancillary, ihdr, ChunkInfo};
+use crate::render::Renderer;
+use crate::resample;
+use crate::sizing::{FitMode, SizingPolicy};
+use std::any::Any;
+use std::collections::HashMap;
 use std::io;
 
 pub type RGBColor = (u8, u8, u8);
-pub type Image<T> = Vec<Vec<T>>;
-
-pub enum Effect {
-    NoEffect,
-    Blur(usize),
-    ASCII,
-    GrayScale,
-}
+pub type Image<T> = ImageBuffer<T>;
 
 #[derive(Debug)]
 pub enum ColorType {
@@ -43,7 +43,27 @@ pub struct Metadata {
     color_type: ColorType,
     palette: Option<Vec<RGBColor>>,
     alpha: Option<AlphaValue>,
-    bkgd: RGBColor,
+    bkgd: Option<RGBColor>,
+    interlaced: bool,
+    format: &'static str,
+    text: Vec<ancillary::TextChunk>,
+    time: Option<ancillary::TIMEChunk>,
+    chunks: Vec<ChunkInfo>,
+    exif: Option<Exif>,
+    physical: Option<ancillary::PHYsChunk>,
+    // Set once the EXIF orientation has swapped the width and height of the decoded image
+    transposed: bool,
+    significant_bits: Option<ancillary::SBITChunk>,
+    histogram: Option<ancillary::HISTChunk>,
+    suggested_palettes: Vec<ancillary::SPLTChunk>,
+    // Values attached by chunk handlers registered by library users
+    extensions: HashMap<String, Box<dyn Any>>,
+}
+
+impl Default for Metadata {
+    fn default() -> Metadata {
+        Metadata::new()
+    }
 }
 
 impl Metadata {
@@ -51,7 +71,19 @@ impl Metadata {
         Metadata {
             alpha: None,
             palette: None,
-            bkgd: (0, 0, 0), // Default background is transparent
+            bkgd: None,
+            interlaced: false,
+            format: "",
+            text: Vec::new(),
+            time: None,
+            chunks: Vec::new(),
+            exif: None,
+            physical: None,
+            transposed: false,
+            significant_bits: None,
+            histogram: None,
+            suggested_palettes: Vec::new(),
+            extensions: HashMap::new(),
             width: 0,
             height: 0,
             bit_depth: 0,
@@ -64,6 +96,22 @@ impl Metadata {
         self.height = ihdr_chunk.height();
         self.bit_depth = ihdr_chunk.bit_depth();
         self.color_type = ihdr_chunk.color_type();
+        self.interlaced = ihdr_chunk.interlace_method() == ihdr::InterlaceMethod::Adam7;
+    }
+
+    // Used by decoders for formats that have no IHDR chunk. The color type and bit depth are
+    // the closest PNG equivalents of the source format
+    pub fn set_image_info(
+        &mut self,
+        width: u32,
+        height: u32,
+        bit_depth: u8,
+        color_type: ColorType,
+    ) {
+        self.width = width;
+        self.height = height;
+        self.bit_depth = bit_depth;
+        self.color_type = color_type;
     }
 
     pub fn width(&self) -> u32 {
@@ -109,12 +157,124 @@ impl Metadata {
         self.alpha = Some(alpha);
     }
 
+    // Default background is transparent
     pub fn bkgd(&self) -> &RGBColor {
-        &self.bkgd
+        self.bkgd.as_ref().unwrap_or(&(0, 0, 0))
+    }
+
+    // Only set if the image specifies a background
+    pub fn explicit_bkgd(&self) -> Option<&RGBColor> {
+        self.bkgd.as_ref()
     }
 
     pub fn set_bkgd(&mut self, bkgd: RGBColor) {
-        self.bkgd = bkgd;
+        self.bkgd = Some(bkgd);
+    }
+
+    pub fn interlaced(&self) -> bool {
+        self.interlaced
+    }
+
+    // Name of the file format the image was decoded from
+    pub fn format(&self) -> &'static str {
+        self.format
+    }
+
+    pub fn set_format(&mut self, format: &'static str) {
+        self.format = format;
+    }
+
+    pub fn text(&self) -> &[ancillary::TextChunk] {
+        &self.text
+    }
+
+    pub fn add_text(&mut self, text: ancillary::TextChunk) {
+        self.text.push(text);
+    }
+
+    pub fn time(&self) -> &Option<ancillary::TIMEChunk> {
+        &self.time
+    }
+
+    pub fn set_time(&mut self, time: ancillary::TIMEChunk) {
+        self.time = Some(time);
+    }
+
+    // Chunks in the order they appear in the file. Only filled in for PNG images
+    pub fn chunks(&self) -> &[ChunkInfo] {
+        &self.chunks
+    }
+
+    pub fn add_chunk(&mut self, chunk: ChunkInfo) {
+        self.chunks.push(chunk);
+    }
+
+    pub fn exif(&self) -> &Option<Exif> {
+        &self.exif
+    }
+
+    pub fn set_exif(&mut self, exif: Exif) {
+        self.exif = Some(exif);
+    }
+
+    pub fn physical(&self) -> &Option<ancillary::PHYsChunk> {
+        &self.physical
+    }
+
+    pub fn set_physical(&mut self, physical: ancillary::PHYsChunk) {
+        self.physical = Some(physical);
+    }
+
+    pub fn significant_bits(&self) -> &Option<ancillary::SBITChunk> {
+        &self.significant_bits
+    }
+
+    pub fn set_significant_bits(&mut self, significant_bits: ancillary::SBITChunk) {
+        self.significant_bits = Some(significant_bits);
+    }
+
+    pub fn histogram(&self) -> &Option<ancillary::HISTChunk> {
+        &self.histogram
+    }
+
+    pub fn set_histogram(&mut self, histogram: ancillary::HISTChunk) {
+        self.histogram = Some(histogram);
+    }
+
+    pub fn suggested_palettes(&self) -> &Vec<ancillary::SPLTChunk> {
+        &self.suggested_palettes
+    }
+
+    pub fn add_suggested_palette(&mut self, palette: ancillary::SPLTChunk) {
+        self.suggested_palettes.push(palette);
+    }
+
+    // Replaces any value previously stored under the same key
+    pub fn set_extension<T: Any>(&mut self, key: &str, value: T) {
+        self.extensions.insert(key.to_owned(), Box::new(value));
+    }
+
+    // None if nothing was stored under the key, or if it has a different type
+    pub fn extension<T: Any>(&self, key: &str) -> Option<&T> {
+        self.extensions.get(key)?.downcast_ref()
+    }
+
+    pub fn set_transposed(&mut self) {
+        self.transposed = true;
+    }
+
+    // Width of a pixel divided by its height in the decoded image, pixels are square unless
+    // specified otherwise. Pixels turned on their side by the orientation are inverted
+    pub fn pixel_aspect(&self) -> f32 {
+        let aspect = self
+            .physical
+            .as_ref()
+            .map_or(1.0, |physical| physical.pixel_aspect());
+        if self.transposed {
+            1.0 / aspect
+        } else {
+            aspect
+        }
     }
 }
 
@@ -122,6 +282,74 @@ pub fn is_transparent(r: u8, g: u8, b: u8) -> bool {
     r == 0 && g == 0 && b == 0
 }
 
+// Ansi displays completely transparent if colour is set to (0, 0, 0) [at least for my terminal]
+// with rgb colour codes. This make sure that opaque black pixels will be put as black instead
+// of transparent
+// Blue is increased since its least receptive for the human eye
+pub fn opaque(r: u8, g: u8, b: u8) -> RGBColor {
+    if is_transparent(r, g, b) {
+        (0, 0, 1)
+    } else {
+        (r, g, b)
+    }
+}
+
+// Red, green, blue and how opaque a pixel is, for filters that mix pixels together. Transparent
+// pixels count as black, as the colors already have alpha applied over black
+pub type Sample = [f32; 4];
+
+pub fn to_sample(&(r, g, b): &RGBColor) -> Sample {
+    if is_transparent(r, g, b) {
+        [0.0; 4]
+    } else {
+        [r as f32, g as f32, b as f32, 1.0]
+    }
+}
+
+pub fn to_color(sample: &Sample) -> RGBColor {
+    // Mostly transparent pixels stay transparent
+    if sample[3] < 0.5 {
+        return (0, 0, 0);
+    }
+    let channel = |value: f32| value.round().clamp(0.0, 255.0) as u8;
+    opaque(channel(sample[0]), channel(sample[1]), channel(sample[2]))
+}
+
+pub fn apply_alpha(r: u8, g: u8, b: u8, a: u8) -> RGBColor {
+    let opacity = (a as f32) / 256.0;
+    (
+        (r as f32 * opacity) as u8,
+        (g as f32 * opacity) as u8,
+        (b as f32 * opacity) as u8,
+    )
+}
+
+// Converts a straight (non premultiplied) RGBA pixel into the displayed colour, leaving fully
+// opaque pixels untouched
+pub fn rgba_color(r: u8, g: u8, b: u8, a: u8) -> RGBColor {
+    let (r, g, b) = opaque(r, g, b);
+    if a == 255 {
+        (r, g, b)
+    } else {
+        apply_alpha(r, g, b, a)
+    }
+}
+
+// Decoders refuse images with more pixels than this, so that a damaged or malicious header can't
+// make them allocate more memory than there is
+pub const MAX_PIXELS: usize = 1 << 28;
+
+// width * height, or an error if the image has more than MAX_PIXELS pixels
+pub fn checked_pixels(width: usize, height: usize) -> io::Result<usize> {
+    match width.checked_mul(height) {
+        Some(pixels) if pixels <= MAX_PIXELS => Ok(pixels),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Image is too large: {}x{}", width, height),
+        )),
+    }
+}
+
 pub fn from_bytes_u32(bytes: &[u8]) -> u32 {
     ((bytes[0] as u32) << 24)
         + ((bytes[1] as u32) << 16)
@@ -133,109 +361,78 @@ pub fn from_bytes_u16(bytes: &[u8]) -> u16 {
     ((bytes[0] as u16) << 8) + (bytes[1] as u16)
 }
 
-pub fn auto_downsize_image(image: Image<RGBColor>, effect: &Effect) -> io::Result<Image<RGBColor>> {
-    // Terminal dimensions
-    let (tw, th) = if let Some((w, h)) = term_size::dimensions() {
-        match effect {
-            // Double characters for one square pixel
-            // Eg:
-            //      ::  <-- One pixel
-            //  not :
-            Effect::ASCII => (w / 2, h),
-            _ => {
-                // Use fg + bg to make one character 2 pixels
-                (w, h * 2)
-            }
-        }
-    } else {
-        return Err(io::Error::new(
-            io::ErrorKind::NotFound,
-            "Failed to get Terminal size",
-        ));
-    };
+// Little endian versions, used by the formats that originate from x86 (TGA, ICO, BMP)
+pub fn from_bytes_u32_le(bytes: &[u8]) -> u32 {
+    ((bytes[3] as u32) << 24)
+        + ((bytes[2] as u32) << 16)
+        + ((bytes[1] as u32) << 8)
+        + (bytes[0] as u32)
+}
 
-    // Raw image dimensions
-    let iw = image[0].len();
-    let ih = image.len();
+pub fn from_bytes_u16_le(bytes: &[u8]) -> u16 {
+    ((bytes[1] as u16) << 8) + (bytes[0] as u16)
+}
 
-    println!("t: {}x{}, i: {}x{}", tw, th, iw, ih);
+// Resizes the image as the sizing policy asks. pixel_aspect is the width of a pixel divided by
+// its height, and non-square pixels, as well as terminal cells that aren't twice as tall as they
+// are wide, are corrected by shrinking one of the axes
+pub fn auto_downsize_image(
+    image: Image<RGBColor>,
+    renderer: &Renderer,
+    policy: &SizingPolicy,
+    pixel_aspect: f32,
+) -> io::Result<Image<RGBColor>> {
+    if !policy.resize || image.is_empty() {
+        return Ok(image);
+    }
 
-    // The required image dimensions
-    let (w, h, r) = if tw > iw && th > ih {
-        (iw, ih, 1.0)
-    } else if tw / th > iw / ih {
-        let r = ih as f32 / th as f32;
+    // Raw image dimensions
+    let iw = image.width();
+    let ih = image.height();
 
-        ((iw as f32 / r) as usize, th, r)
+    // Dimensions with square pixels, once drawn
+    let pixel_aspect = pixel_aspect / policy.drawn_pixel_aspect();
+    let (ew, eh) = if pixel_aspect > 1.0 {
+        (iw as f32, ih as f32 / pixel_aspect)
     } else {
-        let r = iw as f32 / tw as f32;
+        (iw as f32 * pixel_aspect, ih as f32)
+    };
 
-        (tw, (ih as f32 / r) as usize, r)
+    let (tw, th) = policy.target(renderer)?;
+    let scale_x = tw.map(|tw| tw as f32 / ew);
+    let scale_y = th.map(|th| th as f32 / eh);
+
+    // Fill covers the target, so the larger scale is used, which is then cropped
+    let scale = match (scale_x, scale_y, policy.mode) {
+        (Some(sx), Some(sy), FitMode::Fill) => sx.max(sy),
+        (Some(sx), Some(sy), _) => sx.min(sy),
+        (Some(s), None, _) | (None, Some(s), _) => s,
+        (None, None, _) => 1.0,
+    };
+    // Small images are only made bigger if a size was asked for
+    let scale = if policy.is_explicit() {
+        scale
+    } else {
+        scale.min(1.0)
     };
 
-    println!("Print image height: {}x{} ratio: {}", w, h, r);
+    let size = |length: f32| ((length * scale).round() as usize).max(1);
+    let (w, h) = match (policy.mode, tw, th) {
+        (FitMode::Stretch, Some(tw), Some(th)) => (tw, th),
+        _ => (size(ew), size(eh)),
+    };
 
-    if r == 1.0 {
-        return Ok(image);
-    }
+    let image = if w == iw && h == ih {
+        image
+    } else {
+        resample::resize(&image, w, h, policy.filter)
+    };
 
-    let rstep = r * 0.98;
-    let ir2 = 1.0 / (r * r);
-
-    let mut downsized_image: Image<RGBColor> = Vec::new();
-
-    for y in 0..h {
-        let mut scanline = Vec::new();
-
-        let mut sr = 0f32;
-        let mut sg = 0f32;
-        let mut sb = 0f32;
-
-        for x in 0..w {
-            let sx1 = r * x as f32;
-            let sy1 = r * y as f32;
-            let sx2 = sx1 + rstep;
-            let sy2 = sy1 + rstep;
-
-            let istart = sx1 as usize;
-            let iend = sx2.ceil() as usize;
-            let jstart = sy1 as usize;
-            let jend = sy2.ceil() as usize;
-
-            for i in istart..iend {
-                for j in jstart..jend {
-                    let dx = if i == istart {
-                        (i + 1) as f32 - sx1
-                    } else if i == iend - 1 {
-                        sx2 - (i as f32)
-                    } else {
-                        1f32
-                    };
-
-                    let dy = if j == jstart {
-                        (j + 1) as f32 - sy1
-                    } else if j == jend - 1 {
-                        sy2 - (j as f32)
-                    } else {
-                        1f32
-                    };
-
-                    let ar_ratio = dx * dy;
-
-                    sr += (image[j][i].0 as f32) * ar_ratio;
-                    sg += (image[j][i].1 as f32) * ar_ratio;
-                    sb += (image[j][i].2 as f32) * ar_ratio;
-                }
-            }
-
-            sr *= ir2;
-            sg *= ir2;
-            sb *= ir2;
-
-            scanline.push((sr as u8, sg as u8, sb as u8));
+    match (policy.mode, tw, th) {
+        (FitMode::Fill, Some(tw), Some(th)) if w > tw || h > th => {
+            let (cw, ch) = (w.min(tw), h.min(th));
+            Ok(image.view((w - cw) / 2, (h - ch) / 2, cw, ch).to_buffer())
         }
-        downsized_image.push(scanline);
+        _ => Ok(image),
     }
-
-    Ok(downsized_image)
 }
@@ -1,43 +1,90 @@
 pub type VerifyResult = Result<(), u32>;
 
-pub struct CRCHandler {
-    // Table of CRCs of all 8-bit messages.
-    table: [u32; 256],
+const POLYNOMIAL: u32 = 0xEDB88320;
+
+// TABLES[0] is the table of CRCs of all 8-bit messages. TABLES[k][n] is the CRC of the byte n
+// followed by k zero bytes, which lets 8 bytes be processed with independent lookups
+static TABLES: [[u32; 256]; 8] = make_tables();
+
+const fn make_tables() -> [[u32; 256]; 8] {
+    let mut tables = [[0; 256]; 8];
+
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            if c & 1 > 0 {
+                c = POLYNOMIAL ^ (c >> 1);
+            } else {
+                c >>= 1;
+            }
+            k += 1;
+        }
+        tables[0][n] = c;
+        n += 1;
+    }
+
+    let mut n = 0;
+    while n < 256 {
+        let mut k = 1;
+        while k < 8 {
+            let c = tables[k - 1][n];
+            tables[k][n] = tables[0][(c & 0xFF) as usize] ^ (c >> 8);
+            k += 1;
+        }
+        n += 1;
+    }
+
+    tables
 }
 
+// The tables are computed at compile time, so this is free to create
+#[derive(Default)]
+pub struct CRCHandler;
+
 impl CRCHandler {
-    // Make the table for a fast CRC.
     pub fn new() -> CRCHandler {
-        let mut crc_table = [0; 256];
-        for n in 0..256 {
-            let mut c = n as u32;
-            for _ in 0..8 {
-                if c & 1 > 0 {
-                    c = 0xEDB88320 ^ (c >> 1);
-                } else {
-                    c = c >> 1;
-                }
-            }
-            crc_table[n] = c;
+        CRCHandler
+    }
+
+    // Update a running CRC with the bytes in buf, one byte at a time. The CRC should be
+    // initialized to all 1's, and the transmitted value is the 1's complement of the final
+    // running CRC (see the crc() routine below)
+    pub fn update_crc_bytewise(&self, crc: u32, buf: &[u8]) -> u32 {
+        let table = &TABLES[0];
+        let mut c = crc;
+        for &byte in buf {
+            c = table[((c ^ byte as u32) & 0xFF) as usize] ^ (c >> 8);
         }
-        CRCHandler { table: crc_table }
+        c
     }
 
-    // Update a running CRC with the bytes buf[0..len-1]--the CRC
-    // should be initialized to all 1's, and the transmitted value
-    // is the 1's complement of the final running CRC (see the
-    // crc() routine below)).
-    fn update_crc(&self, crc: u32, buf: &[u8], len: usize) -> u32 {
+    // Same as update_crc_bytewise, but reads 8 bytes per iteration
+    pub fn update_crc(&self, crc: u32, buf: &[u8]) -> u32 {
         let mut c = crc;
-        for n in 0..len {
-            c = self.table[((c ^ buf[n] as u32) & 0xFF) as usize] ^ (c >> 8);
+        let mut chunks = buf.chunks_exact(8);
+
+        for chunk in &mut chunks {
+            let low = c ^ u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+            let high = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+
+            c = TABLES[7][(low & 0xFF) as usize]
+                ^ TABLES[6][((low >> 8) & 0xFF) as usize]
+                ^ TABLES[5][((low >> 16) & 0xFF) as usize]
+                ^ TABLES[4][(low >> 24) as usize]
+                ^ TABLES[3][(high & 0xFF) as usize]
+                ^ TABLES[2][((high >> 8) & 0xFF) as usize]
+                ^ TABLES[1][((high >> 16) & 0xFF) as usize]
+                ^ TABLES[0][(high >> 24) as usize];
         }
-        return c;
+
+        self.update_crc_bytewise(c, chunks.remainder())
     }
 
-    // Return the CRC of the bytes buf[0..len-1].
+    // Return the CRC of the bytes in buf
     pub fn crc(&self, buf: &[u8]) -> u32 {
-        self.update_crc(0xFFFFFFFF, buf, buf.len()) ^ 0xFFFFFFFF
+        self.update_crc(0xFFFFFFFF, buf) ^ 0xFFFFFFFF
     }
 
     pub fn verify(&self, crc: u32, buf: &[u8]) -> VerifyResult {
@@ -49,3 +96,33 @@ impl CRCHandler {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunk_type() {
+        assert_eq!(CRCHandler::new().crc(b"IEND"), 0xAE426082);
+        assert_eq!(CRCHandler::new().verify(0xAE426082, b"IEND"), Ok(()));
+    }
+
+    // Every length up to two blocks of 8 and one byte more, starting at every alignment
+    #[test]
+    fn slicing_matches_bytewise() {
+        let handler = CRCHandler::new();
+        let bytes: Vec<u8> = (0..32u32).map(|i| (i * 37 + 11) as u8).collect();
+        for start in 0..8 {
+            for len in 0..=17 {
+                let buf = &bytes[start..start + len];
+                assert_eq!(
+                    handler.update_crc(0xFFFFFFFF, buf),
+                    handler.update_crc_bytewise(0xFFFFFFFF, buf),
+                    "start {}, length {}",
+                    start,
+                    len
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,233 @@
+// Gaussian blurs. Both methods are separable, so every row is blurred and then every column,
+// which is the same as blurring with the 2D kernel for a fraction of the work
+use super::convolve::convolve_separable;
+use super::{replace_with_bg, EdgeMode, Effect};
+use crate::common::*;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BlurMethod {
+    // Weights from the Gaussian itself, cut off at 3 sigma, which takes longer as sigma grows
+    #[default]
+    Gaussian,
+    // Three box blurs in a row, which come close to the Gaussian in constant time per pixel
+    Box,
+}
+
+pub struct Blur {
+    // Standard deviation in pixels
+    sigma: f32,
+    method: BlurMethod,
+    edges: EdgeMode,
+}
+
+impl Blur {
+    pub fn new(sigma: f32, method: BlurMethod, edges: EdgeMode) -> Blur {
+        Blur {
+            sigma,
+            method,
+            edges,
+        }
+    }
+}
+
+pub(crate) fn gaussian_kernel(sigma: f32) -> Vec<f32> {
+    let radius = (sigma * 3.0).ceil() as isize;
+    let kernel: Vec<f32> = (-radius..=radius)
+        .map(|x| (-((x * x) as f32) / (2.0 * sigma * sigma)).exp())
+        .collect();
+
+    let sum: f32 = kernel.iter().sum();
+    kernel.iter().map(|weight| weight / sum).collect()
+}
+
+// Widths of n box blurs that together have a variance of sigma squared, from "Fast
+// Almost-Gaussian Filtering" by Peter Kovesi. Each width is odd, so the boxes are centered
+fn box_widths(sigma: f32, n: usize) -> Vec<usize> {
+    let n_f = n as f32;
+    let ideal = (12.0 * sigma * sigma / n_f + 1.0).sqrt();
+    let mut lower = ideal.floor() as usize;
+    if lower.is_multiple_of(2) {
+        lower -= 1;
+    }
+    let upper = lower + 2;
+
+    // How many of the boxes are the lower width
+    let l = lower as f32;
+    let m = ((12.0 * sigma * sigma - n_f * l * l - 4.0 * n_f * l - 3.0 * n_f) / (-4.0 * l - 4.0))
+        .round() as usize;
+
+    (0..n).map(|i| if i < m { lower } else { upper }).collect()
+}
+
+// The line with radius pixels made up past each end
+fn pad(line: &[Sample], radius: usize, edges: EdgeMode) -> Vec<Sample> {
+    let radius = radius as isize;
+    (-radius..line.len() as isize + radius)
+        .map(|i| line[edges.index(i, line.len())])
+        .collect()
+}
+
+// A running sum is moved along the line, adding the pixel that enters the box and removing the
+// one that leaves it
+fn box_line(line: &[Sample], out: &mut [Sample], width: usize, edges: EdgeMode) {
+    let padded = pad(line, width / 2, edges);
+
+    // Summed in f64, as errors would otherwise build up over long lines
+    let mut sum = [0.0f64; 4];
+    for sample in &padded[..width] {
+        for c in 0..4 {
+            sum[c] += sample[c] as f64;
+        }
+    }
+
+    for x in 0..out.len() {
+        if x > 0 {
+            for c in 0..4 {
+                sum[c] += padded[x + width - 1][c] as f64 - padded[x - 1][c] as f64;
+            }
+        }
+        for c in 0..4 {
+            out[x][c] = (sum[c] / width as f64) as f32;
+        }
+    }
+}
+
+// Columns are done a whole row at a time, as going down each column separately jumps around
+// in memory
+fn box_columns(
+    image: &ImageBuffer<Sample>,
+    box_width: usize,
+    edges: EdgeMode,
+) -> ImageBuffer<Sample> {
+    let (width, height) = (image.width(), image.height());
+    let radius = (box_width / 2) as isize;
+    let row = |y: isize| image.row(edges.index(y, height));
+
+    let mut sums = vec![[0.0f64; 4]; width];
+    for y in -radius..=radius {
+        for (sum, sample) in sums.iter_mut().zip(row(y)) {
+            for c in 0..4 {
+                sum[c] += sample[c] as f64;
+            }
+        }
+    }
+
+    let mut out = ImageBuffer::new(width, height, [0.0; 4]);
+    for y in 0..height {
+        for (out, sum) in out.row_mut(y).iter_mut().zip(&sums) {
+            for c in 0..4 {
+                out[c] = (sum[c] / box_width as f64) as f32;
+            }
+        }
+
+        let (entering, leaving) = (row(y as isize + radius + 1), row(y as isize - radius));
+        for ((sum, entering), leaving) in sums.iter_mut().zip(entering).zip(leaving) {
+            for c in 0..4 {
+                sum[c] += entering[c] as f64 - leaving[c] as f64;
+            }
+        }
+    }
+    out
+}
+
+fn rows(
+    image: &ImageBuffer<Sample>,
+    mut f: impl FnMut(&[Sample], &mut [Sample]),
+) -> ImageBuffer<Sample> {
+    let mut out = ImageBuffer::new(image.width(), image.height(), [0.0; 4]);
+    for y in 0..image.height() {
+        f(image.row(y), out.row_mut(y));
+    }
+    out
+}
+
+impl Effect for Blur {
+    fn apply(&self, image: &Image<RGBColor>, bkgd: &RGBColor) -> Image<RGBColor> {
+        if image.is_empty() || self.sigma <= 0.0 {
+            return image.clone();
+        }
+
+        let mut samples = image.map(|col| to_sample(&replace_with_bg(&col, bkgd)));
+
+        // Boxes can't be narrower than a pixel, which is too wide for small blurs
+        if self.method == BlurMethod::Gaussian || self.sigma < 1.0 {
+            let kernel = gaussian_kernel(self.sigma);
+            samples = convolve_separable(&samples, &kernel, &kernel, self.edges);
+        } else {
+            for width in box_widths(self.sigma, 3) {
+                let horizontal = rows(&samples, |line, out| box_line(line, out, width, self.edges));
+                samples = box_columns(&horizontal, width, self.edges);
+            }
+        }
+
+        samples.map(|sample| to_color(&sample))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EDGE_MODES: [EdgeMode; 3] = [EdgeMode::Clamp, EdgeMode::Mirror, EdgeMode::Wrap];
+
+    #[test]
+    fn kernel() {
+        let kernel = gaussian_kernel(1.0);
+        assert_eq!(kernel.len(), 7);
+        assert!((kernel.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+        assert_eq!(kernel[0], kernel[6]);
+        assert!(kernel[3] > kernel[2] && kernel[2] > kernel[1]);
+    }
+
+    #[test]
+    fn box_variance() {
+        // A box of width w has a variance of (w^2 - 1) / 12, and variances add up
+        for &sigma in [1.0, 2.5, 7.0].iter() {
+            let widths = box_widths(sigma, 3);
+            assert!(widths.iter().all(|w| w % 2 == 1));
+            let variance: f32 = widths.iter().map(|&w| (w * w - 1) as f32 / 12.0).sum();
+            assert!(
+                (variance.sqrt() - sigma).abs() < 0.5,
+                "{} {:?}",
+                sigma,
+                widths
+            );
+        }
+    }
+
+    #[test]
+    fn constant_image() {
+        let image = ImageBuffer::new(6, 5, (100, 150, 200));
+        for &method in [BlurMethod::Gaussian, BlurMethod::Box].iter() {
+            for &edges in EDGE_MODES.iter() {
+                let blurred = Blur::new(2.0, method, edges).apply(&image, &(0, 0, 0));
+                assert_eq!(blurred, image, "{:?} {:?}", method, edges);
+            }
+        }
+    }
+
+    #[test]
+    fn spreads_evenly() {
+        let mut image = ImageBuffer::new(7, 7, (10, 10, 10));
+        image[(3, 3)] = (250, 250, 250);
+        for &method in [BlurMethod::Gaussian, BlurMethod::Box].iter() {
+            let blurred = Blur::new(1.0, method, EdgeMode::Clamp).apply(&image, &(0, 0, 0));
+            let center = blurred[(3, 3)].0;
+            assert!(center > 10 && center < 250);
+            assert_eq!(blurred[(2, 3)], blurred[(4, 3)]);
+            assert_eq!(blurred[(3, 2)], blurred[(3, 4)]);
+            assert_eq!(blurred[(2, 3)], blurred[(3, 2)]);
+            assert!(blurred[(2, 3)].0 <= center && blurred[(2, 2)].0 <= blurred[(2, 3)].0);
+        }
+    }
+
+    #[test]
+    fn transparent() {
+        let image = ImageBuffer::new(3, 3, (0, 0, 0));
+        let blurred = Blur::new(1.0, BlurMethod::Gaussian, EdgeMode::Clamp);
+        assert_eq!(blurred.apply(&image, &(0, 0, 0)), image);
+        // Shown over the background instead
+        let over_bkgd = blurred.apply(&image, &(1, 2, 3));
+        assert!(over_bkgd.pixels().all(|&col| col == (1, 2, 3)));
+    }
+}
@@ -0,0 +1,274 @@
+// Effects that change each pixel on its own, without looking at its neighbours. Transparent
+// pixels are shown over the background first, and stay transparent if there isn't one
+use super::{replace_with_bg, Effect};
+use crate::common::*;
+use std::io::{Error, ErrorKind, Result};
+
+// How much each of red, green and blue count towards how bright a color looks
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Luma {
+    // HDTV and sRGB, which most images are in
+    #[default]
+    Rec709,
+    // SDTV and JPEG
+    Rec601,
+}
+
+impl Luma {
+    pub fn parse(name: &str) -> Result<Luma> {
+        match name {
+            "709" | "rec709" => Ok(Luma::Rec709),
+            "601" | "rec601" => Ok(Luma::Rec601),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unknown luma standard: {}, expected 709 or 601", name),
+            )),
+        }
+    }
+
+    pub fn weights(&self) -> [f32; 3] {
+        match self {
+            Luma::Rec709 => [0.2126, 0.7152, 0.0722],
+            Luma::Rec601 => [0.299, 0.587, 0.114],
+        }
+    }
+
+    pub fn of(&self, &(r, g, b): &RGBColor) -> f32 {
+        let [wr, wg, wb] = self.weights();
+        wr * r as f32 + wg * g as f32 + wb * b as f32
+    }
+}
+
+fn channel(value: f32) -> u8 {
+    value.round().clamp(0.0, 255.0) as u8
+}
+
+// Applies f to every pixel that isn't transparent
+fn map_colors(
+    image: &Image<RGBColor>,
+    bkgd: &RGBColor,
+    f: impl Fn(RGBColor) -> RGBColor,
+) -> Image<RGBColor> {
+    image.map(|col| {
+        let col = replace_with_bg(&col, bkgd);
+        if is_transparent(col.0, col.1, col.2) {
+            col
+        } else {
+            let (r, g, b) = f(col);
+            opaque(r, g, b)
+        }
+    })
+}
+
+pub struct Grayscale {
+    luma: Luma,
+}
+
+impl Grayscale {
+    pub fn new(luma: Luma) -> Grayscale {
+        Grayscale { luma }
+    }
+}
+
+impl Effect for Grayscale {
+    fn apply(&self, image: &Image<RGBColor>, bkgd: &RGBColor) -> Image<RGBColor> {
+        map_colors(image, bkgd, |col| {
+            let value = channel(self.luma.of(&col));
+            (value, value, value)
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorAdjustment {
+    // Multiplies every channel, so 1 leaves the image as it is
+    Brightness(f32),
+    // Multiplies the distance of every channel from middle gray
+    Contrast(f32),
+    // Values above 1 brighten the dark parts of the image more than the light ones
+    Gamma(f32),
+    // Multiplies the saturation of every color
+    Saturation(f32),
+    // Rotates every color around the color wheel, in degrees
+    Hue(f32),
+    Invert,
+    // Brownish tones, like an old photograph
+    Sepia,
+}
+
+// For adjustments that change every channel the same way, so they can be worked out once for each
+// of the 256 values
+fn lookup_table(f: impl Fn(f32) -> f32) -> [u8; 256] {
+    let mut table = [0; 256];
+    for (value, entry) in table.iter_mut().enumerate() {
+        *entry = channel(f(value as f32));
+    }
+    table
+}
+
+impl Effect for ColorAdjustment {
+    fn apply(&self, image: &Image<RGBColor>, bkgd: &RGBColor) -> Image<RGBColor> {
+        let table = match *self {
+            ColorAdjustment::Brightness(factor) => Some(lookup_table(|v| v * factor)),
+            ColorAdjustment::Contrast(factor) => {
+                Some(lookup_table(|v| (v - 128.0) * factor + 128.0))
+            }
+            ColorAdjustment::Gamma(gamma) => {
+                Some(lookup_table(|v| 255.0 * (v / 255.0).powf(1.0 / gamma)))
+            }
+            ColorAdjustment::Invert => Some(lookup_table(|v| 255.0 - v)),
+            _ => None,
+        };
+        if let Some(table) = table {
+            return map_colors(image, bkgd, |(r, g, b)| {
+                (table[r as usize], table[g as usize], table[b as usize])
+            });
+        }
+
+        match *self {
+            ColorAdjustment::Saturation(factor) => map_colors(image, bkgd, |col| {
+                let (h, s, l) = to_hsl(col);
+                from_hsl(h, (s * factor).clamp(0.0, 1.0), l)
+            }),
+            ColorAdjustment::Hue(degrees) => map_colors(image, bkgd, |col| {
+                let (h, s, l) = to_hsl(col);
+                from_hsl((h + degrees).rem_euclid(360.0), s, l)
+            }),
+            ColorAdjustment::Sepia => map_colors(image, bkgd, |(r, g, b)| {
+                let (r, g, b) = (r as f32, g as f32, b as f32);
+                (
+                    channel(0.393 * r + 0.769 * g + 0.189 * b),
+                    channel(0.349 * r + 0.686 * g + 0.168 * b),
+                    channel(0.272 * r + 0.534 * g + 0.131 * b),
+                )
+            }),
+            _ => image.clone(),
+        }
+    }
+}
+
+// Hue in degrees, and saturation and lightness from 0 to 1
+fn to_hsl((r, g, b): RGBColor) -> (f32, f32, f32) {
+    let (r, g, b) = (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let l = (max + min) / 2.0;
+
+    let delta = max - min;
+    if delta == 0.0 {
+        return (0.0, 0.0, l);
+    }
+
+    let s = delta / (1.0 - (2.0 * l - 1.0).abs());
+    let h = if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    (h, s, l)
+}
+
+fn from_hsl(h: f32, s: f32, l: f32) -> RGBColor {
+    let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let x = c * (1.0 - ((h / 60.0).rem_euclid(2.0) - 1.0).abs());
+    let m = l - c / 2.0;
+
+    let (r, g, b) = match (h / 60.0) as usize {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    (
+        channel((r + m) * 255.0),
+        channel((g + m) * 255.0),
+        channel((b + m) * 255.0),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn adjust(adjustment: ColorAdjustment, col: RGBColor) -> RGBColor {
+        adjustment.apply(&ImageBuffer::new(1, 1, col), &(0, 0, 0))[(0, 0)]
+    }
+
+    #[test]
+    fn grayscale() {
+        let image = ImageBuffer::from_vec(2, 1, vec![(255, 0, 0), (0, 255, 0)]);
+        let gray = Grayscale::new(Luma::Rec709).apply(&image, &(0, 0, 0));
+        assert_eq!(gray.row(0), [(54, 54, 54), (182, 182, 182)]);
+        let gray = Grayscale::new(Luma::Rec601).apply(&image, &(0, 0, 0));
+        assert_eq!(gray.row(0), [(76, 76, 76), (150, 150, 150)]);
+    }
+
+    #[test]
+    fn adjustments() {
+        let col = (100, 50, 200);
+        assert_eq!(
+            adjust(ColorAdjustment::Brightness(2.0), col),
+            (200, 100, 255)
+        );
+        assert_eq!(adjust(ColorAdjustment::Contrast(0.0), col), (128, 128, 128));
+        assert_eq!(adjust(ColorAdjustment::Contrast(2.0), col), (72, 0, 255));
+        assert_eq!(adjust(ColorAdjustment::Gamma(1.0), col), col);
+        assert_eq!(adjust(ColorAdjustment::Invert, col), (155, 205, 55));
+        assert_eq!(
+            adjust(ColorAdjustment::Sepia, (255, 255, 255)),
+            (255, 255, 239)
+        );
+    }
+
+    #[test]
+    fn saturation_and_hue() {
+        assert_eq!(
+            adjust(ColorAdjustment::Saturation(0.0), (200, 100, 50)),
+            (125, 125, 125)
+        );
+        assert_eq!(
+            adjust(ColorAdjustment::Saturation(1.0), (200, 100, 50)),
+            (200, 100, 50)
+        );
+        assert_eq!(
+            adjust(ColorAdjustment::Hue(120.0), (255, 0, 0)),
+            (0, 255, 0)
+        );
+        assert_eq!(
+            adjust(ColorAdjustment::Hue(-120.0), (255, 0, 0)),
+            (0, 0, 255)
+        );
+    }
+
+    #[test]
+    fn hsl_round_trip() {
+        for &col in [(200, 100, 50), (10, 20, 30), (255, 255, 255), (0, 128, 64)].iter() {
+            let (h, s, l) = to_hsl(col);
+            assert_eq!(from_hsl(h, s, l), col);
+        }
+    }
+
+    #[test]
+    fn black_and_transparent() {
+        // Black is still made opaque, while transparent pixels stay transparent
+        assert_eq!(
+            adjust(ColorAdjustment::Brightness(0.0), (10, 20, 30)),
+            (0, 0, 1)
+        );
+        assert_eq!(adjust(ColorAdjustment::Invert, (0, 0, 0)), (0, 0, 0));
+        let image = ImageBuffer::new(1, 1, (0, 0, 0));
+        let inverted = ColorAdjustment::Invert.apply(&image, &(10, 20, 30));
+        assert_eq!(inverted[(0, 0)], (245, 235, 225));
+    }
+
+    #[test]
+    fn parse_luma() {
+        assert_eq!(Luma::parse("601").unwrap(), Luma::Rec601);
+        assert_eq!(Luma::parse("rec709").unwrap(), Luma::Rec709);
+        assert!(Luma::parse("2020").is_err());
+    }
+}
@@ -0,0 +1,322 @@
+// Convolution of images with kernels, shared by the effects that weigh up each pixel's
+// neighbours. All four channels of the samples are convolved, and the sums are left as they are,
+// so it is up to each effect what to do with opacity and values out of range
+use super::{replace_with_bg, EdgeMode, Effect};
+use crate::common::*;
+use std::io::{Error, ErrorKind, Result};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Kernel {
+    width: usize,
+    height: usize,
+    // Row by row
+    weights: Vec<f32>,
+}
+
+impl Kernel {
+    // Both sides must be odd, so that the kernel has a center pixel
+    pub fn new(width: usize, height: usize, weights: Vec<f32>) -> Result<Kernel> {
+        if width.is_multiple_of(2) || height.is_multiple_of(2) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Kernel is {}x{}, but both sides must be odd", width, height),
+            ));
+        }
+        if weights.len() != width * height {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Kernel is {}x{}, but has {} weights",
+                    width,
+                    height,
+                    weights.len()
+                ),
+            ));
+        }
+        Ok(Kernel {
+            width,
+            height,
+            weights,
+        })
+    }
+
+    // For the kernels in the code, which are known to be valid
+    pub(crate) fn square(weights: &[f32]) -> Kernel {
+        let size = (weights.len() as f32).sqrt() as usize;
+        Kernel::new(size, size, weights.to_vec()).expect("kernel must be square with odd sides")
+    }
+
+    // Rows are separated by semicolons or new lines, and the weights in them by commas or spaces.
+    // Anything after a # is a comment
+    pub fn parse(text: &str) -> Result<Kernel> {
+        let invalid = |reason: String| Error::new(ErrorKind::InvalidInput, reason);
+
+        let mut rows: Vec<Vec<f32>> = Vec::new();
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("");
+            for row in line.split(';') {
+                let weights = row
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|weight| !weight.is_empty())
+                    .map(|weight| {
+                        weight
+                            .parse::<f32>()
+                            .ok()
+                            .filter(|weight| weight.is_finite())
+                            .ok_or_else(|| invalid(format!("Invalid kernel weight: {}", weight)))
+                    })
+                    .collect::<Result<Vec<f32>>>()?;
+                if !weights.is_empty() {
+                    rows.push(weights);
+                }
+            }
+        }
+
+        let width = rows.first().map_or(0, |row| row.len());
+        if width == 0 {
+            return Err(invalid("Kernel has no weights".to_string()));
+        }
+        if let Some(row) = rows.iter().position(|row| row.len() != width) {
+            return Err(invalid(format!(
+                "Kernel row {} has {} weights, but the first has {}",
+                row + 1,
+                rows[row].len(),
+                width
+            )));
+        }
+
+        let height = rows.len();
+        Kernel::new(width, height, rows.concat())
+    }
+
+    pub fn sum(&self) -> f32 {
+        self.weights.iter().sum()
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn weights(&self) -> &[f32] {
+        &self.weights
+    }
+
+    // Swaps rows with columns, which turns a horizontal gradient into a vertical one
+    pub fn transpose(&self) -> Kernel {
+        let mut weights = Vec::with_capacity(self.weights.len());
+        for x in 0..self.width {
+            for y in 0..self.height {
+                weights.push(self.weights[y * self.width + x]);
+            }
+        }
+        Kernel {
+            width: self.height,
+            height: self.width,
+            weights,
+        }
+    }
+}
+
+// The image with extra pixels made up past its left and right edges, so that rows can be read
+// without checking the edges for every pixel
+fn pad_rows(image: &ImageBuffer<Sample>, radius: usize, edges: EdgeMode) -> ImageBuffer<Sample> {
+    let width = image.width();
+    let radius = radius as isize;
+    ImageBuffer::from_fn(width + 2 * radius as usize, image.height(), |x, y| {
+        image[(edges.index(x as isize - radius, width), y)]
+    })
+}
+
+// Weighs up the pixels under the kernel, centered on each pixel in turn
+pub fn convolve(
+    image: &ImageBuffer<Sample>,
+    kernel: &Kernel,
+    edges: EdgeMode,
+) -> ImageBuffer<Sample> {
+    let (width, height) = (image.width(), image.height());
+    if image.is_empty() {
+        return image.clone();
+    }
+
+    let radius_y = (kernel.height / 2) as isize;
+    let padded = pad_rows(image, kernel.width / 2, edges);
+
+    let mut out = ImageBuffer::new(width, height, [0.0; 4]);
+    for y in 0..height {
+        let out_row = out.row_mut(y);
+        for (ky, weights) in kernel.weights.chunks(kernel.width).enumerate() {
+            let row = padded.row(edges.index(y as isize + ky as isize - radius_y, height));
+            for (kx, &weight) in weights.iter().enumerate() {
+                if weight == 0.0 {
+                    continue;
+                }
+                for (out, sample) in out_row.iter_mut().zip(&row[kx..]) {
+                    for c in 0..4 {
+                        out[c] += sample[c] * weight;
+                    }
+                }
+            }
+        }
+    }
+    out
+}
+
+// Same as convolving with the kernel whose weights are the products of horizontal and vertical,
+// but only takes as long as both of them added together
+pub fn convolve_separable(
+    image: &ImageBuffer<Sample>,
+    horizontal: &[f32],
+    vertical: &[f32],
+    edges: EdgeMode,
+) -> ImageBuffer<Sample> {
+    let rows = Kernel::new(horizontal.len(), 1, horizontal.to_vec())
+        .expect("horizontal kernel must have an odd length");
+    let columns = Kernel::new(1, vertical.len(), vertical.to_vec())
+        .expect("vertical kernel must have an odd length");
+    convolve(&convolve(image, &rows, edges), &columns, edges)
+}
+
+// A kernel given by the user. Each color channel is divided by the divisor, has the bias added
+// and is then clamped, while opacity is kept as it was
+pub struct Convolution {
+    kernel: Kernel,
+    divisor: f32,
+    bias: f32,
+    edges: EdgeMode,
+}
+
+impl Convolution {
+    // Without a divisor, the weights are divided by their sum so that the brightness stays the
+    // same, unless they add up to 0 like edge detection kernels do
+    pub fn new(kernel: Kernel, divisor: Option<f32>, bias: f32, edges: EdgeMode) -> Convolution {
+        let divisor = divisor.unwrap_or_else(|| match kernel.sum() {
+            sum if sum.abs() > f32::EPSILON => sum,
+            _ => 1.0,
+        });
+        Convolution {
+            kernel,
+            divisor,
+            bias,
+            edges,
+        }
+    }
+}
+
+impl Effect for Convolution {
+    fn apply(&self, image: &Image<RGBColor>, bkgd: &RGBColor) -> Image<RGBColor> {
+        let samples = image.map(|col| to_sample(&replace_with_bg(&col, bkgd)));
+        let sums = convolve(&samples, &self.kernel, self.edges);
+
+        ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+            let mut sample = sums[(x, y)];
+            for value in &mut sample[..3] {
+                *value = *value / self.divisor + self.bias;
+            }
+            sample[3] = samples[(x, y)][3];
+            to_color(&sample)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EDGE_MODES: [EdgeMode; 3] = [EdgeMode::Clamp, EdgeMode::Mirror, EdgeMode::Wrap];
+
+    fn ramp(width: usize, height: usize) -> ImageBuffer<Sample> {
+        ImageBuffer::from_fn(width, height, |x, y| {
+            let value = (x * 10 + y * 3) as f32;
+            [value, 255.0 - value, value / 2.0, 1.0]
+        })
+    }
+
+    #[test]
+    fn parse() {
+        let kernel = Kernel::parse("1 2 1 # top\n0,0,0; -1 -2 -1").unwrap();
+        assert_eq!((kernel.width(), kernel.height()), (3, 3));
+        assert_eq!(kernel.weights()[..3], [1.0, 2.0, 1.0]);
+        assert_eq!(kernel.sum(), 0.0);
+
+        let kernel = Kernel::parse("1 2 1").unwrap();
+        assert_eq!((kernel.width(), kernel.height()), (3, 1));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        // Even sides, ragged rows, bad weights and no weights at all
+        for text in ["1 1; 1 1", "1 2 1; 1 2", "1 x 1", "1 inf 1", "# nothing"].iter() {
+            let e = Kernel::parse(text).err().unwrap();
+            assert_eq!(e.kind(), ErrorKind::InvalidInput, "{}", text);
+        }
+    }
+
+    #[test]
+    fn transpose() {
+        let kernel = Kernel::new(3, 1, vec![1.0, 2.0, 3.0]).unwrap().transpose();
+        assert_eq!((kernel.width(), kernel.height()), (1, 3));
+        assert_eq!(kernel.weights(), [1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn constant_image() {
+        let image = ImageBuffer::new(5, 4, [10.0, 20.0, 30.0, 1.0]);
+        let kernel = Kernel::square(&[1.0, 2.0, 1.0, 2.0, 4.0, 2.0, 1.0, 2.0, 1.0]);
+        for &edges in EDGE_MODES.iter() {
+            let out = convolve(&image, &kernel, edges);
+            assert!(out.pixels().all(|s| *s == [160.0, 320.0, 480.0, 16.0]));
+        }
+    }
+
+    #[test]
+    fn identity() {
+        let image = ramp(4, 3);
+        let kernel = Kernel::square(&[0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0]);
+        assert_eq!(convolve(&image, &kernel, EdgeMode::Clamp), image);
+    }
+
+    #[test]
+    fn edges() {
+        // Each pixel takes the value of the one on its left
+        let image = ImageBuffer::from_fn(3, 1, |x, _| [x as f32; 4]);
+        let kernel = Kernel::new(3, 1, vec![1.0, 0.0, 0.0]).unwrap();
+        let first = |edges| convolve(&image, &kernel, edges)[(0, 0)][0];
+        assert_eq!(first(EdgeMode::Clamp), 0.0);
+        assert_eq!(first(EdgeMode::Mirror), 1.0);
+        assert_eq!(first(EdgeMode::Wrap), 2.0);
+    }
+
+    #[test]
+    fn separable() {
+        let image = ramp(6, 5);
+        let weights = [1.0, 2.0, 1.0];
+        let kernel = Kernel::square(&[1.0, 2.0, 1.0, 2.0, 4.0, 2.0, 1.0, 2.0, 1.0]);
+        for &edges in EDGE_MODES.iter() {
+            let full = convolve(&image, &kernel, edges);
+            let separable = convolve_separable(&image, &weights, &weights, edges);
+            for (a, b) in full.pixels().zip(separable.pixels()) {
+                for c in 0..4 {
+                    assert!((a[c] - b[c]).abs() < 1e-3);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn divisor_and_bias() {
+        let image = ImageBuffer::new(2, 2, (100, 50, 20));
+        let kernel = Kernel::square(&[1.0]);
+        let out = Convolution::new(kernel.clone(), Some(2.0), 10.0, EdgeMode::Clamp)
+            .apply(&image, &(0, 0, 0));
+        assert!(out.pixels().all(|&col| col == (60, 35, 20)));
+
+        // Without a divisor, the kernel is divided by its sum
+        let kernel = Kernel::square(&[2.0]);
+        let out = Convolution::new(kernel, None, 0.0, EdgeMode::Clamp).apply(&image, &(0, 0, 0));
+        assert_eq!(out, image);
+    }
+}
@@ -0,0 +1,251 @@
+// Edge detection, which leaves the edges of the image bright on black. Edges are found in the
+// brightness of the image, so the result is gray
+use super::blur::gaussian_kernel;
+use super::convolve::{convolve, convolve_separable, Kernel};
+use super::{replace_with_bg, EdgeMode, Effect};
+use crate::common::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EdgeOperator {
+    // Gradient that weighs the closest neighbours twice as much as the diagonal ones
+    Sobel,
+    // Gradient that weighs all the neighbours the same
+    Prewitt,
+    // Second derivative, which finds edges in every direction at once
+    Laplacian,
+}
+
+#[rustfmt::skip]
+const SOBEL: [f32; 9] = [
+    -1.0, 0.0, 1.0,
+    -2.0, 0.0, 2.0,
+    -1.0, 0.0, 1.0,
+];
+
+#[rustfmt::skip]
+const PREWITT: [f32; 9] = [
+    -1.0, 0.0, 1.0,
+    -1.0, 0.0, 1.0,
+    -1.0, 0.0, 1.0,
+];
+
+#[rustfmt::skip]
+const LAPLACIAN: [f32; 9] = [
+    0.0,  1.0, 0.0,
+    1.0, -4.0, 1.0,
+    0.0,  1.0, 0.0,
+];
+
+// Gradients weaker than this are rounding errors in flat parts of the image, rather than edges
+const MIN_GRADIENT: f32 = 1e-3;
+
+// Brightness in every color channel, keeping the opacity
+fn brightness(image: &Image<RGBColor>, bkgd: &RGBColor) -> ImageBuffer<Sample> {
+    image.map(|col| {
+        let [r, g, b, a] = to_sample(&replace_with_bg(&col, bkgd));
+        let value = (r + g + b) / 3.0;
+        [value, value, value, a]
+    })
+}
+
+// Length of the gradient found by the kernel and its transpose, with its direction in radians
+fn gradient(
+    samples: &ImageBuffer<Sample>,
+    kernel: &Kernel,
+    edges: EdgeMode,
+) -> ImageBuffer<(f32, f32)> {
+    let gx = convolve(samples, kernel, edges);
+    let gy = convolve(samples, &kernel.transpose(), edges);
+    ImageBuffer::from_fn(samples.width(), samples.height(), |x, y| {
+        let (dx, dy) = (gx[(x, y)][0], gy[(x, y)][0]);
+        ((dx * dx + dy * dy).sqrt(), dy.atan2(dx))
+    })
+}
+
+fn to_gray(value: f32, opacity: f32) -> RGBColor {
+    to_color(&[value, value, value, opacity])
+}
+
+pub struct EdgeDetect {
+    operator: EdgeOperator,
+    edges: EdgeMode,
+}
+
+impl EdgeDetect {
+    pub fn new(operator: EdgeOperator, edges: EdgeMode) -> EdgeDetect {
+        EdgeDetect { operator, edges }
+    }
+}
+
+impl Effect for EdgeDetect {
+    fn apply(&self, image: &Image<RGBColor>, bkgd: &RGBColor) -> Image<RGBColor> {
+        let samples = brightness(image, bkgd);
+
+        let strength = match self.operator {
+            EdgeOperator::Sobel => gradient(&samples, &Kernel::square(&SOBEL), self.edges)
+                .map(|(magnitude, _)| magnitude),
+            EdgeOperator::Prewitt => gradient(&samples, &Kernel::square(&PREWITT), self.edges)
+                .map(|(magnitude, _)| magnitude),
+            EdgeOperator::Laplacian => {
+                convolve(&samples, &Kernel::square(&LAPLACIAN), self.edges).map(|s| s[0].abs())
+            }
+        };
+
+        ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+            to_gray(strength[(x, y)], samples[(x, y)][3])
+        })
+    }
+}
+
+// Thin, connected edges. The image is blurred to ignore noise, the Sobel gradient is kept only
+// where it is the strongest across the edge, and then only edges that are strong, or that are
+// weak but joined to strong ones, are kept
+pub struct Canny {
+    sigma: f32,
+    // Fractions of the strongest gradient in the image
+    low: f32,
+    high: f32,
+    edges: EdgeMode,
+}
+
+impl Canny {
+    // A blur with a sigma of 1.4, and thresholds at 10% and 20% of the strongest gradient
+    pub fn with_edges(edges: EdgeMode) -> Canny {
+        Canny::new(1.4, 0.1, 0.2, edges)
+    }
+
+    pub fn new(sigma: f32, low: f32, high: f32, edges: EdgeMode) -> Canny {
+        Canny {
+            sigma,
+            low,
+            high,
+            edges,
+        }
+    }
+}
+
+impl Effect for Canny {
+    fn apply(&self, image: &Image<RGBColor>, bkgd: &RGBColor) -> Image<RGBColor> {
+        let (width, height) = (image.width(), image.height());
+        let samples = brightness(image, bkgd);
+
+        let blurred = if self.sigma > 0.0 {
+            let kernel = gaussian_kernel(self.sigma);
+            convolve_separable(&samples, &kernel, &kernel, self.edges)
+        } else {
+            samples.clone()
+        };
+        let gradient = gradient(&blurred, &Kernel::square(&SOBEL), self.edges);
+
+        // Compared with the neighbours on either side, in the direction of the gradient
+        let magnitude = |x: isize, y: isize| {
+            if x < 0 || y < 0 {
+                return 0.0;
+            }
+            gradient.get(x as usize, y as usize).map_or(0.0, |g| g.0)
+        };
+        let thin = ImageBuffer::from_fn(width, height, |x, y| {
+            let (strength, angle) = gradient[(x, y)];
+            let octant = ((angle.to_degrees() + 180.0 + 22.5) / 45.0) as usize % 4;
+            let (dx, dy) = [(1, 0), (1, 1), (0, 1), (-1, 1)][octant];
+            let (x, y) = (x as isize, y as isize);
+            if strength >= magnitude(x + dx, y + dy) && strength >= magnitude(x - dx, y - dy) {
+                strength
+            } else {
+                0.0
+            }
+        });
+
+        let strongest = thin.pixels().fold(0.0f32, |max, &value| max.max(value));
+        let (low, high) = (self.low * strongest, self.high * strongest);
+
+        // Edges are followed out from the strong pixels through any that are above low
+        let mut edge = ImageBuffer::new(width, height, false);
+        let mut stack: Vec<(usize, usize)> = thin
+            .enumerate_pixels()
+            .filter(|&(_, _, &value)| value > MIN_GRADIENT && value >= high)
+            .map(|(x, y, _)| (x, y))
+            .collect();
+        for &(x, y) in &stack {
+            edge[(x, y)] = true;
+        }
+        while let Some((x, y)) = stack.pop() {
+            for ny in y.saturating_sub(1)..(y + 2).min(height) {
+                for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                    if !edge[(nx, ny)] && thin[(nx, ny)] > MIN_GRADIENT && thin[(nx, ny)] >= low {
+                        edge[(nx, ny)] = true;
+                        stack.push((nx, ny));
+                    }
+                }
+            }
+        }
+
+        ImageBuffer::from_fn(width, height, |x, y| {
+            let value = if edge[(x, y)] { 255.0 } else { 0.0 };
+            to_gray(value, samples[(x, y)][3])
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Brightness 10 on the left half and 30 on the right
+    fn step() -> Image<RGBColor> {
+        ImageBuffer::from_fn(8, 5, |x, _| if x < 4 { (10, 10, 10) } else { (30, 30, 30) })
+    }
+
+    fn brightness_row(image: &Image<RGBColor>, y: usize) -> Vec<u8> {
+        image.row(y).iter().map(|col| col.0).collect()
+    }
+
+    #[test]
+    fn step_edge() {
+        let cases = [
+            (EdgeOperator::Sobel, 80),
+            (EdgeOperator::Prewitt, 60),
+            (EdgeOperator::Laplacian, 20),
+        ];
+        for &(operator, strength) in cases.iter() {
+            let edges = EdgeDetect::new(operator, EdgeMode::Clamp).apply(&step(), &(0, 0, 0));
+            for y in 0..5 {
+                let expected = [0, 0, 0, strength, strength, 0, 0, 0];
+                assert_eq!(brightness_row(&edges, y), expected, "{:?}", operator);
+            }
+            assert_eq!(edges[(0, 0)], (0, 0, 1));
+            assert_eq!(edges[(3, 0)], (strength, strength, strength));
+        }
+    }
+
+    #[test]
+    fn constant_image() {
+        let image = ImageBuffer::new(5, 5, (100, 100, 100));
+        for &operator in [EdgeOperator::Sobel, EdgeOperator::Laplacian].iter() {
+            let edges = EdgeDetect::new(operator, EdgeMode::Clamp).apply(&image, &(0, 0, 0));
+            assert!(edges.pixels().all(|&col| col == (0, 0, 1)));
+        }
+        let edges = Canny::with_edges(EdgeMode::Clamp).apply(&image, &(0, 0, 0));
+        assert!(edges.pixels().all(|&col| col == (0, 0, 1)));
+    }
+
+    #[test]
+    fn canny() {
+        let edges = Canny::with_edges(EdgeMode::Clamp).apply(&step(), &(0, 0, 0));
+        // The edge is a single pixel wide, on either side of the step
+        for y in 0..5 {
+            let row = brightness_row(&edges, y);
+            let edge: Vec<usize> = (0..8).filter(|&x| row[x] == 255).collect();
+            assert!(edge == [3] || edge == [4], "{:?}", row);
+            assert!(row.iter().all(|&value| value == 0 || value == 255));
+        }
+    }
+
+    #[test]
+    fn transparent() {
+        let mut image = step();
+        image[(0, 0)] = (0, 0, 0);
+        let edges = EdgeDetect::new(EdgeOperator::Sobel, EdgeMode::Clamp).apply(&image, &(0, 0, 0));
+        assert_eq!(edges[(0, 0)], (0, 0, 0));
+    }
+}
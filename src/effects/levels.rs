@@ -0,0 +1,356 @@
+// Effects that spread out the brightness of low-contrast images. They all work on luma, and each
+// color is scaled to its new luma, so hues and saturation stay as they were. They look at the
+// whole image, so are best applied to it at its full size
+use super::{replace_with_bg, Effect, Luma};
+use crate::common::*;
+
+fn histogram<'a>(lumas: impl Iterator<Item = &'a Option<f32>>) -> [u32; 256] {
+    let mut histogram = [0; 256];
+    for luma in lumas.flatten() {
+        histogram[*luma as usize] += 1;
+    }
+    histogram
+}
+
+// Luma of every pixel from 0 to 255, or None for transparent ones
+fn lumas(image: &Image<RGBColor>, bkgd: &RGBColor, luma: Luma) -> ImageBuffer<Option<f32>> {
+    image.map(|col| {
+        let col = replace_with_bg(&col, bkgd);
+        if is_transparent(col.0, col.1, col.2) {
+            None
+        } else {
+            Some(luma.of(&col).round().clamp(0.0, 255.0))
+        }
+    })
+}
+
+// Scales the color to the new luma. Colors can't be made brighter than their brightest channel
+// allows without changing hue, so those end up a little darker instead
+fn with_luma(col: RGBColor, old: f32, new: f32) -> RGBColor {
+    if old < 0.5 {
+        let value = new.round().clamp(0.0, 255.0) as u8;
+        return opaque(value, value, value);
+    }
+
+    let brightest = col.0.max(col.1).max(col.2) as f32;
+    let scale = (new / old).min(255.0 / brightest);
+    let channel = |value: u8| (value as f32 * scale).round().clamp(0.0, 255.0) as u8;
+    opaque(channel(col.0), channel(col.1), channel(col.2))
+}
+
+// Maps the luma of every pixel that isn't transparent, given its position and old luma
+fn map_lumas(
+    image: &Image<RGBColor>,
+    bkgd: &RGBColor,
+    luma: Luma,
+    f: impl Fn(usize, usize, f32) -> f32,
+) -> Image<RGBColor> {
+    ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+        let col = replace_with_bg(&image[(x, y)], bkgd);
+        if is_transparent(col.0, col.1, col.2) {
+            return col;
+        }
+        let old = luma.of(&col);
+        let new = f(x, y, old.round().clamp(0.0, 255.0));
+        with_luma(col, old, new)
+    })
+}
+
+// Stretches the luma so that the darkest pixels become black and the brightest white
+pub struct AutoLevels {
+    // Percentage of pixels at each end that are ignored, so a few outliers don't stop the rest
+    // from being stretched
+    clip: f32,
+    luma: Luma,
+}
+
+impl AutoLevels {
+    pub fn new(clip: f32, luma: Luma) -> AutoLevels {
+        AutoLevels { clip, luma }
+    }
+}
+
+// The first value with more than ignored pixels at it and the values before it
+fn past(
+    histogram: &[u32; 256],
+    ignored: u32,
+    values: impl Iterator<Item = usize>,
+) -> Option<usize> {
+    let mut count = 0;
+    for value in values {
+        count += histogram[value];
+        if count > ignored {
+            return Some(value);
+        }
+    }
+    None
+}
+
+impl Effect for AutoLevels {
+    fn apply(&self, image: &Image<RGBColor>, bkgd: &RGBColor) -> Image<RGBColor> {
+        let histogram = histogram(lumas(image, bkgd, self.luma).pixels());
+        let total: u32 = histogram.iter().sum();
+        let ignored = (total as f32 * self.clip / 100.0) as u32;
+
+        let (low, high) = match (
+            past(&histogram, ignored, 0..256),
+            past(&histogram, ignored, (0..256).rev()),
+        ) {
+            (Some(low), Some(high)) if low < high => (low as f32, high as f32),
+            _ => return image.clone(),
+        };
+
+        map_lumas(image, bkgd, self.luma, |_, _, value| {
+            (value - low) / (high - low) * 255.0
+        })
+    }
+}
+
+// The luma each value is moved to so that they are all used about as much as each other, from
+// the cumulative histogram. None if there is only one value, which can't be spread out
+fn equalized(histogram: &[f32; 256]) -> Option<[f32; 256]> {
+    let total: f32 = histogram.iter().sum();
+    let first = histogram.iter().copied().find(|&count| count > 0.0)?;
+    if total - first <= 0.0 {
+        return None;
+    }
+
+    let mut table = [0.0; 256];
+    let mut sum = 0.0;
+    for (entry, count) in table.iter_mut().zip(histogram) {
+        sum += count;
+        *entry = ((sum - first) / (total - first) * 255.0).max(0.0);
+    }
+    Some(table)
+}
+
+// Histogram equalization over the whole image
+pub struct Equalize {
+    luma: Luma,
+}
+
+impl Equalize {
+    pub fn new(luma: Luma) -> Equalize {
+        Equalize { luma }
+    }
+}
+
+impl Effect for Equalize {
+    fn apply(&self, image: &Image<RGBColor>, bkgd: &RGBColor) -> Image<RGBColor> {
+        let histogram = histogram(lumas(image, bkgd, self.luma).pixels()).map(|count| count as f32);
+        match equalized(&histogram) {
+            Some(table) => map_lumas(image, bkgd, self.luma, |_, _, value| table[value as usize]),
+            None => image.clone(),
+        }
+    }
+}
+
+// Contrast limited adaptive histogram equalization. The image is split into tiles that are each
+// equalized on their own, which brings out detail in both its dark and light parts. Each bin of
+// a tile's histogram is limited, so that large flat areas don't have their noise stretched, and
+// pixels are mapped with the tiles around them so that there are no seams between tiles
+pub struct Clahe {
+    // Tiles across and down the image
+    tiles: usize,
+    // How many times the average count a bin can have
+    clip_limit: f32,
+    luma: Luma,
+}
+
+impl Clahe {
+    // 8 by 8 tiles, with bins limited to twice the average
+    pub fn with_luma(luma: Luma) -> Clahe {
+        Clahe::new(8, 2.0, luma)
+    }
+
+    pub fn new(tiles: usize, clip_limit: f32, luma: Luma) -> Clahe {
+        Clahe {
+            tiles,
+            clip_limit,
+            luma,
+        }
+    }
+
+    pub fn tiles(&self) -> usize {
+        self.tiles
+    }
+
+    pub fn clip_limit(&self) -> f32 {
+        self.clip_limit
+    }
+}
+
+// Counts above the limit are cut off and shared out between all the bins
+fn clip_histogram(histogram: &mut [f32; 256], limit: f32) {
+    let mut excess = 0.0;
+    for count in histogram.iter_mut() {
+        if *count > limit {
+            excess += *count - limit;
+            *count = limit;
+        }
+    }
+    for count in histogram.iter_mut() {
+        *count += excess / 256.0;
+    }
+}
+
+impl Effect for Clahe {
+    fn apply(&self, image: &Image<RGBColor>, bkgd: &RGBColor) -> Image<RGBColor> {
+        let (width, height) = (image.width(), image.height());
+        if image.is_empty() {
+            return image.clone();
+        }
+
+        let lumas = lumas(image, bkgd, self.luma);
+        let (tiles_x, tiles_y) = (self.tiles.clamp(1, width), self.tiles.clamp(1, height));
+        let (tile_width, tile_height) = (
+            width as f32 / tiles_x as f32,
+            height as f32 / tiles_y as f32,
+        );
+
+        // Tiles without any change to them are left as they are
+        let identity: [f32; 256] = std::array::from_fn(|value| value as f32);
+        let mut tables = Vec::with_capacity(tiles_x * tiles_y);
+        for ty in 0..tiles_y {
+            for tx in 0..tiles_x {
+                let tile = lumas.view(
+                    tx * width / tiles_x,
+                    ty * height / tiles_y,
+                    (tx + 1) * width / tiles_x - tx * width / tiles_x,
+                    (ty + 1) * height / tiles_y - ty * height / tiles_y,
+                );
+                let mut histogram = histogram(tile.pixels()).map(|count| count as f32);
+                let total: f32 = histogram.iter().sum();
+                clip_histogram(&mut histogram, (self.clip_limit * total / 256.0).max(1.0));
+                tables.push(equalized(&histogram).unwrap_or(identity));
+            }
+        }
+
+        // Where a pixel is between the centers of the tiles before and after it, in one direction
+        let between = |position: usize, size: f32, tiles: usize| {
+            let tile = ((position as f32 + 0.5) / size - 0.5).max(0.0);
+            let before = (tile as usize).min(tiles - 1);
+            let after = (before + 1).min(tiles - 1);
+            (before, after, (tile - before as f32).min(1.0))
+        };
+
+        map_lumas(image, bkgd, self.luma, |x, y, value| {
+            let (left, right, wx) = between(x, tile_width, tiles_x);
+            let (top, bottom, wy) = between(y, tile_height, tiles_y);
+            let table = |tx: usize, ty: usize| tables[ty * tiles_x + tx][value as usize];
+
+            let upper = table(left, top) * (1.0 - wx) + table(right, top) * wx;
+            let lower = table(left, bottom) * (1.0 - wx) + table(right, bottom) * wx;
+            upper * (1.0 - wy) + lower * wy
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grays(values: &[u8]) -> Image<RGBColor> {
+        ImageBuffer::from_vec(values.len(), 1, values.iter().map(|&v| (v, v, v)).collect())
+    }
+
+    #[test]
+    fn auto_levels() {
+        let image = grays(&[50, 100, 150]);
+        let stretched = AutoLevels::new(0.0, Luma::Rec709).apply(&image, &(0, 0, 0));
+        assert_eq!(
+            stretched,
+            grays(&[0, 128, 255]).map(|(r, g, b)| opaque(r, g, b))
+        );
+
+        // The outliers at either end are ignored
+        let image = grays(&[0, 50, 50, 100, 100, 255]);
+        let stretched = AutoLevels::new(20.0, Luma::Rec709).apply(&image, &(0, 0, 0));
+        assert_eq!(
+            stretched.row(0)[1..5],
+            [(0, 0, 1), (0, 0, 1), (255, 255, 255), (255, 255, 255)]
+        );
+    }
+
+    #[test]
+    fn equalize() {
+        let image = grays(&[10, 10, 20, 30]);
+        let equalized = Equalize::new(Luma::Rec709).apply(&image, &(0, 0, 0));
+        assert_eq!(
+            equalized.row(0),
+            [(0, 0, 1), (0, 0, 1), (128, 128, 128), (255, 255, 255)]
+        );
+    }
+
+    #[test]
+    fn keeps_hue() {
+        let image = ImageBuffer::from_vec(2, 1, vec![(20, 10, 10), (100, 50, 50)]);
+        let stretched = AutoLevels::new(0.0, Luma::Rec709).apply(&image, &(0, 0, 0));
+        let (r, g, b) = stretched[(1, 0)];
+        assert_eq!((r, g, b), (255, 128, 128));
+        assert_eq!(g, b);
+    }
+
+    #[test]
+    fn constant_image() {
+        let image = ImageBuffer::new(16, 16, (90, 100, 110));
+        assert_eq!(
+            AutoLevels::new(0.0, Luma::Rec709).apply(&image, &(0, 0, 0)),
+            image
+        );
+        assert_eq!(Equalize::new(Luma::Rec709).apply(&image, &(0, 0, 0)), image);
+    }
+
+    // A dark, low-contrast gradient
+    fn gradient() -> Image<RGBColor> {
+        ImageBuffer::from_fn(16, 16, |x, y| {
+            let v = (x + y) as u8 + 20;
+            (v, v, v)
+        })
+    }
+
+    fn luma_range(image: &Image<RGBColor>) -> u8 {
+        let lumas: Vec<u8> = image.pixels().map(|col| col.1).collect();
+        lumas.iter().max().unwrap() - lumas.iter().min().unwrap()
+    }
+
+    #[test]
+    fn clahe() {
+        // A single tile that is never clipped is the same as equalizing the whole image
+        let equalized = Equalize::new(Luma::Rec709).apply(&gradient(), &(0, 0, 0));
+        let clahe = Clahe::new(1, 1000.0, Luma::Rec709).apply(&gradient(), &(0, 0, 0));
+        assert_eq!(clahe, equalized);
+        assert_eq!(luma_range(&clahe), 255);
+
+        // Lower clip limits stretch the contrast less
+        let ranges: Vec<u8> = [4.0, 16.0, 1000.0]
+            .iter()
+            .map(|&limit| {
+                luma_range(&Clahe::new(2, limit, Luma::Rec709).apply(&gradient(), &(0, 0, 0)))
+            })
+            .collect();
+        assert!(luma_range(&gradient()) < ranges[0]);
+        assert!(
+            ranges[0] < ranges[1] && ranges[1] < ranges[2],
+            "{:?}",
+            ranges
+        );
+    }
+
+    #[test]
+    fn clipped_histogram_keeps_total() {
+        let mut histogram = [0.0; 256];
+        histogram[10] = 100.0;
+        histogram[20] = 4.0;
+        clip_histogram(&mut histogram, 8.0);
+        assert!((histogram.iter().sum::<f32>() - 104.0).abs() < 1e-3);
+        assert!(histogram[10] < 9.0);
+    }
+
+    #[test]
+    fn transparent() {
+        let image = ImageBuffer::from_vec(3, 1, vec![(0, 0, 0), (50, 50, 50), (150, 150, 150)]);
+        let stretched = AutoLevels::new(0.0, Luma::Rec709).apply(&image, &(0, 0, 0));
+        assert_eq!(stretched.row(0), [(0, 0, 0), (0, 0, 1), (255, 255, 255)]);
+    }
+}
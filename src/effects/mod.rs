@@ -0,0 +1,127 @@
+// Effects change the pixels of an image, and are applied one after the other before it is
+// rendered or saved. How the image is drawn in the terminal is up to the renderer instead
+use crate::common::*;
+use std::io::{Error, ErrorKind, Result};
+
+mod blur;
+mod color;
+pub mod convolve;
+mod edge_detect;
+mod levels;
+mod sharpen;
+
+pub use blur::{Blur, BlurMethod};
+pub use color::{ColorAdjustment, Grayscale, Luma};
+pub use convolve::{Convolution, Kernel};
+pub use edge_detect::{Canny, EdgeDetect, EdgeOperator};
+pub use levels::{AutoLevels, Clahe, Equalize};
+pub use sharpen::{Sharpen, UnsharpMask};
+
+pub trait Effect {
+    // bkgd is the background transparent pixels are shown over, which is also transparent if
+    // the image doesn't specify one
+    fn apply(&self, image: &Image<RGBColor>, bkgd: &RGBColor) -> Image<RGBColor>;
+}
+
+// Effects in the order they are applied in
+#[derive(Default)]
+pub struct Pipeline {
+    effects: Vec<Box<dyn Effect>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Pipeline {
+        Pipeline::default()
+    }
+
+    pub fn push(&mut self, effect: impl Effect + 'static) {
+        self.effects.push(Box::new(effect));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.effects.is_empty()
+    }
+
+    pub fn apply(&self, image: Image<RGBColor>, bkgd: &RGBColor) -> Image<RGBColor> {
+        self.effects
+            .iter()
+            .fold(image, |image, effect| effect.apply(&image, bkgd))
+    }
+}
+
+// How pixels past the edges of the image are made up, for effects that look at neighbours
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EdgeMode {
+    // The closest edge pixel is repeated
+    #[default]
+    Clamp,
+    // The image is reflected about its edge pixels
+    Mirror,
+    // The image repeats, continuing from the opposite edge
+    Wrap,
+}
+
+impl EdgeMode {
+    pub fn parse(name: &str) -> Result<EdgeMode> {
+        match name {
+            "clamp" => Ok(EdgeMode::Clamp),
+            "mirror" => Ok(EdgeMode::Mirror),
+            "wrap" => Ok(EdgeMode::Wrap),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Unknown edge mode: {}, expected one of clamp, mirror or wrap",
+                    name
+                ),
+            )),
+        }
+    }
+
+    // The pixel to use for position i of a row or column that is len pixels long
+    pub fn index(&self, i: isize, len: usize) -> usize {
+        let last = len as isize - 1;
+        match self {
+            EdgeMode::Clamp => i.clamp(0, last) as usize,
+            EdgeMode::Mirror if last == 0 => 0,
+            EdgeMode::Mirror => {
+                let i = i.rem_euclid(2 * last);
+                (if i > last { 2 * last - i } else { i }) as usize
+            }
+            EdgeMode::Wrap => i.rem_euclid(len as isize) as usize,
+        }
+    }
+}
+
+pub(crate) fn replace_with_bg(col: &RGBColor, bkgd: &RGBColor) -> RGBColor {
+    if is_transparent(col.0, col.1, col.2) {
+        *bkgd
+    } else {
+        *col
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edge_mode_index() {
+        let cases = [
+            (EdgeMode::Clamp, [0, 0, 3, 3]),
+            (EdgeMode::Mirror, [2, 1, 2, 1]),
+            (EdgeMode::Wrap, [2, 3, 0, 1]),
+        ];
+        for (edges, expected) in cases.iter() {
+            let indices: Vec<usize> = [-2, -1, 4, 5].iter().map(|&i| edges.index(i, 4)).collect();
+            assert_eq!(indices, expected, "{:?}", edges);
+            assert_eq!(edges.index(2, 4), 2);
+            assert_eq!(edges.index(-1, 1), 0);
+        }
+    }
+
+    #[test]
+    fn parse_edge_mode() {
+        assert_eq!(EdgeMode::parse("mirror").unwrap(), EdgeMode::Mirror);
+        assert!(EdgeMode::parse("reflect").is_err());
+    }
+}
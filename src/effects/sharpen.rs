@@ -0,0 +1,121 @@
+use super::blur::gaussian_kernel;
+use super::convolve::{convolve, convolve_separable, Kernel};
+use super::{replace_with_bg, EdgeMode, Effect};
+use crate::common::*;
+
+// Adds the difference from the four closest pixels
+pub struct Sharpen {
+    edges: EdgeMode,
+}
+
+impl Sharpen {
+    pub fn new(edges: EdgeMode) -> Sharpen {
+        Sharpen { edges }
+    }
+}
+
+#[rustfmt::skip]
+const SHARPEN: [f32; 9] = [
+     0.0, -1.0,  0.0,
+    -1.0,  5.0, -1.0,
+     0.0, -1.0,  0.0,
+];
+
+impl Effect for Sharpen {
+    fn apply(&self, image: &Image<RGBColor>, bkgd: &RGBColor) -> Image<RGBColor> {
+        let samples = image.map(|col| to_sample(&replace_with_bg(&col, bkgd)));
+        let sharpened = convolve(&samples, &Kernel::square(&SHARPEN), self.edges);
+
+        // The kernel adds up to 1, so only the colors change and opacity is kept as it was
+        ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+            let [r, g, b, _] = sharpened[(x, y)];
+            to_color(&[r, g, b, samples[(x, y)][3]])
+        })
+    }
+}
+
+// Sharpens by adding back the difference between the image and a blurred copy of it
+pub struct UnsharpMask {
+    // Sigma of the blur, in pixels
+    radius: f32,
+    // How much of the difference is added
+    amount: f32,
+    // Differences smaller than this are left alone, so that noise isn't sharpened too
+    threshold: f32,
+    edges: EdgeMode,
+}
+
+impl UnsharpMask {
+    pub fn new(radius: f32, amount: f32, threshold: f32, edges: EdgeMode) -> UnsharpMask {
+        UnsharpMask {
+            radius,
+            amount,
+            threshold,
+            edges,
+        }
+    }
+}
+
+impl Effect for UnsharpMask {
+    fn apply(&self, image: &Image<RGBColor>, bkgd: &RGBColor) -> Image<RGBColor> {
+        if image.is_empty() || self.radius <= 0.0 {
+            return image.clone();
+        }
+
+        let samples = image.map(|col| to_sample(&replace_with_bg(&col, bkgd)));
+        let kernel = gaussian_kernel(self.radius);
+        let blurred = convolve_separable(&samples, &kernel, &kernel, self.edges);
+
+        ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+            let mut sample = samples[(x, y)];
+            let blurred = blurred[(x, y)];
+            for c in 0..3 {
+                let difference = sample[c] - blurred[c];
+                if difference.abs() >= self.threshold {
+                    sample[c] += difference * self.amount;
+                }
+            }
+            to_color(&sample)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Brightness 10 on the left half and 30 on the right
+    fn step() -> Image<RGBColor> {
+        ImageBuffer::from_fn(6, 3, |x, _| if x < 3 { (10, 10, 10) } else { (30, 30, 30) })
+    }
+
+    #[test]
+    fn constant_image() {
+        let image = ImageBuffer::new(4, 4, (100, 150, 200));
+        let sharpened = Sharpen::new(EdgeMode::Clamp).apply(&image, &(0, 0, 0));
+        assert_eq!(sharpened, image);
+        let unsharp = UnsharpMask::new(1.0, 1.0, 0.0, EdgeMode::Clamp).apply(&image, &(0, 0, 0));
+        assert_eq!(unsharp, image);
+    }
+
+    #[test]
+    fn sharpen() {
+        let sharpened = Sharpen::new(EdgeMode::Clamp).apply(&step(), &(0, 0, 0));
+        let row: Vec<u8> = sharpened.row(1).iter().map(|col| col.0).collect();
+        // 5 * 10 - 10 - 10 - 10 - 30 is clamped to 0, which is made opaque in blue
+        assert_eq!(row, [10, 10, 0, 50, 30, 30]);
+        assert_eq!(sharpened[(2, 1)], (0, 0, 1));
+    }
+
+    #[test]
+    fn unsharp_mask() {
+        let sharpened = UnsharpMask::new(1.0, 1.0, 0.0, EdgeMode::Clamp).apply(&step(), &(0, 0, 0));
+        assert!(sharpened[(2, 1)].0 < 10 && sharpened[(3, 1)].0 > 30);
+        assert_eq!((sharpened[(0, 1)].0, sharpened[(5, 1)].0), (10, 30));
+
+        // The step is smaller than the threshold
+        let unchanged =
+            UnsharpMask::new(1.0, 1.0, 20.0, EdgeMode::Clamp).apply(&step(), &(0, 0, 0));
+        assert_eq!(unchanged, step());
+    }
+}
@@ -0,0 +1,375 @@
+use crate::common::*;
+use crate::tiff::ifd::{self, Ifd};
+use std::io::{Error, ErrorKind, Result};
+
+mod tags {
+    pub const MAKE: u16 = 271;
+    pub const MODEL: u16 = 272;
+    pub const ORIENTATION: u16 = 274;
+    pub const DATE_TIME: u16 = 306;
+    pub const EXIF_IFD: u16 = 34665;
+    pub const GPS_IFD: u16 = 34853;
+    pub const DATE_TIME_ORIGINAL: u16 = 36867;
+
+    pub const GPS_LATITUDE_REF: u16 = 1;
+    pub const GPS_LATITUDE: u16 = 2;
+    pub const GPS_LONGITUDE_REF: u16 = 3;
+    pub const GPS_LONGITUDE: u16 = 4;
+    pub const GPS_ALTITUDE_REF: u16 = 5;
+    pub const GPS_ALTITUDE: u16 = 6;
+}
+
+// JPEG APP1 segments start with this before the TIFF header
+const APP1_PREFIX: &[u8] = b"Exif\0\0";
+
+pub struct Gps {
+    // In degrees, negative for south and west
+    pub latitude: f64,
+    pub longitude: f64,
+    // In metres, negative below sea level
+    pub altitude: Option<f64>,
+}
+
+#[derive(Default)]
+pub struct Exif {
+    // 1 to 8, as defined by the TIFF Orientation tag
+    pub orientation: Option<u16>,
+    pub make: Option<String>,
+    pub model: Option<String>,
+    // As stored, "YYYY:MM:DD HH:MM:SS"
+    pub date_time: Option<String>,
+    pub gps: Option<Gps>,
+}
+
+// Degrees, minutes and seconds to decimal degrees
+fn coordinate(gps: &Ifd, tag: u16, ref_tag: u16, negative: &str) -> Option<f64> {
+    let dms = gps.get(tag)?.rationals()?;
+    if dms.len() < 3 {
+        return None;
+    }
+    let degrees = dms[0] + dms[1] / 60.0 + dms[2] / 3600.0;
+    if !degrees.is_finite() {
+        return None;
+    }
+
+    match gps.get(ref_tag).and_then(|entry| entry.ascii()) {
+        Some(reference) if reference == negative => Some(-degrees),
+        _ => Some(degrees),
+    }
+}
+
+fn parse_gps(buffer: &[u8], offset: usize, order: ifd::ByteOrder) -> Option<Gps> {
+    let gps = Ifd::parse(buffer, offset, order).ok()?;
+
+    let altitude = gps
+        .get(tags::GPS_ALTITUDE)
+        .and_then(|entry| entry.rationals())
+        .and_then(|values| values.first().copied())
+        .filter(|altitude| altitude.is_finite())
+        .map(|altitude| {
+            // 1 means below sea level
+            if gps.value(tags::GPS_ALTITUDE_REF) == Some(1) {
+                -altitude
+            } else {
+                altitude
+            }
+        });
+
+    Some(Gps {
+        latitude: coordinate(&gps, tags::GPS_LATITUDE, tags::GPS_LATITUDE_REF, "S")?,
+        longitude: coordinate(&gps, tags::GPS_LONGITUDE, tags::GPS_LONGITUDE_REF, "W")?,
+        altitude,
+    })
+}
+
+// Parses EXIF data, which is a TIFF file without any image data. Accepts the contents of the
+// PNG eXIf and WebP EXIF chunks, and JPEG APP1 segments
+pub fn parse(bytes: &[u8]) -> Result<Exif> {
+    let buffer = bytes.strip_prefix(APP1_PREFIX).unwrap_or(bytes);
+    let (order, offset) = ifd::parse_header(buffer)?;
+    let ifd0 = Ifd::parse(buffer, offset, order)?;
+
+    let mut exif = Exif {
+        orientation: ifd0
+            .value(tags::ORIENTATION)
+            .map(|orientation| orientation as u16)
+            .filter(|orientation| (1..=8).contains(orientation)),
+        make: ifd0.get(tags::MAKE).and_then(|entry| entry.ascii()),
+        model: ifd0.get(tags::MODEL).and_then(|entry| entry.ascii()),
+        date_time: ifd0.get(tags::DATE_TIME).and_then(|entry| entry.ascii()),
+        gps: None,
+    };
+
+    // The time the photo was taken is preferred over when the file was last changed
+    if let Some(offset) = ifd0.value(tags::EXIF_IFD) {
+        let date_time = Ifd::parse(buffer, offset as usize, order)
+            .ok()
+            .and_then(|exif_ifd| {
+                exif_ifd
+                    .get(tags::DATE_TIME_ORIGINAL)
+                    .and_then(|entry| entry.ascii())
+            });
+        if date_time.is_some() {
+            exif.date_time = date_time;
+        }
+    }
+
+    if let Some(offset) = ifd0.value(tags::GPS_IFD) {
+        exif.gps = parse_gps(buffer, offset as usize, order);
+    }
+
+    if exif.orientation.is_none()
+        && exif.make.is_none()
+        && exif.model.is_none()
+        && exif.date_time.is_none()
+        && exif.gps.is_none()
+    {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "EXIF data has none of the supported tags",
+        ));
+    }
+
+    Ok(exif)
+}
+
+// Orientations 5 to 8 turn the image on its side
+pub fn swaps_axes(orientation: u16) -> bool {
+    (5..=8).contains(&orientation)
+}
+
+// Transforms the image so that it is displayed upright. The orientation says where the stored
+// first row and column should end up, orientations 5 to 8 swap the width and height
+pub fn apply_orientation<T: Copy>(image: Image<T>, orientation: u16) -> Image<T> {
+    if image.is_empty() || !(2..=8).contains(&orientation) {
+        return image;
+    }
+
+    let w = image.width();
+    let h = image.height();
+    let (out_w, out_h) = if swaps_axes(orientation) {
+        (h, w)
+    } else {
+        (w, h)
+    };
+
+    ImageBuffer::from_fn(out_w, out_h, |x, y| {
+        let (sx, sy) = match orientation {
+            2 => (w - 1 - x, y),
+            3 => (w - 1 - x, h - 1 - y),
+            4 => (x, h - 1 - y),
+            5 => (y, x),
+            6 => (y, h - 1 - x),
+            7 => (w - 1 - y, h - 1 - x),
+            _ => (w - 1 - y, x),
+        };
+        image[(sx, sy)]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    enum Value<'a> {
+        Byte(u8),
+        Short(u16),
+        Ascii(&'a str),
+        Rationals(&'a [(u32, u32)]),
+        // Offset of another IFD
+        Ifd(usize),
+    }
+
+    // A TIFF structure with the given IFDs one after the other, followed by the values that don't
+    // fit in their entries
+    fn exif(order: ifd::ByteOrder, ifds: &[&[(u16, Value)]]) -> Vec<u8> {
+        let big_endian = order == ifd::ByteOrder::BigEndian;
+        let u16_bytes = |v: u16| {
+            if big_endian {
+                v.to_be_bytes()
+            } else {
+                v.to_le_bytes()
+            }
+        };
+        let u32_bytes = |v: u32| {
+            if big_endian {
+                v.to_be_bytes()
+            } else {
+                v.to_le_bytes()
+            }
+        };
+
+        let mut offsets = Vec::new();
+        let mut end = 8;
+        for entries in ifds {
+            offsets.push(end as u32);
+            end += 2 + 12 * entries.len() + 4;
+        }
+
+        let mut out = if big_endian {
+            b"MM".to_vec()
+        } else {
+            b"II".to_vec()
+        };
+        out.extend_from_slice(&u16_bytes(42));
+        out.extend_from_slice(&u32_bytes(8));
+
+        let mut values = Vec::new();
+        for entries in ifds {
+            out.extend_from_slice(&u16_bytes(entries.len() as u16));
+            for (tag, value) in entries.iter() {
+                let (field_type, count, mut data) = match value {
+                    Value::Byte(v) => (ifd::BYTE, 1, vec![*v]),
+                    Value::Short(v) => (ifd::SHORT, 1, u16_bytes(*v).to_vec()),
+                    Value::Ascii(s) => (ifd::ASCII, s.len() + 1, [s.as_bytes(), &[0]].concat()),
+                    Value::Rationals(r) => (
+                        ifd::RATIONAL,
+                        r.len(),
+                        r.iter()
+                            .flat_map(|&(n, d)| [u32_bytes(n), u32_bytes(d)].concat())
+                            .collect(),
+                    ),
+                    Value::Ifd(i) => (ifd::LONG, 1, u32_bytes(offsets[*i]).to_vec()),
+                };
+                out.extend_from_slice(&u16_bytes(*tag));
+                out.extend_from_slice(&u16_bytes(field_type));
+                out.extend_from_slice(&u32_bytes(count as u32));
+                if data.len() <= 4 {
+                    data.resize(4, 0);
+                    out.extend_from_slice(&data);
+                } else {
+                    out.extend_from_slice(&u32_bytes((end + values.len()) as u32));
+                    values.extend_from_slice(&data);
+                }
+            }
+            out.extend_from_slice(&u32_bytes(0));
+        }
+
+        out.extend_from_slice(&values);
+        out
+    }
+
+    fn camera(order: ifd::ByteOrder) -> Vec<u8> {
+        exif(
+            order,
+            &[
+                &[
+                    (tags::MAKE, Value::Ascii("Canon")),
+                    (tags::MODEL, Value::Ascii("EOS 5D")),
+                    (tags::ORIENTATION, Value::Short(6)),
+                    (tags::DATE_TIME, Value::Ascii("2021:02:03 04:05:06")),
+                    (tags::EXIF_IFD, Value::Ifd(1)),
+                    (tags::GPS_IFD, Value::Ifd(2)),
+                ],
+                &[(
+                    tags::DATE_TIME_ORIGINAL,
+                    Value::Ascii("2020:01:02 03:04:05"),
+                )],
+                &[
+                    (tags::GPS_LATITUDE_REF, Value::Ascii("N")),
+                    (
+                        tags::GPS_LATITUDE,
+                        Value::Rationals(&[(51, 1), (30, 1), (0, 1)]),
+                    ),
+                    (tags::GPS_LONGITUDE_REF, Value::Ascii("W")),
+                    (
+                        tags::GPS_LONGITUDE,
+                        Value::Rationals(&[(0, 1), (7, 1), (3960, 100)]),
+                    ),
+                    (tags::GPS_ALTITUDE_REF, Value::Byte(1)),
+                    (tags::GPS_ALTITUDE, Value::Rationals(&[(105, 10)])),
+                ],
+            ],
+        )
+    }
+
+    fn check_camera(exif: &Exif) {
+        assert_eq!(exif.orientation, Some(6));
+        assert_eq!(exif.make.as_deref(), Some("Canon"));
+        assert_eq!(exif.model.as_deref(), Some("EOS 5D"));
+        // From the EXIF IFD rather than IFD0
+        assert_eq!(exif.date_time.as_deref(), Some("2020:01:02 03:04:05"));
+
+        let gps = exif.gps.as_ref().unwrap();
+        assert!((gps.latitude - 51.5).abs() < 1e-9);
+        assert!((gps.longitude + 0.1276666).abs() < 1e-6);
+        assert_eq!(gps.altitude, Some(-10.5));
+    }
+
+    #[test]
+    fn little_endian() {
+        check_camera(&parse(&camera(ifd::ByteOrder::LittleEndian)).unwrap());
+    }
+
+    #[test]
+    fn big_endian() {
+        check_camera(&parse(&camera(ifd::ByteOrder::BigEndian)).unwrap());
+    }
+
+    #[test]
+    fn app1_segment() {
+        let segment = [APP1_PREFIX, &camera(ifd::ByteOrder::BigEndian)].concat();
+        check_camera(&parse(&segment).unwrap());
+    }
+
+    #[test]
+    fn incomplete_gps() {
+        // Without a longitude there is no position at all
+        let buffer = exif(
+            ifd::ByteOrder::LittleEndian,
+            &[
+                &[
+                    (tags::ORIENTATION, Value::Short(3)),
+                    (tags::GPS_IFD, Value::Ifd(1)),
+                ],
+                &[(
+                    tags::GPS_LATITUDE,
+                    Value::Rationals(&[(51, 1), (30, 1), (0, 1)]),
+                )],
+            ],
+        );
+        let exif = parse(&buffer).unwrap();
+        assert_eq!(exif.orientation, Some(3));
+        assert!(exif.gps.is_none());
+    }
+
+    #[test]
+    fn unsupported() {
+        // Orientations outside of 1 to 8 are ignored, which leaves nothing
+        let buffer = exif(
+            ifd::ByteOrder::LittleEndian,
+            &[&[(tags::ORIENTATION, Value::Short(9))]],
+        );
+        assert_eq!(parse(&buffer).err().unwrap().kind(), ErrorKind::InvalidData);
+
+        assert!(parse(b"XX\0\x2a\0\0\0\x08").is_err());
+        assert!(parse(&camera(ifd::ByteOrder::LittleEndian)[..20]).is_err());
+    }
+
+    #[test]
+    fn orientations() {
+        // Stored 2 pixels wide and 3 high:
+        // 0 1
+        // 2 3
+        // 4 5
+        let image = ImageBuffer::from_vec(2, 3, (0..6).collect::<Vec<u8>>());
+        let cases: [(u16, usize, &[u8]); 8] = [
+            (1, 2, &[0, 1, 2, 3, 4, 5]),
+            (2, 2, &[1, 0, 3, 2, 5, 4]),
+            (3, 2, &[5, 4, 3, 2, 1, 0]),
+            (4, 2, &[4, 5, 2, 3, 0, 1]),
+            (5, 3, &[0, 2, 4, 1, 3, 5]),
+            (6, 3, &[4, 2, 0, 5, 3, 1]),
+            (7, 3, &[5, 3, 1, 4, 2, 0]),
+            (8, 3, &[1, 3, 5, 0, 2, 4]),
+        ];
+        for &(orientation, width, expected) in cases.iter() {
+            let oriented = apply_orientation(image.clone(), orientation);
+            assert_eq!(oriented.width(), width, "orientation {}", orientation);
+            assert_eq!(oriented.height(), 6 / width, "orientation {}", orientation);
+            let pixels: Vec<u8> = oriented.pixels().copied().collect();
+            assert_eq!(pixels, expected, "orientation {}", orientation);
+            assert_eq!(swaps_axes(orientation), width == 3);
+        }
+    }
+}
@@ -0,0 +1,234 @@
+use crate::common::*;
+use std::io::{Error, ErrorKind, Result};
+
+// Only uncompressed bitmaps are allowed in icons
+const BI_RGB: u32 = 0;
+
+fn truncated() -> Error {
+    Error::new(ErrorKind::UnexpectedEof, "Icon bitmap is truncated")
+}
+
+// Rows in a DIB are padded to a multiple of 4 bytes
+fn stride(width: usize, bit_count: usize) -> usize {
+    (width * bit_count).div_ceil(32) * 4
+}
+
+// Parses a DIB as stored in ICO and CUR files. It is a BMP without the file header, with its
+// height doubled because the colour (XOR) bitmap is followed by a 1 bit transparency (AND) mask
+pub fn parse(bytes: &[u8], metadata: &mut Metadata) -> Result<Image<RGBColor>> {
+    if bytes.len() < 40 {
+        return Err(truncated());
+    }
+
+    let header_size = from_bytes_u32_le(&bytes[0..4]) as usize;
+    let width = (from_bytes_u32_le(&bytes[4..8]) as i32).unsigned_abs() as usize;
+    let raw_height = from_bytes_u32_le(&bytes[8..12]) as i32;
+    let bit_count = from_bytes_u16_le(&bytes[14..16]) as usize;
+    let compression = from_bytes_u32_le(&bytes[16..20]);
+    let colors_used = from_bytes_u32_le(&bytes[32..36]) as usize;
+
+    // A negative height means the rows are stored top to bottom
+    let top_down = raw_height < 0;
+    let height = raw_height.unsigned_abs() as usize / 2;
+
+    if compression != BI_RGB {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Unsupported icon bitmap compression: {}", compression),
+        ));
+    }
+
+    if ![1, 4, 8, 16, 24, 32].contains(&bit_count) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Unsupported icon bit count: {}", bit_count),
+        ));
+    }
+
+    let mut i = header_size;
+
+    let palette = if bit_count <= 8 {
+        let len = if colors_used == 0 {
+            1 << bit_count
+        } else {
+            colors_used
+        };
+        let palette_bytes = bytes.get(i..i + len * 4).ok_or_else(truncated)?;
+        i += len * 4;
+
+        // Entries are stored as BGR followed by a reserved byte
+        palette_bytes
+            .chunks_exact(4)
+            .map(|c| (c[2], c[1], c[0]))
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let xor_stride = stride(width, bit_count);
+    let xor = bytes
+        .get(i..i + xor_stride * height)
+        .ok_or_else(truncated)?;
+    i += xor_stride * height;
+
+    // Some 32 bit icons leave out the mask since the alpha channel is used anyway
+    let and_stride = stride(width, 1);
+    let and = bytes.get(i..i + and_stride * height);
+
+    // Older 32 bit icons have an empty alpha channel and rely only on the mask
+    let use_alpha = bit_count == 32 && xor.chunks_exact(4).any(|px| px[3] != 0);
+
+    let mut image: Image<RGBColor> = ImageBuffer::with_width(width, height);
+
+    for y in 0..height {
+        let src_y = if top_down { y } else { height - 1 - y };
+        let row = &xor[src_y * xor_stride..(src_y + 1) * xor_stride];
+        let mask = and.map(|and| &and[src_y * and_stride..(src_y + 1) * and_stride]);
+
+        let mut scanline = Vec::with_capacity(width);
+        for x in 0..width {
+            let masked = match mask {
+                Some(mask) => (mask[x / 8] >> (7 - x % 8)) & 1 == 1,
+                None => false,
+            };
+
+            let (r, g, b, a) = match bit_count {
+                1 | 4 | 8 => {
+                    // Worked out in u16, as 1 << 8 doesn't fit in the u8 the index is read from
+                    let bits = ((1u16 << bit_count) - 1) as u8;
+                    let bit = x * bit_count;
+                    let index = (row[bit / 8] >> (8 - bit_count - bit % 8)) & bits;
+                    let (r, g, b) = match palette.get(index as usize) {
+                        Some(color) => *color,
+                        None => {
+                            return Err(Error::new(
+                                ErrorKind::InvalidData,
+                                format!("Palette index out of bounds: {}", index),
+                            ))
+                        }
+                    };
+                    (r, g, b, 255)
+                }
+                16 => {
+                    // 5 bits per channel, stored as xRRRRRGG GGGBBBBB
+                    let val = from_bytes_u16_le(&row[x * 2..x * 2 + 2]);
+                    let scale = |c: u16| ((c << 3) | (c >> 2)) as u8;
+                    (
+                        scale((val >> 10) & 0x1f),
+                        scale((val >> 5) & 0x1f),
+                        scale(val & 0x1f),
+                        255,
+                    )
+                }
+                24 => (row[x * 3 + 2], row[x * 3 + 1], row[x * 3], 255),
+                _ => (
+                    row[x * 4 + 2],
+                    row[x * 4 + 1],
+                    row[x * 4],
+                    if use_alpha { row[x * 4 + 3] } else { 255 },
+                ),
+            };
+
+            if masked && !use_alpha {
+                scanline.push((0, 0, 0));
+            } else {
+                scanline.push(rgba_color(r, g, b, a));
+            }
+        }
+        image.push_row(&scanline);
+    }
+
+    let (bit_depth, color_type) = match bit_count {
+        1 | 4 | 8 => (bit_count as u8, ColorType::Palette),
+        16 => (5, ColorType::RGB),
+        24 => (8, ColorType::RGB),
+        _ => (8, ColorType::RGBA),
+    };
+    metadata.set_image_info(width as u32, height as u32, bit_depth, color_type);
+    if !palette.is_empty() {
+        metadata.set_palette(palette);
+    }
+
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A bottom-up DIB with a palette of colors (i, i, i) for each index i, every pixel opaque
+    fn palette_dib(width: usize, height: usize, bit_count: usize, indices: &[&[u8]]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&40u32.to_le_bytes());
+        bytes.extend_from_slice(&(width as u32).to_le_bytes());
+        bytes.extend_from_slice(&(height as u32 * 2).to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&(bit_count as u16).to_le_bytes());
+        bytes.extend_from_slice(&[0; 24]);
+
+        for i in 0..1usize << bit_count {
+            bytes.extend_from_slice(&[i as u8, i as u8, i as u8, 0]);
+        }
+
+        for row in indices.iter().rev() {
+            let mut packed = vec![0u8; stride(width, bit_count)];
+            for (x, &index) in row.iter().enumerate() {
+                let bit = x * bit_count;
+                packed[bit / 8] |= index << (8 - bit_count - bit % 8);
+            }
+            bytes.extend_from_slice(&packed);
+        }
+        bytes.extend(vec![0; stride(width, 1) * height]);
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> Vec<RGBColor> {
+        let mut metadata = Metadata::new();
+        parse(bytes, &mut metadata)
+            .unwrap()
+            .pixels()
+            .copied()
+            .collect()
+    }
+
+    #[test]
+    fn one_bit() {
+        let bytes = palette_dib(3, 2, 1, &[&[1, 0, 1], &[0, 1, 0]]);
+        assert_eq!(
+            decode(&bytes),
+            vec![
+                (1, 1, 1),
+                (0, 0, 1),
+                (1, 1, 1),
+                (0, 0, 1),
+                (1, 1, 1),
+                (0, 0, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn four_bit() {
+        let bytes = palette_dib(3, 2, 4, &[&[15, 2, 9], &[4, 0, 7]]);
+        assert_eq!(
+            decode(&bytes),
+            vec![
+                (15, 15, 15),
+                (2, 2, 2),
+                (9, 9, 9),
+                (4, 4, 4),
+                (0, 0, 1),
+                (7, 7, 7)
+            ]
+        );
+    }
+
+    #[test]
+    fn eight_bit() {
+        let bytes = palette_dib(2, 2, 8, &[&[255, 1], &[128, 200]]);
+        assert_eq!(
+            decode(&bytes),
+            vec![(255, 255, 255), (1, 1, 1), (128, 128, 128), (200, 200, 200)]
+        );
+    }
+}
@@ -0,0 +1,133 @@
+mod dib;
+
+use crate::common::*;
+use crate::png;
+use std::io::{Error, ErrorKind, Result};
+
+const HEADER_SIZE: usize = 6;
+const ENTRY_SIZE: usize = 16;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ResourceType {
+    Icon,
+    Cursor,
+}
+
+pub struct DirEntry {
+    pub width: u32,
+    pub height: u32,
+    pub bit_count: u16,
+    pub size: usize,
+    pub offset: usize,
+}
+
+impl DirEntry {
+    fn parse(bytes: &[u8], resource_type: &ResourceType) -> DirEntry {
+        DirEntry {
+            // A size of 0 means 256 pixels
+            width: if bytes[0] == 0 { 256 } else { bytes[0] as u32 },
+            height: if bytes[1] == 0 { 256 } else { bytes[1] as u32 },
+            // In cursors this field is the y coordinate of the hotspot instead
+            bit_count: match resource_type {
+                ResourceType::Icon => from_bytes_u16_le(&bytes[6..8]),
+                ResourceType::Cursor => 0,
+            },
+            size: from_bytes_u32_le(&bytes[8..12]) as usize,
+            offset: from_bytes_u32_le(&bytes[12..16]) as usize,
+        }
+    }
+
+    fn data<'a>(&self, buffer: &'a [u8]) -> Result<&'a [u8]> {
+        buffer
+            .get(self.offset..self.offset + self.size)
+            .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "Icon image data is truncated"))
+    }
+}
+
+pub fn resource_type(buffer: &[u8]) -> Option<ResourceType> {
+    if buffer.len() < HEADER_SIZE || buffer[0..2] != [0, 0] {
+        return None;
+    }
+
+    match from_bytes_u16_le(&buffer[2..4]) {
+        1 => Some(ResourceType::Icon),
+        2 => Some(ResourceType::Cursor),
+        _ => None,
+    }
+}
+
+pub fn is_ico(buffer: &[u8]) -> bool {
+    resource_type(buffer).is_some() && from_bytes_u16_le(&buffer[4..6]) > 0
+}
+
+pub fn parse_directory(buffer: &[u8]) -> Result<Vec<DirEntry>> {
+    let resource_type = match resource_type(buffer) {
+        Some(resource_type) => resource_type,
+        None => return Err(Error::new(ErrorKind::InvalidData, "Invalid ICO/CUR header")),
+    };
+
+    let count = from_bytes_u16_le(&buffer[4..6]) as usize;
+    let mut entries = Vec::with_capacity(count);
+
+    for n in 0..count {
+        let s = HEADER_SIZE + n * ENTRY_SIZE;
+        let bytes = buffer
+            .get(s..s + ENTRY_SIZE)
+            .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "Icon directory is truncated"))?;
+        let mut entry = DirEntry::parse(bytes, &resource_type);
+
+        // The directory bit count is unreliable (and missing for cursors), so it is read from the
+        // header of the embedded image instead
+        if let Ok(data) = entry.data(buffer) {
+            if let Some(bit_count) = embedded_bit_count(data) {
+                entry.bit_count = bit_count;
+            }
+        }
+
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+// The best entry is the largest one, as it will be downsized to fit the terminal anyway. Between
+// entries of the same size, the one with the most colors wins
+pub fn best_entry(entries: &[DirEntry]) -> Option<&DirEntry> {
+    entries
+        .iter()
+        .max_by_key(|entry| (entry.width * entry.height, entry.bit_count))
+}
+
+fn embedded_bit_count(data: &[u8]) -> Option<u16> {
+    if data.starts_with(&png::SIGNATURE) {
+        // The IHDR chunk is always first, its data starts after the 8 byte chunk header
+        let ihdr = data.get(16..29)?;
+        let channels = match ihdr[9] {
+            0 | 3 => 1,
+            4 => 2,
+            2 => 3,
+            6 => 4,
+            _ => return None,
+        };
+        Some(ihdr[8] as u16 * channels)
+    } else {
+        data.get(14..16).map(from_bytes_u16_le)
+    }
+}
+
+pub fn parse(buffer: Vec<u8>, metadata: &mut Metadata) -> Result<Image<RGBColor>> {
+    let entries = parse_directory(&buffer)?;
+    let entry = match best_entry(&entries) {
+        Some(entry) => entry,
+        None => return Err(Error::new(ErrorKind::NotFound, "Icon has no images")),
+    };
+
+    let data = entry.data(&buffer)?;
+
+    // Since Windows Vista, icon images can be stored as complete PNG files instead of a DIB
+    if data.starts_with(&png::SIGNATURE) {
+        png::parse(data.to_vec(), metadata)
+    } else {
+        dib::parse(data, metadata)
+    }
+}
@@ -0,0 +1,385 @@
+use std::ops::{Index, IndexMut};
+
+// A 2D grid of pixels stored row after row in a single Vec. Row y starts at y * stride, and
+// only the first width pixels of it belong to the image
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImageBuffer<P> {
+    width: usize,
+    height: usize,
+    stride: usize,
+    data: Vec<P>,
+}
+
+// A borrowed rectangle of an image, which shares the stride of the image it is from
+#[derive(Clone, Copy, Debug)]
+pub struct ImageView<'a, P> {
+    width: usize,
+    height: usize,
+    stride: usize,
+    data: &'a [P],
+}
+
+// Panics if something ending just before (end_x, end_y) doesn't fit in the image
+fn check_bounds(what: &str, end_x: usize, end_y: usize, width: usize, height: usize) {
+    if end_x > width || end_y > height {
+        panic!(
+            "{} ending at ({}, {}) is out of bounds of {}x{}",
+            what, end_x, end_y, width, height
+        );
+    }
+}
+
+// An empty 0x0 image
+impl<P> Default for ImageBuffer<P> {
+    fn default() -> ImageBuffer<P> {
+        ImageBuffer {
+            width: 0,
+            height: 0,
+            stride: 0,
+            data: Vec::new(),
+        }
+    }
+}
+
+impl<P: Copy> ImageBuffer<P> {
+    pub fn new(width: usize, height: usize, fill: P) -> ImageBuffer<P> {
+        ImageBuffer {
+            width,
+            height,
+            stride: width,
+            data: vec![fill; width * height],
+        }
+    }
+
+    // No rows yet, for decoders that add them one at a time with push_row
+    pub fn with_width(width: usize, rows: usize) -> ImageBuffer<P> {
+        ImageBuffer {
+            width,
+            height: 0,
+            stride: width,
+            data: Vec::with_capacity(width * rows),
+        }
+    }
+
+    pub fn from_vec(width: usize, height: usize, data: Vec<P>) -> ImageBuffer<P> {
+        assert_eq!(
+            data.len(),
+            width * height,
+            "pixel count doesn't match {}x{}",
+            width,
+            height
+        );
+        ImageBuffer {
+            width,
+            height,
+            stride: width,
+            data,
+        }
+    }
+
+    pub fn from_fn(
+        width: usize,
+        height: usize,
+        mut f: impl FnMut(usize, usize) -> P,
+    ) -> ImageBuffer<P> {
+        let mut data = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                data.push(f(x, y));
+            }
+        }
+        ImageBuffer::from_vec(width, height, data)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // Number of pixels from the start of a row to the start of the next
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    // Every row must be exactly width pixels long
+    pub fn push_row(&mut self, row: &[P]) {
+        assert_eq!(
+            row.len(),
+            self.width,
+            "row {} has the wrong length",
+            self.height
+        );
+        self.data.extend_from_slice(row);
+        self.height += 1;
+    }
+
+    // Adds rows of fill, or removes rows from the bottom
+    pub fn resize_height(&mut self, height: usize, fill: P) {
+        self.data.resize(height * self.stride, fill);
+        self.height = height;
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Option<&P> {
+        if x < self.width && y < self.height {
+            Some(&self.data[y * self.stride + x])
+        } else {
+            None
+        }
+    }
+
+    pub fn get_mut(&mut self, x: usize, y: usize) -> Option<&mut P> {
+        if x < self.width && y < self.height {
+            Some(&mut self.data[y * self.stride + x])
+        } else {
+            None
+        }
+    }
+
+    pub fn row(&self, y: usize) -> &[P] {
+        check_bounds("Row", 0, y + 1, self.width, self.height);
+        &self.data[y * self.stride..y * self.stride + self.width]
+    }
+
+    pub fn row_mut(&mut self, y: usize) -> &mut [P] {
+        check_bounds("Row", 0, y + 1, self.width, self.height);
+        &mut self.data[y * self.stride..y * self.stride + self.width]
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[P]> + '_ {
+        (0..self.height).map(move |y| self.row(y))
+    }
+
+    pub fn rows_mut(&mut self) -> impl Iterator<Item = &mut [P]> + '_ {
+        let width = self.width;
+        self.data
+            .chunks_mut(self.stride.max(1))
+            .take(self.height)
+            .map(move |row| &mut row[..width])
+    }
+
+    // Row by row, left to right
+    pub fn pixels(&self) -> impl Iterator<Item = &P> + '_ {
+        self.rows().flatten()
+    }
+
+    pub fn pixels_mut(&mut self) -> impl Iterator<Item = &mut P> + '_ {
+        self.rows_mut().flatten()
+    }
+
+    // Same as pixels, with the position of each pixel
+    pub fn enumerate_pixels(&self) -> impl Iterator<Item = (usize, usize, &P)> + '_ {
+        self.rows()
+            .enumerate()
+            .flat_map(|(y, row)| row.iter().enumerate().map(move |(x, px)| (x, y, px)))
+    }
+
+    pub fn as_view(&self) -> ImageView<'_, P> {
+        ImageView {
+            width: self.width,
+            height: self.height,
+            stride: self.stride,
+            data: &self.data,
+        }
+    }
+
+    // The width x height rectangle with its top left corner at (x, y)
+    pub fn view(&self, x: usize, y: usize, width: usize, height: usize) -> ImageView<'_, P> {
+        self.as_view().view(x, y, width, height)
+    }
+
+    pub fn map<Q: Copy>(&self, mut f: impl FnMut(P) -> Q) -> ImageBuffer<Q> {
+        ImageBuffer::from_fn(self.width, self.height, |x, y| f(self[(x, y)]))
+    }
+}
+
+impl<'a, P: Copy> ImageView<'a, P> {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Option<&'a P> {
+        if x < self.width && y < self.height {
+            Some(&self.data[y * self.stride + x])
+        } else {
+            None
+        }
+    }
+
+    pub fn row(&self, y: usize) -> &'a [P] {
+        check_bounds("Row", 0, y + 1, self.width, self.height);
+        &self.data[y * self.stride..y * self.stride + self.width]
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &'a [P]> + '_ {
+        (0..self.height).map(move |y| self.row(y))
+    }
+
+    pub fn pixels(&self) -> impl Iterator<Item = &'a P> + '_ {
+        self.rows().flatten()
+    }
+
+    // Relative to the top left corner of this view
+    pub fn view(&self, x: usize, y: usize, width: usize, height: usize) -> ImageView<'a, P> {
+        check_bounds("View", x + width, y + height, self.width, self.height);
+        // With no rows, the corner can be past the end of the data
+        let data = if height == 0 {
+            &self.data[..0]
+        } else {
+            let start = y * self.stride + x;
+            &self.data[start..start + (height - 1) * self.stride + width]
+        };
+        ImageView {
+            width,
+            height,
+            stride: self.stride,
+            data,
+        }
+    }
+
+    // Copies the pixels into an image of their own
+    pub fn to_buffer(&self) -> ImageBuffer<P> {
+        let mut image = ImageBuffer::with_width(self.width, self.height);
+        for row in self.rows() {
+            image.push_row(row);
+        }
+        image
+    }
+}
+
+impl<P: Copy> Index<(usize, usize)> for ImageBuffer<P> {
+    type Output = P;
+
+    // Indexed by (x, y)
+    fn index(&self, (x, y): (usize, usize)) -> &P {
+        check_bounds("Pixel", x + 1, y + 1, self.width, self.height);
+        &self.data[y * self.stride + x]
+    }
+}
+
+impl<P: Copy> IndexMut<(usize, usize)> for ImageBuffer<P> {
+    fn index_mut(&mut self, (x, y): (usize, usize)) -> &mut P {
+        check_bounds("Pixel", x + 1, y + 1, self.width, self.height);
+        &mut self.data[y * self.stride + x]
+    }
+}
+
+impl<'a, P: Copy> Index<(usize, usize)> for ImageView<'a, P> {
+    type Output = P;
+
+    fn index(&self, (x, y): (usize, usize)) -> &P {
+        check_bounds("Pixel", x + 1, y + 1, self.width, self.height);
+        &self.data[y * self.stride + x]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Each pixel is its position, so it is easy to tell where it came from
+    fn positions(width: usize, height: usize) -> ImageBuffer<(usize, usize)> {
+        ImageBuffer::from_fn(width, height, |x, y| (x, y))
+    }
+
+    #[test]
+    fn rows() {
+        let image = positions(3, 2);
+        assert_eq!(image.stride(), 3);
+        assert_eq!(image.row(1), &[(0, 1), (1, 1), (2, 1)]);
+        assert_eq!(image.rows().count(), 2);
+        assert_eq!(image.pixels().count(), 6);
+        assert_eq!(image.get(3, 0), None);
+        assert_eq!(image.get(2, 1), Some(&(2, 1)));
+    }
+
+    #[test]
+    fn rows_mut() {
+        let mut image = positions(3, 2);
+        for (y, row) in image.rows_mut().enumerate() {
+            assert_eq!(row.len(), 3);
+            row[0] = (9, y);
+        }
+        assert_eq!(image[(0, 1)], (9, 1));
+        assert_eq!(image[(1, 1)], (1, 1));
+    }
+
+    #[test]
+    fn view() {
+        let image = positions(5, 4);
+        let view = image.view(1, 2, 3, 2);
+        assert_eq!((view.width(), view.height(), view.stride()), (3, 2, 5));
+        assert_eq!(view[(0, 0)], (1, 2));
+        assert_eq!(view.row(1), &[(1, 3), (2, 3), (3, 3)]);
+        assert_eq!(view.get(3, 0), None);
+
+        // Views of views are relative to their corner
+        let inner = view.view(1, 1, 2, 1);
+        assert_eq!(inner.row(0), &[(2, 3), (3, 3)]);
+
+        let buffer = view.to_buffer();
+        assert_eq!(
+            (buffer.width(), buffer.height(), buffer.stride()),
+            (3, 2, 3)
+        );
+        assert_eq!(buffer, ImageBuffer::from_fn(3, 2, |x, y| (x + 1, y + 2)));
+    }
+
+    #[test]
+    fn empty_views() {
+        let image = positions(4, 3);
+        for (x, y, width, height) in [(0, 0, 0, 0), (4, 0, 0, 3), (2, 3, 2, 0), (4, 3, 0, 0)] {
+            let view = image.view(x, y, width, height);
+            assert_eq!(view.pixels().count(), 0);
+            assert_eq!(view.rows().count(), height);
+
+            let buffer = view.to_buffer();
+            assert_eq!((buffer.width(), buffer.height()), (width, height));
+            assert!(buffer.is_empty());
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn view_out_of_bounds() {
+        positions(4, 3).view(3, 0, 2, 1);
+    }
+
+    #[test]
+    fn push_row() {
+        let mut image = ImageBuffer::with_width(2, 3);
+        assert!(image.is_empty());
+        image.push_row(&[1, 2]);
+        image.push_row(&[3, 4]);
+        assert_eq!(image, ImageBuffer::from_vec(2, 2, vec![1, 2, 3, 4]));
+    }
+
+    #[test]
+    #[should_panic]
+    fn push_row_wrong_length() {
+        ImageBuffer::with_width(2, 1).push_row(&[1, 2, 3]);
+    }
+
+    #[test]
+    fn resize_height() {
+        let mut image = ImageBuffer::from_vec(2, 2, vec![1, 2, 3, 4]);
+        image.resize_height(3, 0);
+        assert_eq!(image.row(2), &[0, 0]);
+        image.resize_height(1, 0);
+        assert_eq!(image, ImageBuffer::from_vec(2, 1, vec![1, 2]));
+    }
+}
@@ -0,0 +1,697 @@
+// zlib (RFC 1950) and DEFLATE (RFC 1951) decompression. Input can be fed in pieces as it
+// arrives, decoding as far as the available input allows
+use std::io::{Error, ErrorKind, Result};
+
+const MAX_BITS: usize = 15;
+// Codes up to this length are decoded with a single table lookup
+const FAST_BITS: usize = 10;
+// Consumed input is only dropped once there is this much of it, to avoid moving it too often
+const COMPACT_SIZE: usize = 64 * 1024;
+
+static LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+static LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+static DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+static DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// Order in which the code length code lengths are stored in a dynamic block header
+static CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+fn corrupt(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+pub fn adler32(bytes: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    // The largest number of bytes that can be summed before b overflows
+    const BLOCK: usize = 5552;
+
+    let mut a = 1u32;
+    let mut b = 0u32;
+    for block in bytes.chunks(BLOCK) {
+        for &byte in block {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+// Why decoding stopped before the end of the stream
+enum Stop {
+    // The rest of the current step has not been fed yet
+    NeedInput,
+    Corrupt(Error),
+}
+
+impl From<Error> for Stop {
+    fn from(error: Error) -> Stop {
+        Stop::Corrupt(error)
+    }
+}
+
+type Step<T> = std::result::Result<T, Stop>;
+
+// A canonical Huffman code, as described by the code length of each symbol
+struct Huffman {
+    // Number of codes of each length
+    counts: [u16; MAX_BITS + 1],
+    // Symbols ordered by their code
+    symbols: Vec<u16>,
+    // Indexed by the next FAST_BITS bits of input, holds the code length in the top bits and the
+    // symbol in the low 9. 0 if the code is longer than FAST_BITS
+    fast: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Huffman> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        // Check that no length has more codes than are left, incomplete codes are allowed
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = (left << 1) - count as i32;
+            if left < 0 {
+                return Err(corrupt(
+                    "Huffman code lengths are over-subscribed".to_owned(),
+                ));
+            }
+        }
+
+        let mut offsets = [0u16; MAX_BITS + 1];
+        for length in 1..MAX_BITS {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+
+        // Codes are assigned in increasing order, starting from the shortest codes, but are
+        // stored starting from their most significant bit, so they are reversed for the lookup
+        let mut fast = vec![0u16; 1 << FAST_BITS];
+        let mut code = 0u32;
+        let mut index = 0;
+        for (length, &count) in counts.iter().enumerate().take(FAST_BITS + 1).skip(1) {
+            for _ in 0..count {
+                let reversed = code.reverse_bits() >> (32 - length);
+                let entry = ((length as u16) << 9) | symbols[index];
+                let mut i = reversed as usize;
+                while i < fast.len() {
+                    fast[i] = entry;
+                    i += 1 << length;
+                }
+                code += 1;
+                index += 1;
+            }
+            code <<= 1;
+        }
+
+        Ok(Huffman {
+            counts,
+            symbols,
+            fast,
+        })
+    }
+
+    fn fixed() -> (Huffman, Huffman) {
+        let mut lengths = [0u8; 288];
+        lengths[0..144].fill(8);
+        lengths[144..256].fill(9);
+        lengths[256..280].fill(7);
+        lengths[280..288].fill(8);
+
+        // Both are complete codes, so they cannot fail
+        (
+            Huffman::new(&lengths).unwrap(),
+            Huffman::new(&[5; 30]).unwrap(),
+        )
+    }
+}
+
+enum State {
+    ZlibHeader,
+    BlockHeader,
+    Stored(usize),
+    Compressed(Box<(Huffman, Huffman)>),
+    Checksum,
+    Done,
+}
+
+pub struct Inflater {
+    input: Vec<u8>,
+    // In bits, from the start of input
+    position: usize,
+    output: Vec<u8>,
+    state: State,
+    last_block: bool,
+    // Raw DEFLATE data has no checksum at the end
+    zlib: bool,
+    // Maximum number of bytes the stream may decompress to
+    limit: Option<usize>,
+}
+
+impl Default for Inflater {
+    fn default() -> Inflater {
+        Inflater::new()
+    }
+}
+
+impl Inflater {
+    // Expects a zlib stream
+    pub fn new() -> Inflater {
+        Inflater {
+            input: Vec::new(),
+            position: 0,
+            output: Vec::new(),
+            state: State::ZlibHeader,
+            last_block: false,
+            zlib: true,
+            limit: None,
+        }
+    }
+
+    // Expects raw DEFLATE data, without the zlib header and checksum
+    pub fn raw() -> Inflater {
+        Inflater {
+            state: State::BlockHeader,
+            zlib: false,
+            ..Inflater::new()
+        }
+    }
+
+    // Fails instead of decompressing more than limit bytes
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = Some(limit);
+    }
+
+    // Decompresses as much as possible. What has been decompressed is kept in the output even
+    // if the stream turns out to be corrupt
+    pub fn feed(&mut self, input: &[u8]) -> Result<()> {
+        if let State::Done = self.state {
+            return Ok(());
+        }
+
+        self.input.extend_from_slice(input);
+        let result = self.run();
+
+        let consumed = self.position / 8;
+        if consumed >= COMPACT_SIZE {
+            self.input.drain(..consumed);
+            self.position -= consumed * 8;
+        }
+
+        result
+    }
+
+    // Whether the end of the stream has been reached
+    pub fn is_done(&self) -> bool {
+        matches!(self.state, State::Done)
+    }
+
+    // Fails if the stream did not end
+    pub fn finish(&self) -> Result<()> {
+        if self.is_done() {
+            return Ok(());
+        }
+        Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            "Compressed data ended before the end of the stream",
+        ))
+    }
+
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    pub fn into_output(self) -> Vec<u8> {
+        self.output
+    }
+
+    fn run(&mut self) -> Result<()> {
+        loop {
+            let checkpoint = self.position;
+            match self.step() {
+                Ok(true) => return Ok(()),
+                Ok(false) => {}
+                Err(Stop::NeedInput) => {
+                    // Start the step again once more input is fed
+                    self.position = checkpoint;
+                    return Ok(());
+                }
+                Err(Stop::Corrupt(e)) => return Err(e),
+            }
+        }
+    }
+
+    // Does one indivisible piece of work, returning true once the stream is done
+    fn step(&mut self) -> Step<bool> {
+        match &self.state {
+            State::ZlibHeader => {
+                let cmf = self.bits(8)?;
+                let flg = self.bits(8)?;
+                if cmf & 0x0F != 8 || cmf >> 4 > 7 {
+                    return Err(corrupt("zlib stream does not use DEFLATE".to_owned()).into());
+                }
+                if ((cmf << 8) | flg) % 31 != 0 {
+                    return Err(corrupt("zlib header check bits are wrong".to_owned()).into());
+                }
+                if flg & 0x20 != 0 {
+                    return Err(
+                        corrupt("zlib preset dictionaries are unsupported".to_owned()).into(),
+                    );
+                }
+                self.state = State::BlockHeader;
+            }
+            State::BlockHeader => {
+                if self.last_block {
+                    self.state = if self.zlib {
+                        State::Checksum
+                    } else {
+                        State::Done
+                    };
+                    return Ok(false);
+                }
+                let last = self.bits(1)? == 1;
+                self.state = match self.bits(2)? {
+                    0 => {
+                        self.align();
+                        let length = self.bits(16)?;
+                        let inverse = self.bits(16)?;
+                        if length != !inverse & 0xFFFF {
+                            return Err(corrupt(format!(
+                                "Stored block length is corrupt at output byte {}",
+                                self.output.len()
+                            ))
+                            .into());
+                        }
+                        State::Stored(length as usize)
+                    }
+                    1 => State::Compressed(Box::new(Huffman::fixed())),
+                    2 => State::Compressed(Box::new(self.dynamic_codes()?)),
+                    _ => {
+                        return Err(corrupt(format!(
+                            "Invalid block type at output byte {}",
+                            self.output.len()
+                        ))
+                        .into())
+                    }
+                };
+                self.last_block = last;
+            }
+            State::Stored(remaining) => {
+                let remaining = *remaining;
+                let start = self.position / 8;
+                let available = (self.input.len() - start).min(remaining);
+                self.reserve(available)?;
+                self.output
+                    .extend_from_slice(&self.input[start..start + available]);
+                self.position += available * 8;
+
+                if available == remaining {
+                    self.state = State::BlockHeader;
+                } else if available == 0 {
+                    return Err(Stop::NeedInput);
+                } else {
+                    // Keep what was copied, the rest is waited for on the next step
+                    self.state = State::Stored(remaining - available);
+                }
+            }
+            State::Compressed(_) => {
+                if self.codes()? {
+                    self.state = State::BlockHeader;
+                }
+            }
+            State::Checksum => {
+                self.align();
+                let mut expected = 0;
+                for _ in 0..4 {
+                    expected = (expected << 8) | self.bits(8)?;
+                }
+                let actual = adler32(&self.output);
+                if expected != actual {
+                    return Err(corrupt(format!(
+                        "Adler-32 checksum mismatch, expected {:08x} but got {:08x}",
+                        expected, actual
+                    ))
+                    .into());
+                }
+                self.state = State::Done;
+            }
+            State::Done => return Ok(true),
+        }
+        Ok(false)
+    }
+
+    // Decodes symbols until the end of the block, returning true if it was reached. If the input
+    // runs out part way through a symbol, the symbols before it are kept
+    fn codes(&mut self) -> Step<bool> {
+        let codes = match std::mem::replace(&mut self.state, State::BlockHeader) {
+            State::Compressed(codes) => codes,
+            _ => unreachable!(),
+        };
+
+        let start = self.position;
+        let result = loop {
+            let checkpoint = self.position;
+            match self.symbol(&codes.0, &codes.1) {
+                Ok(false) => {}
+                Err(Stop::NeedInput) if checkpoint != start => {
+                    self.position = checkpoint;
+                    break Ok(false);
+                }
+                result => break result,
+            }
+        };
+
+        self.state = State::Compressed(codes);
+        result
+    }
+
+    // Decodes a literal or a length and distance pair, returning true at the end of the block
+    fn symbol(&mut self, literals: &Huffman, distances: &Huffman) -> Step<bool> {
+        let symbol = self.decode(literals)? as usize;
+        if symbol < 256 {
+            self.reserve(1)?;
+            self.output.push(symbol as u8);
+            return Ok(false);
+        }
+        if symbol == 256 {
+            return Ok(true);
+        }
+
+        let symbol = symbol - 257;
+        if symbol >= LENGTH_BASE.len() {
+            return Err(corrupt(format!(
+                "Invalid length code at output byte {}",
+                self.output.len()
+            ))
+            .into());
+        }
+        let length =
+            LENGTH_BASE[symbol] as usize + self.bits(LENGTH_EXTRA[symbol] as usize)? as usize;
+
+        let symbol = self.decode(distances)? as usize;
+        if symbol >= DISTANCE_BASE.len() {
+            return Err(corrupt(format!(
+                "Invalid distance code at output byte {}",
+                self.output.len()
+            ))
+            .into());
+        }
+        let distance =
+            DISTANCE_BASE[symbol] as usize + self.bits(DISTANCE_EXTRA[symbol] as usize)? as usize;
+        if distance > self.output.len() {
+            return Err(corrupt(format!(
+                "Distance {} is too far back at output byte {}",
+                distance,
+                self.output.len()
+            ))
+            .into());
+        }
+
+        self.reserve(length)?;
+        let start = self.output.len() - distance;
+        if distance >= length {
+            self.output.extend_from_within(start..start + length);
+        } else {
+            // The copy overlaps what it is writing, repeating the last distance bytes. Every
+            // copy doubles how much can be copied at once
+            let end = self.output.len() + length;
+            while self.output.len() < end {
+                let count = (self.output.len() - start).min(end - self.output.len());
+                self.output.extend_from_within(start..start + count);
+            }
+        }
+        Ok(false)
+    }
+
+    fn dynamic_codes(&mut self) -> Step<(Huffman, Huffman)> {
+        let literal_count = self.bits(5)? as usize + 257;
+        let distance_count = self.bits(5)? as usize + 1;
+        let code_length_count = self.bits(4)? as usize + 4;
+        if literal_count > 286 || distance_count > 30 {
+            return Err(corrupt("Dynamic block has too many codes".to_owned()).into());
+        }
+
+        let mut code_lengths = [0u8; 19];
+        for &symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+            code_lengths[symbol] = self.bits(3)? as u8;
+        }
+        let code_length_code = Huffman::new(&code_lengths)?;
+
+        let mut lengths = vec![0u8; literal_count + distance_count];
+        let mut i = 0;
+        while i < lengths.len() {
+            let symbol = self.decode(&code_length_code)?;
+            let (value, repeat) = match symbol {
+                0..=15 => (symbol as u8, 1),
+                16 => {
+                    if i == 0 {
+                        return Err(corrupt(
+                            "Repeated code length has no previous length".to_owned(),
+                        )
+                        .into());
+                    }
+                    (lengths[i - 1], 3 + self.bits(2)? as usize)
+                }
+                17 => (0, 3 + self.bits(3)? as usize),
+                _ => (0, 11 + self.bits(7)? as usize),
+            };
+            if i + repeat > lengths.len() {
+                return Err(
+                    corrupt("Code lengths overflow the dynamic block header".to_owned()).into(),
+                );
+            }
+            lengths[i..i + repeat].fill(value);
+            i += repeat;
+        }
+
+        if lengths[256] == 0 {
+            return Err(corrupt("Dynamic block has no end of block code".to_owned()).into());
+        }
+
+        Ok((
+            Huffman::new(&lengths[..literal_count])?,
+            Huffman::new(&lengths[literal_count..])?,
+        ))
+    }
+
+    fn reserve(&self, length: usize) -> Step<()> {
+        match self.limit {
+            Some(limit) if self.output.len() + length > limit => Err(corrupt(format!(
+                "Decompressed data is larger than the limit of {} bytes",
+                limit
+            ))
+            .into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn available(&self) -> usize {
+        self.input.len() * 8 - self.position
+    }
+
+    // Up to 25 bits, without consuming them. Bits past the end of the input are 0
+    fn peek(&self, count: usize) -> u32 {
+        let byte = self.position / 8;
+        let value = match self.input.get(byte..byte + 4) {
+            Some(bytes) => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            None => {
+                let mut value = 0u32;
+                for (i, &b) in self.input[byte.min(self.input.len())..].iter().enumerate() {
+                    value |= (b as u32) << (i * 8);
+                }
+                value
+            }
+        };
+        (value >> (self.position % 8)) & ((1u32 << count) - 1)
+    }
+
+    // Bits are packed starting from the least significant bit of each byte
+    fn bits(&mut self, count: usize) -> Step<u32> {
+        if count > self.available() {
+            return Err(Stop::NeedInput);
+        }
+        let value = self.peek(count);
+        self.position += count;
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        self.position = self.position.div_ceil(8) * 8;
+    }
+
+    fn decode(&mut self, huffman: &Huffman) -> Step<u16> {
+        let entry = huffman.fast[self.peek(FAST_BITS) as usize];
+        if entry != 0 {
+            let length = (entry >> 9) as usize;
+            if length > self.available() {
+                return Err(Stop::NeedInput);
+            }
+            self.position += length;
+            return Ok(entry & 0x1FF);
+        }
+
+        // Longer codes are decoded a bit at a time
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+        for length in 1..=MAX_BITS {
+            code |= self.bits(1)? as i32;
+            let count = huffman.counts[length] as i32;
+            if code - count < first {
+                return Ok(huffman.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+
+        Err(corrupt(format!(
+            "Invalid Huffman code at output byte {}",
+            self.output.len()
+        ))
+        .into())
+    }
+}
+
+// Decompresses a whole zlib stream
+pub fn decompress(bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut inflater = Inflater::new();
+    inflater.feed(bytes)?;
+    inflater.finish()?;
+    Ok(inflater.into_output())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libflate::lz77::DefaultLz77Encoder;
+    use libflate::zlib::{EncodeOptions, Encoder};
+    use std::io::Write;
+
+    // Text with enough repeats to be worth compressing, and enough distinct bytes to need codes
+    // of several lengths
+    fn sample(len: usize) -> Vec<u8> {
+        let text = b"The quick brown fox jumps over the lazy dog, 0123456789 times. ";
+        (0..len)
+            .map(|i| text[(i * 7 + i / text.len()) % text.len()])
+            .collect()
+    }
+
+    // Compresses with the PNG encoder's library, checking that the first block is of the type
+    // the test means to cover
+    fn compress(
+        data: &[u8],
+        options: EncodeOptions<DefaultLz77Encoder>,
+        block_type: u8,
+    ) -> Vec<u8> {
+        let mut encoder = Encoder::with_options(Vec::new(), options).unwrap();
+        encoder.write_all(data).unwrap();
+        let bytes = encoder.finish().into_result().unwrap();
+        assert_eq!((bytes[2] >> 1) & 3, block_type);
+        bytes
+    }
+
+    #[test]
+    fn stored() {
+        // More than fits in one stored block
+        let data = sample(70_000);
+        let bytes = compress(&data, EncodeOptions::new().no_compression(), 0);
+        assert_eq!(decompress(&bytes).unwrap(), data);
+    }
+
+    #[test]
+    fn fixed() {
+        let data = sample(10_000);
+        let bytes = compress(&data, EncodeOptions::new().fixed_huffman_codes(), 1);
+        assert_eq!(decompress(&bytes).unwrap(), data);
+    }
+
+    #[test]
+    fn dynamic() {
+        let data = sample(100_000);
+        let bytes = compress(&data, EncodeOptions::new(), 2);
+        assert_eq!(decompress(&bytes).unwrap(), data);
+    }
+
+    #[test]
+    fn byte_at_a_time() {
+        let data = sample(20_000);
+        for options in [
+            EncodeOptions::new().no_compression(),
+            EncodeOptions::new().fixed_huffman_codes(),
+            EncodeOptions::new(),
+        ] {
+            let mut encoder = Encoder::with_options(Vec::new(), options).unwrap();
+            encoder.write_all(&data).unwrap();
+            let bytes = encoder.finish().into_result().unwrap();
+
+            let mut inflater = Inflater::new();
+            for byte in &bytes {
+                assert!(!inflater.is_done());
+                inflater.feed(std::slice::from_ref(byte)).unwrap();
+            }
+            inflater.finish().unwrap();
+            assert_eq!(inflater.output(), &data[..]);
+        }
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let data = sample(1000);
+        let mut bytes = compress(&data, EncodeOptions::new(), 2);
+        *bytes.last_mut().unwrap() ^= 1;
+
+        let mut inflater = Inflater::new();
+        assert_eq!(
+            inflater.feed(&bytes).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        // Everything before the checksum is kept
+        assert_eq!(inflater.output(), &data[..]);
+    }
+
+    #[test]
+    fn limit() {
+        let data = sample(10_000);
+        let bytes = compress(&data, EncodeOptions::new(), 2);
+
+        let mut inflater = Inflater::new();
+        inflater.set_limit(data.len() - 1);
+        assert!(inflater.feed(&bytes).is_err());
+
+        let mut inflater = Inflater::new();
+        inflater.set_limit(data.len());
+        inflater.feed(&bytes).unwrap();
+        inflater.finish().unwrap();
+    }
+
+    #[test]
+    fn checksum() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+    }
+}
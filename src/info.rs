@@ -0,0 +1,505 @@
+use crate::common::*;
+use crate::png::chunks::ancillary;
+use std::fmt::Write;
+
+fn chunk_name(chunk_type: &[u8; 4]) -> String {
+    String::from_utf8_lossy(chunk_type).into_owned()
+}
+
+fn interlace_name(metadata: &Metadata) -> &'static str {
+    if metadata.interlaced() {
+        "Adam7"
+    } else {
+        "none"
+    }
+}
+
+// Human readable report, as printed by `viu-rs info`
+pub fn report(metadata: &Metadata, frames: usize) -> String {
+    let mut out = String::new();
+
+    // Writing to a String cannot fail
+    let _ = writeln!(out, "Format:      {}", metadata.format());
+    let _ = writeln!(
+        out,
+        "Dimensions:  {}x{}",
+        metadata.width(),
+        metadata.height()
+    );
+    let _ = writeln!(out, "Color type:  {:?}", metadata.color_type());
+    let _ = writeln!(out, "Bit depth:   {}", metadata.bit_depth());
+    let _ = writeln!(out, "Interlace:   {}", interlace_name(metadata));
+    if frames > 1 {
+        let _ = writeln!(out, "Frames:      {}", frames);
+    }
+    if let Some(palette) = metadata.palette() {
+        let _ = writeln!(out, "Palette:     {} colors", palette.len());
+    }
+    match metadata.explicit_bkgd() {
+        Some((r, g, b)) => {
+            let _ = writeln!(out, "Background:  ({}, {}, {})", r, g, b);
+        }
+        None => {
+            let _ = writeln!(out, "Background:  none");
+        }
+    }
+    if let Some(time) = metadata.time() {
+        let _ = writeln!(out, "Modified:    {}", time.iso8601());
+    }
+    if let Some(physical) = metadata.physical() {
+        match physical.dpi() {
+            Some((x_dpi, y_dpi)) => {
+                let _ = writeln!(
+                    out,
+                    "Resolution:  {}x{} pixels per metre ({:.0}x{:.0} DPI)",
+                    physical.x, physical.y, x_dpi, y_dpi
+                );
+                let _ = writeln!(
+                    out,
+                    "Print size:  {:.1}x{:.1} mm",
+                    metadata.width() as f32 * 1000.0 / physical.x as f32,
+                    metadata.height() as f32 * 1000.0 / physical.y as f32
+                );
+            }
+            None => {
+                let _ = writeln!(out, "Pixel ratio: {}:{}", physical.y, physical.x);
+            }
+        }
+    }
+    if let Some(sbit) = metadata.significant_bits() {
+        let _ = writeln!(out, "Significant: {} bits", join(&sbit.significant));
+    }
+
+    if let (Some(histogram), Some(palette)) = (metadata.histogram(), metadata.palette()) {
+        let _ = writeln!(out, "\nPalette histogram:");
+        let mut entries: Vec<(usize, &u16)> = histogram
+            .frequencies
+            .iter()
+            .enumerate()
+            .filter(|(_, &frequency)| frequency > 0)
+            .collect();
+        entries.sort_by(|a, b| b.1.cmp(a.1));
+        for (index, frequency) in entries {
+            let (r, g, b) = palette[index];
+            let _ = writeln!(
+                out,
+                "    {:>3}  ({:>3}, {:>3}, {:>3}){:>8}",
+                index, r, g, b, frequency
+            );
+        }
+    }
+
+    if !metadata.suggested_palettes().is_empty() {
+        let _ = writeln!(out, "\nSuggested palettes:");
+        for palette in metadata.suggested_palettes() {
+            let _ = writeln!(
+                out,
+                "    {}: {} colors, {} bit samples",
+                palette.name,
+                palette.entries.len(),
+                palette.depth
+            );
+        }
+    }
+
+    if let Some(exif) = metadata.exif() {
+        let _ = writeln!(out, "\nEXIF:");
+        if let Some(orientation) = exif.orientation {
+            let _ = writeln!(
+                out,
+                "    Orientation: {} ({})",
+                orientation,
+                orientation_name(orientation)
+            );
+        }
+        let camera: Vec<&str> = [&exif.make, &exif.model]
+            .iter()
+            .filter_map(|value| value.as_deref())
+            .collect();
+        if !camera.is_empty() {
+            let _ = writeln!(out, "    Camera:      {}", camera.join(" "));
+        }
+        if let Some(date_time) = &exif.date_time {
+            let _ = writeln!(out, "    Date:        {}", date_time);
+        }
+        if let Some(gps) = &exif.gps {
+            let _ = write!(
+                out,
+                "    GPS:         {:.6}, {:.6}",
+                gps.latitude, gps.longitude
+            );
+            if let Some(altitude) = gps.altitude {
+                let _ = write!(out, ", {:.1}m", altitude);
+            }
+            let _ = writeln!(out);
+        }
+    }
+
+    if !metadata.text().is_empty() {
+        let _ = writeln!(out, "\nText:");
+        for text in metadata.text() {
+            let _ = write!(out, "    {}", text.key);
+            if let Some(language) = text.language.as_ref().filter(|l| !l.is_empty()) {
+                let _ = write!(out, " [{}]", language);
+            }
+            if let Some(translated_key) = text.translated_key.as_ref().filter(|k| !k.is_empty()) {
+                let _ = write!(out, " ({})", translated_key);
+            }
+            let _ = writeln!(out, ": {}", text.text);
+        }
+    }
+
+    if !metadata.chunks().is_empty() {
+        let _ = writeln!(out, "\nChunks:");
+        let _ = writeln!(out, "    Type      Offset    Length  CRC       Status");
+        for chunk in metadata.chunks() {
+            let _ = writeln!(
+                out,
+                "    {:<6}{:>10}{:>10}  {:08x}  {}",
+                chunk_name(&chunk.chunk_type),
+                chunk.offset,
+                chunk.length,
+                chunk.crc,
+                match chunk.crc_valid {
+                    Some(true) => "ok",
+                    Some(false) => "MISMATCH",
+                    None => "unchecked",
+                }
+            );
+        }
+    }
+
+    out
+}
+
+// The palette entries that are used, most used first, each with a bar as long as its share of
+// the pixels. Bars are drawn in the entry's color, or with # when colors are turned off
+pub fn palette_histogram(
+    palette: &[RGBColor],
+    frequencies: &[u32],
+    width: usize,
+    color: bool,
+) -> String {
+    let mut out = String::new();
+    let mut entries: Vec<(usize, u32)> = frequencies
+        .iter()
+        .copied()
+        .enumerate()
+        .take(palette.len())
+        .filter(|&(_, frequency)| frequency > 0)
+        .collect();
+    entries.sort_by_key(|&(_, frequency)| std::cmp::Reverse(frequency));
+
+    let most = entries.first().map_or(1, |&(_, frequency)| frequency) as u64;
+    // Room is left for the index, color and frequency around the bar
+    let longest = width.saturating_sub(36).max(10) as u64;
+    for (index, frequency) in entries {
+        let (r, g, b) = palette[index];
+        let length = ((frequency as u64 * longest).div_ceil(most)) as usize;
+        let bar = if color {
+            format!("\x1B[38;2;{};{};{}m{}\x1B[0m", r, g, b, "█".repeat(length))
+        } else {
+            "#".repeat(length)
+        };
+        let _ = writeln!(
+            out,
+            "{:>3}  ({:>3}, {:>3}, {:>3})  {}{} {}",
+            index,
+            r,
+            g,
+            b,
+            bar,
+            " ".repeat(longest as usize - length),
+            frequency
+        );
+    }
+    out
+}
+
+fn join<T: ToString>(values: &[T]) -> String {
+    values
+        .iter()
+        .map(|value| value.to_string())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn orientation_name(orientation: u16) -> &'static str {
+    match orientation {
+        1 => "normal",
+        2 => "mirrored horizontally",
+        3 => "rotated 180 degrees",
+        4 => "mirrored vertically",
+        5 => "mirrored horizontally, rotated 90 degrees counter-clockwise",
+        6 => "rotated 90 degrees clockwise",
+        7 => "mirrored horizontally, rotated 90 degrees clockwise",
+        _ => "rotated 90 degrees counter-clockwise",
+    }
+}
+
+fn escape_json(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// Same information as the report, for scripts. Missing values are null
+pub fn json(metadata: &Metadata, frames: usize) -> String {
+    let mut out = String::from("{\n");
+
+    let _ = writeln!(out, "  \"format\": {},", escape_json(metadata.format()));
+    let _ = writeln!(out, "  \"width\": {},", metadata.width());
+    let _ = writeln!(out, "  \"height\": {},", metadata.height());
+    let _ = writeln!(
+        out,
+        "  \"color_type\": {},",
+        escape_json(&format!("{:?}", metadata.color_type()))
+    );
+    let _ = writeln!(out, "  \"bit_depth\": {},", metadata.bit_depth());
+    let _ = writeln!(
+        out,
+        "  \"interlace\": {},",
+        escape_json(interlace_name(metadata))
+    );
+    let _ = writeln!(out, "  \"frames\": {},", frames);
+    let _ = writeln!(
+        out,
+        "  \"palette_size\": {},",
+        match metadata.palette() {
+            Some(palette) => palette.len().to_string(),
+            None => "null".to_owned(),
+        }
+    );
+    let _ = writeln!(
+        out,
+        "  \"background\": {},",
+        match metadata.explicit_bkgd() {
+            Some((r, g, b)) => format!("[{}, {}, {}]", r, g, b),
+            None => "null".to_owned(),
+        }
+    );
+    let _ = writeln!(
+        out,
+        "  \"modified\": {},",
+        match metadata.time() {
+            Some(time) => escape_json(&time.iso8601()),
+            None => "null".to_owned(),
+        }
+    );
+
+    let physical = match metadata.physical() {
+        Some(physical) => format!(
+            "{{ \"x\": {}, \"y\": {}, \"unit\": {}, \"dpi\": {} }}",
+            physical.x,
+            physical.y,
+            escape_json(match physical.unit {
+                ancillary::PhysicalUnit::Metre => "metre",
+                ancillary::PhysicalUnit::Unknown => "unknown",
+            }),
+            match physical.dpi() {
+                Some((x_dpi, y_dpi)) => format!("[{}, {}]", x_dpi, y_dpi),
+                None => "null".to_owned(),
+            }
+        ),
+        None => "null".to_owned(),
+    };
+    let _ = writeln!(out, "  \"physical\": {},", physical);
+
+    let _ = writeln!(
+        out,
+        "  \"significant_bits\": {},",
+        match metadata.significant_bits() {
+            Some(sbit) => format!("[{}]", join(&sbit.significant)),
+            None => "null".to_owned(),
+        }
+    );
+    let _ = writeln!(
+        out,
+        "  \"histogram\": {},",
+        match metadata.histogram() {
+            Some(histogram) => format!("[{}]", join(&histogram.frequencies)),
+            None => "null".to_owned(),
+        }
+    );
+
+    let suggested_palettes: Vec<String> = metadata
+        .suggested_palettes()
+        .iter()
+        .map(|palette| {
+            let entries: Vec<String> = palette
+                .entries
+                .iter()
+                .map(|entry| {
+                    format!(
+                        "[{}, {}, {}, {}, {}]",
+                        entry.red, entry.green, entry.blue, entry.alpha, entry.frequency
+                    )
+                })
+                .collect();
+            format!(
+                "    {{ \"name\": {}, \"depth\": {}, \"entries\": [{}] }}",
+                escape_json(&palette.name),
+                palette.depth,
+                entries.join(", ")
+            )
+        })
+        .collect();
+    if suggested_palettes.is_empty() {
+        let _ = writeln!(out, "  \"suggested_palettes\": [],");
+    } else {
+        let _ = writeln!(
+            out,
+            "  \"suggested_palettes\": [\n{}\n  ],",
+            suggested_palettes.join(",\n")
+        );
+    }
+
+    let optional = |value: &Option<String>| match value {
+        Some(value) => escape_json(value),
+        None => "null".to_owned(),
+    };
+
+    let exif = match metadata.exif() {
+        Some(exif) => format!(
+            "{{ \"orientation\": {}, \"make\": {}, \"model\": {}, \"date_time\": {}, \"gps\": {} }}",
+            match exif.orientation {
+                Some(orientation) => orientation.to_string(),
+                None => "null".to_owned(),
+            },
+            optional(&exif.make),
+            optional(&exif.model),
+            optional(&exif.date_time),
+            match &exif.gps {
+                Some(gps) => format!(
+                    "{{ \"latitude\": {}, \"longitude\": {}, \"altitude\": {} }}",
+                    gps.latitude,
+                    gps.longitude,
+                    match gps.altitude {
+                        Some(altitude) => altitude.to_string(),
+                        None => "null".to_owned(),
+                    }
+                ),
+                None => "null".to_owned(),
+            }
+        ),
+        None => "null".to_owned(),
+    };
+    let _ = writeln!(out, "  \"exif\": {},", exif);
+
+    let text: Vec<String> = metadata
+        .text()
+        .iter()
+        .map(|text| {
+            format!(
+                "    {{ \"keyword\": {}, \"text\": {}, \"language\": {}, \"translated_keyword\": {}, \"compressed\": {} }}",
+                escape_json(&text.key),
+                escape_json(&text.text),
+                optional(&text.language),
+                optional(&text.translated_key),
+                text.compressed
+            )
+        })
+        .collect();
+    if text.is_empty() {
+        let _ = writeln!(out, "  \"text\": [],");
+    } else {
+        let _ = writeln!(out, "  \"text\": [\n{}\n  ],", text.join(",\n"));
+    }
+
+    let chunks: Vec<String> = metadata
+        .chunks()
+        .iter()
+        .map(|chunk| {
+            format!(
+                "    {{ \"type\": {}, \"offset\": {}, \"length\": {}, \"crc\": \"{:08x}\", \"crc_valid\": {} }}",
+                escape_json(&chunk_name(&chunk.chunk_type)),
+                chunk.offset,
+                chunk.length,
+                chunk.crc,
+                match chunk.crc_valid {
+                    Some(valid) => valid.to_string(),
+                    None => "null".to_owned(),
+                }
+            )
+        })
+        .collect();
+    if chunks.is_empty() {
+        let _ = writeln!(out, "  \"chunks\": []");
+    } else {
+        let _ = writeln!(out, "  \"chunks\": [\n{}\n  ]", chunks.join(",\n"));
+    }
+
+    out.push('}');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::{self, chunks::chunk_types, ChunkWriter};
+
+    // A 2x1 gray image with the given bKGD and tRNS values, and samples 0 and trns
+    fn gray_png(bit_depth: u8, bkgd: u16, trns: u16) -> Vec<u8> {
+        let mut writer = ChunkWriter::new();
+        writer.write(&chunk_types::IHDR, &png::ihdr_data(2, 1, bit_depth, 0));
+        writer.write(&chunk_types::bKGD, &bkgd.to_be_bytes());
+        writer.write(&chunk_types::tRNS, &trns.to_be_bytes());
+        let row = match bit_depth {
+            16 => [&[0, 0, 0][..], &trns.to_be_bytes()].concat(),
+            8 => vec![0, 0, trns as u8],
+            depth => vec![0, (trns as u8) << (8 - 2 * depth)],
+        };
+        writer.write(&chunk_types::IDAT, &png::compress(&row).unwrap());
+        writer.write(&chunk_types::IEND, &[]);
+        writer.finish()
+    }
+
+    fn inspect(buffer: &[u8]) -> Metadata {
+        let mut metadata = Metadata::new();
+        png::inspect(buffer, &mut metadata, &png::ParseOptions::default()).unwrap();
+        metadata
+    }
+
+    #[test]
+    fn gray_bkgd() {
+        for (bit_depth, bkgd, expected) in [(8, 200, 200), (4, 10, 170), (2, 1, 85), (1, 1, 255)] {
+            let metadata = inspect(&gray_png(bit_depth, bkgd, 0));
+            assert!(report(&metadata, 1).contains(&format!(
+                "Background:  ({}, {}, {})",
+                expected, expected, expected
+            )));
+        }
+        let metadata = inspect(&gray_png(16, 0xC812, 0));
+        assert!(report(&metadata, 1).contains("Background:  (200, 200, 200)"));
+    }
+
+    // The transparent sample has to be scaled like the image data for the pixel to match it
+    #[test]
+    fn gray_trns() {
+        for (bit_depth, trns, expected) in [(8, 200, 200), (4, 10, 170), (2, 1, 85), (1, 1, 255)] {
+            let buffer = gray_png(bit_depth, 0, trns);
+            assert!(matches!(
+                inspect(&buffer).alpha(),
+                Some(AlphaValue::Gray(alpha)) if *alpha == expected
+            ));
+
+            let mut metadata = Metadata::new();
+            let image = png::parse(buffer, &mut metadata).unwrap();
+            assert_eq!(image.row(0), &[(1, 1, 1), (0, 0, 0)]);
+        }
+    }
+}
@@ -0,0 +1,16 @@
+pub mod common;
+pub mod crc;
+pub mod effects;
+pub mod exif;
+pub mod ico;
+pub mod image_buffer;
+pub mod inflate;
+pub mod info;
+pub mod png;
+pub mod render;
+pub mod resample;
+pub mod sizing;
+pub mod terminal;
+pub mod tga;
+pub mod tiff;
+pub mod webp;
@@ -2,36 +2,606 @@ use std::env;
 use std::fs;
 use std::io;
 use std::io::prelude::*;
-use std::io::{Error, ErrorKind};
+use std::io::{Error, ErrorKind, IsTerminal};
+use std::process;
+use std::thread;
+use std::time::Duration;
 
-mod common;
-mod crc;
-mod display_image;
-mod png;
+use viu_rs::common::*;
+use viu_rs::effects::{
+    AutoLevels, Blur, BlurMethod, Canny, Clahe, ColorAdjustment, Convolution, EdgeDetect, EdgeMode,
+    EdgeOperator, Equalize, Grayscale, Kernel, Luma, Pipeline, Sharpen, UnsharpMask,
+};
+use viu_rs::render::{self, Colors, Renderer};
+use viu_rs::resample::Filter;
+use viu_rs::sizing::{FitMode, Length, SizingPolicy};
+use viu_rs::{exif, ico, info, png, terminal, tga, tiff, webp};
 
-use common::*;
-use display_image::display_image;
-
-const HELP_STR: &'static str = "Usage: viu-rs [<option>] <image path>\n
+const HELP_STR: &str =
+    "Usage: viu-rs [<option>] [--page <n>] [--lenient] [--no-crc] [--no-auto-orient] [--filter <name>] [<sizing options>] [<effects>] [--renderer <name>] [--output <path>] <image path>\n
+Supported Formats: PNG, WebP (lossless), TIFF, TGA, ICO, CUR\n
 Available Options:
     blur:
-        Apply a blur of given intensity to the image
+        Apply a Gaussian blur over the given number of pixels on each side, same as --blur with
+        a third of it as sigma
         Usage: viu-rs blur <intensity> <image path>
     ascii:
-        Display a grayscale ascii version
+        Display a grayscale ascii version, same as --renderer ascii
         Usage: viu-rs ascii <image path>
     grayscale:
-        Display a grayscale version of the image
+        Display a grayscale version of the image, same as --grayscale
         Usage: viu-rs grayscale <image path>
+    convert:
+        Saves the image as a png, with any effects applied
+        Usage: viu-rs convert [<effects>] <image path> <output path>
+    palette:
+        Shows how much each color of a palette image is used, from its png histogram if it has
+        one, or else by counting its pixels
+        Usage: viu-rs palette <image path>
+    info:
+        Prints the image's metadata, and for png files the chunk layout with the status of each
+        chunk's CRC, reading as much of damaged files as it can
+        Usage: viu-rs info [--json] <image path>
+    validate:
+        Checks that a png file follows the spec, listing any warnings and errors
+        Usage: viu-rs validate <image path>
     show:
         Shows the image.
         Usage: viu-rs show <image path>
     <no-option-given>:
         Shows the image.
-        Usage: viu-rs <image path>";
+        Usage: viu-rs <image path>
+    --page:
+        Selects the page of a multi-page TIFF, starting at 0
+        Usage: viu-rs --page <n> <image path>
+    --lenient:
+        Recovers what it can from a damaged png instead of failing, missing rows are magenta
+        Usage: viu-rs --lenient <image path>
+    --no-crc:
+        Skips verifying the png chunk checksums, which is faster for large files that are trusted
+        Usage: viu-rs --no-crc <image path>
+    --no-auto-orient:
+        Shows the image as stored, instead of rotating it as specified by its EXIF orientation
+        Usage: viu-rs --no-auto-orient <image path>
+    --filter:
+        Resampling filter used to fit the image to the terminal: nearest, box (default), bilinear,
+        mitchell, catmull-rom or lanczos3
+        Usage: viu-rs --filter <name> <image path>
+    --width, --height:
+        Size to show the image at, in terminal cells or as a percentage of the terminal like 50%.
+        Images smaller than the size are scaled up, unlike when fitting to the terminal
+        Usage: viu-rs --width <size> --height <size> <image path>
+    --fit, --fill, --stretch:
+        Whether the image fits inside the size keeping its aspect ratio (default), covers it and is
+        cropped, or is stretched to exactly the size
+        Usage: viu-rs --width <size> --height <size> --fill <image path>
+    --no-resize:
+        Shows one image pixel per pixel, even if the image doesn't fit in the terminal
+        Usage: viu-rs --no-resize <image path>
+    --center:
+        Centers the image horizontally in the terminal
+        Usage: viu-rs --center <image path>
+    --padding:
+        Leaves the given number of empty cells around the image
+        Usage: viu-rs --padding <n> <image path>
+    --cell-aspect:
+        Width divided by height of a terminal cell, like 0.5 or 8:16, for terminals that don't
+        report their size in pixels. Images are stretched to look right in cells of that shape
+        Usage: viu-rs --cell-aspect <ratio> <image path>
+    --output:
+        Writes the image as ANSI art to a file instead of showing it. Like when the output is
+        piped, COLUMNS and LINES are used as the size if set, and only the first frame of an
+        animation is written
+        Usage: viu-rs --output <path> <image path>
+    --default-width:
+        Columns used when the output isn't a terminal and COLUMNS isn't set, 80 by default
+        Usage: viu-rs --default-width <n> <image path>
+    --renderer:
+        How the image is drawn: blocks (default), ascii or braille
+        Usage: viu-rs --renderer <name> <image path>
+    --colors:
+        Colors used to draw the image: truecolor (default), or 256 for terminals without 24 bit
+        colors. With 256 colors, png images that suggest a palette are shown with its colors
+        Usage: viu-rs --colors 256 <image path>
+
+Effects are applied one after the other, in the order they are given:
+    --blur <sigma>:
+        Gaussian blur, with the standard deviation in pixels
+    --box-blur <sigma>:
+        Close to the Gaussian blur, but takes the same time however large sigma is
+    --grayscale:
+        Removes the colors of the image, keeping how bright each one looks
+    --brightness <factor>:
+        Multiplies every channel, so 1 leaves the image as it is and 0.5 makes it half as bright
+    --contrast <factor>:
+        Multiplies the distance of every channel from middle gray
+    --gamma <gamma>:
+        Gamma correction, where values above 1 bring out the dark parts of the image
+    --saturation <factor>:
+        Multiplies the saturation of every color, so 0 leaves only gray
+    --hue <degrees>:
+        Rotates every color around the color wheel
+    --invert:
+        Inverts the colors of the image
+    --sepia:
+        Brownish tones, like an old photograph
+    --sharpen:
+        Sharpens the image with a 3x3 kernel
+    --unsharp <radius>[,<amount>[,<threshold>]]:
+        Sharpens by adding amount (1 by default) times the difference from a blur of the given
+        radius, where it is more than threshold (0 by default)
+    --sobel, --prewitt, --laplacian:
+        Shows the edges of the image, found with the given operator
+    --canny:
+        Shows the edges of the image as thin lines, which makes ascii and braille easier to read
+    --kernel <weights or file> [--divisor <n>] [--bias <n>]:
+        Convolves the image with a kernel, given as rows separated by semicolons, or in a file
+        with a row on each line. Both sides must be odd. Each channel is divided by the divisor,
+        which is the sum of the weights by default, and then has the bias added
+        Usage: viu-rs --kernel \"1 2 1; 2 4 2; 1 2 1\" --divisor 16 <image path>
+    Usage: viu-rs --blur 2 --grayscale <image path>
+    Usage: viu-rs --contrast 1.2 --gamma 1.5 <image path>
+Contrast is stretched on the image at its full size, before it is resized and before any of the
+other effects, in the order given:
+    --auto-levels <percent>:
+        Stretches the brightness so the darkest pixels become black and the brightest white,
+        ignoring the given percentage of pixels at each end, which can be 0
+    --equalize:
+        Histogram equalization, which spreads the brightness out evenly
+    --clahe [--tiles <n>] [--clip-limit <n>]:
+        Equalizes tiles of the image on their own, which brings out detail in both dark and
+        light parts. There are 8 tiles across and down by default, and no level can have more
+        than clip limit (2 by default) times its share of the pixels
+    Usage: viu-rs --auto-levels 0.5 <image path>
+Options for the effects:
+    --luma <standard>:
+        Weights of red, green and blue in grayscale: 709 (default) for most images, or 601 as
+        used by SDTV and JPEG
+    --edges <mode>:
+        How pixels past the edges of the image are made up: clamp (default), mirror or wrap
+    --before-resize:
+        Applies the effects to the image at its full size, before it is resized to fit the
+        terminal, so sizes are in pixels of the image rather than of the terminal
+
+Setting NO_COLOR shows the image in ASCII instead of with colors";
+
+// Options that apply to decoding any of the subcommands' input
+struct DecodeOptions {
+    // Page of a multi-page TIFF
+    page: usize,
+    lenient: bool,
+    skip_crc: bool,
+    // Rotate and flip the image as specified by its EXIF orientation
+    auto_orient: bool,
+}
+
+// Removes the flag and the value after it from the arguments, returning the value
+fn take_value(args: &mut Vec<String>, flag: &str) -> io::Result<Option<String>> {
+    match args.iter().position(|arg| arg == flag) {
+        Some(i) if i + 1 < args.len() => {
+            let value = args.remove(i + 1);
+            args.remove(i);
+            Ok(Some(value))
+        }
+        Some(_) => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("A value must be given for {}", flag),
+        )),
+        None => Ok(None),
+    }
+}
+
+// Removes the flag from the arguments, returning whether it was there
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    match args.iter().position(|arg| arg == flag) {
+        Some(i) => {
+            args.remove(i);
+            true
+        }
+        None => false,
+    }
+}
+
+// How the image is drawn, and where to when it isn't shown in the terminal
+struct OutputOptions {
+    renderer: Renderer,
+    colors: Colors,
+    // File to write the ANSI art to, instead of stdout
+    path: Option<String>,
+    // Columns used when the output isn't a terminal and COLUMNS isn't set
+    default_width: usize,
+}
+
+fn parse_output(args: &mut Vec<String>) -> io::Result<OutputOptions> {
+    let mut output = OutputOptions {
+        renderer: match take_value(args, "--renderer")? {
+            Some(name) => Renderer::parse(&name)?,
+            None => Renderer::default(),
+        },
+        colors: match take_value(args, "--colors")? {
+            Some(name) => Colors::parse(&name)?,
+            None => Colors::default(),
+        },
+        path: take_value(args, "--output")?,
+        default_width: terminal::DEFAULT_WIDTH,
+    };
+    if let Some(width) = take_value(args, "--default-width")? {
+        output.default_width = match width.parse() {
+            Ok(width) if width > 0 => width,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "Invalid default width: {}, expected a number of cells",
+                        width
+                    ),
+                ))
+            }
+        };
+    }
+    Ok(output)
+}
+
+// Removes the flag at i and the value after it, returning the value
+fn take_value_at(args: &mut Vec<String>, i: usize) -> io::Result<String> {
+    if i + 1 < args.len() {
+        let value = args.remove(i + 1);
+        args.remove(i);
+        Ok(value)
+    } else {
+        Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("A value must be given for {}", args[i]),
+        ))
+    }
+}
+
+// The effects are kept in the order they were given in
+fn parse_effects(args: &mut Vec<String>, edges: EdgeMode, luma: Luma) -> io::Result<Pipeline> {
+    let mut pipeline = Pipeline::new();
+
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--blur" => {
+                let sigma = parse_sigma(&take_value_at(args, i)?)?;
+                pipeline.push(Blur::new(sigma, BlurMethod::Gaussian, edges));
+            }
+            "--box-blur" => {
+                let sigma = parse_sigma(&take_value_at(args, i)?)?;
+                pipeline.push(Blur::new(sigma, BlurMethod::Box, edges));
+            }
+            "--grayscale" => {
+                args.remove(i);
+                pipeline.push(Grayscale::new(luma));
+            }
+            "--brightness" | "--contrast" | "--gamma" | "--saturation" | "--hue" => {
+                let flag = args[i].clone();
+                let value = take_value_at(args, i)?;
+                pipeline.push(parse_adjustment(&flag, &value)?);
+            }
+            "--invert" => {
+                args.remove(i);
+                pipeline.push(ColorAdjustment::Invert);
+            }
+            "--sepia" => {
+                args.remove(i);
+                pipeline.push(ColorAdjustment::Sepia);
+            }
+            "--sharpen" => {
+                args.remove(i);
+                pipeline.push(Sharpen::new(edges));
+            }
+            "--unsharp" => {
+                let values = take_value_at(args, i)?;
+                let (radius, amount, threshold) = parse_unsharp(&values)?;
+                pipeline.push(UnsharpMask::new(radius, amount, threshold, edges));
+            }
+            "--sobel" | "--prewitt" | "--laplacian" => {
+                let operator = match args.remove(i).as_str() {
+                    "--sobel" => EdgeOperator::Sobel,
+                    "--prewitt" => EdgeOperator::Prewitt,
+                    _ => EdgeOperator::Laplacian,
+                };
+                pipeline.push(EdgeDetect::new(operator, edges));
+            }
+            "--canny" => {
+                args.remove(i);
+                pipeline.push(Canny::with_edges(edges));
+            }
+            "--kernel" => {
+                let kernel = parse_kernel(&take_value_at(args, i)?)?;
+
+                // The divisor and bias go right after the kernel they are for
+                let (mut divisor, mut bias) = (None, 0.0);
+                while let Some(flag) = args.get(i) {
+                    match flag.as_str() {
+                        "--divisor" => {
+                            divisor = Some(parse_kernel_number(&take_value_at(args, i)?)?);
+                        }
+                        "--bias" => bias = parse_kernel_number(&take_value_at(args, i)?)?,
+                        _ => break,
+                    }
+                }
+                if divisor == Some(0.0) {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        "Kernel divisor can't be 0",
+                    ));
+                }
+
+                pipeline.push(Convolution::new(kernel, divisor, bias, edges));
+            }
+            _ => i += 1,
+        }
+    }
+
+    Ok(pipeline)
+}
+
+// Factors can't be negative and gamma must be above 0, while the hue can be rotated either way
+fn parse_adjustment(flag: &str, value: &str) -> io::Result<ColorAdjustment> {
+    let number = value
+        .parse::<f32>()
+        .ok()
+        .filter(|number| number.is_finite());
+    let adjustment = match (flag, number) {
+        ("--hue", Some(degrees)) => Some(ColorAdjustment::Hue(degrees)),
+        ("--gamma", Some(gamma)) if gamma > 0.0 => Some(ColorAdjustment::Gamma(gamma)),
+        ("--brightness", Some(factor)) if factor >= 0.0 => {
+            Some(ColorAdjustment::Brightness(factor))
+        }
+        ("--contrast", Some(factor)) if factor >= 0.0 => Some(ColorAdjustment::Contrast(factor)),
+        ("--saturation", Some(factor)) if factor >= 0.0 => {
+            Some(ColorAdjustment::Saturation(factor))
+        }
+        _ => None,
+    };
+    adjustment.ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid value for {}: {}", flag, value),
+        )
+    })
+}
+
+// Contrast effects are kept apart from the others, as they always see the image at its full size
+fn parse_levels(args: &mut Vec<String>, luma: Luma) -> io::Result<Pipeline> {
+    let mut pipeline = Pipeline::new();
+
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--auto-levels" => {
+                let value = take_value_at(args, i)?;
+                match value.parse::<f32>() {
+                    Ok(clip) if (0.0..50.0).contains(&clip) => {
+                        pipeline.push(AutoLevels::new(clip, luma))
+                    }
+                    _ => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!(
+                                "Invalid auto levels: {}, expected a percentage below 50",
+                                value
+                            ),
+                        ))
+                    }
+                }
+            }
+            "--equalize" => {
+                args.remove(i);
+                pipeline.push(Equalize::new(luma));
+            }
+            "--clahe" => {
+                args.remove(i);
+                let mut clahe = Clahe::with_luma(luma);
+
+                // The tiles and clip limit go right after the --clahe they are for
+                while let Some(flag) = args.get(i) {
+                    match flag.as_str() {
+                        "--tiles" => {
+                            let value = take_value_at(args, i)?;
+                            let tiles = value.parse::<usize>().ok().filter(|&tiles| tiles > 0);
+                            let tiles = tiles.ok_or_else(|| {
+                                Error::new(
+                                    ErrorKind::InvalidInput,
+                                    format!("Invalid number of tiles: {}", value),
+                                )
+                            })?;
+                            clahe = Clahe::new(tiles, clahe.clip_limit(), luma);
+                        }
+                        "--clip-limit" => {
+                            let value = take_value_at(args, i)?;
+                            let limit = value.parse::<f32>().ok();
+                            let limit = limit.filter(|&limit| limit >= 1.0 && limit.is_finite());
+                            let limit = limit.ok_or_else(|| {
+                                Error::new(
+                                    ErrorKind::InvalidInput,
+                                    format!("Invalid clip limit: {}, expected at least 1", value),
+                                )
+                            })?;
+                            clahe = Clahe::new(clahe.tiles(), limit, luma);
+                        }
+                        _ => break,
+                    }
+                }
+
+                pipeline.push(clahe);
+            }
+            _ => i += 1,
+        }
+    }
+
+    Ok(pipeline)
+}
+
+// Either the weights themselves, like "1 2 1; 2 4 2; 1 2 1", or a file with a row on each line
+fn parse_kernel(value: &str) -> io::Result<Kernel> {
+    if fs::metadata(value).is_ok_and(|metadata| metadata.is_file()) {
+        Kernel::parse(&fs::read_to_string(value)?)
+    } else {
+        Kernel::parse(value)
+    }
+}
+
+fn parse_kernel_number(value: &str) -> io::Result<f32> {
+    match value.parse::<f32>() {
+        Ok(number) if number.is_finite() => Ok(number),
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid number: {}", value),
+        )),
+    }
+}
+
+// <radius>[,<amount>[,<threshold>]], with an amount of 1 and a threshold of 0 if not given
+fn parse_unsharp(values: &str) -> io::Result<(f32, f32, f32)> {
+    let invalid = || {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Invalid unsharp mask: {}, expected <radius>[,<amount>[,<threshold>]]",
+                values
+            ),
+        )
+    };
+
+    let numbers = values
+        .split(',')
+        .map(|value| value.trim().parse::<f32>())
+        .collect::<Result<Vec<f32>, _>>()
+        .map_err(|_| invalid())?;
+    if numbers
+        .iter()
+        .any(|number| !number.is_finite() || *number < 0.0)
+    {
+        return Err(invalid());
+    }
+
+    match numbers[..] {
+        [radius] => Ok((radius, 1.0, 0.0)),
+        [radius, amount] => Ok((radius, amount, 0.0)),
+        [radius, amount, threshold] => Ok((radius, amount, threshold)),
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_sigma(sigma: &str) -> io::Result<f32> {
+    match sigma.parse::<f32>() {
+        Ok(sigma) if sigma >= 0.0 && sigma.is_finite() => Ok(sigma),
+        _ => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Invalid blur size: {}, expected a number of pixels", sigma),
+        )),
+    }
+}
+
+// The blur subcommand still takes the number of pixels blurred over on each side, which the
+// Gaussian kernel reaches at 3 sigma
+fn parse_blur_intensity(intensity: &str) -> io::Result<f32> {
+    match intensity.parse::<usize>() {
+        Ok(intensity) => Ok(intensity as f32 / 3.0),
+        Err(_) => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Invalid blur intensity: {}, expected a number of pixels",
+                intensity
+            ),
+        )),
+    }
+}
+
+// How the image is sized and placed in the terminal
+fn parse_sizing(args: &mut Vec<String>) -> io::Result<SizingPolicy> {
+    let mut sizing = SizingPolicy::new(None, None);
+
+    if let Some(name) = take_value(args, "--filter")? {
+        sizing.filter = Filter::parse(&name)?;
+    }
+    if let Some(width) = take_value(args, "--width")? {
+        sizing.width = Some(Length::parse(&width)?);
+    }
+    if let Some(height) = take_value(args, "--height")? {
+        sizing.height = Some(Length::parse(&height)?);
+    }
+    if take_flag(args, "--fit") {
+        sizing.mode = FitMode::Fit;
+    }
+    if take_flag(args, "--fill") {
+        sizing.mode = FitMode::Fill;
+    }
+    if take_flag(args, "--stretch") {
+        sizing.mode = FitMode::Stretch;
+    }
+    if take_flag(args, "--no-resize") {
+        sizing.resize = false;
+    }
+    if take_flag(args, "--center") {
+        sizing.center = true;
+    }
+    if let Some(aspect) = take_value(args, "--cell-aspect")? {
+        sizing.cell_aspect = Some(terminal::parse_cell_aspect(&aspect)?);
+    }
+    if let Some(padding) = take_value(args, "--padding")? {
+        sizing.padding = padding.parse().map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid padding: {}, expected a number of cells", padding),
+            )
+        })?;
+    }
+
+    Ok(sizing)
+}
 
 fn run() -> io::Result<()> {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+
+    let mut options = DecodeOptions {
+        page: 0,
+        lenient: false,
+        skip_crc: false,
+        auto_orient: true,
+    };
+    if let Some(i) = args.iter().position(|arg| arg == "--page") {
+        options.page = match args.get(i + 1).map(|n| n.parse::<usize>()) {
+            Some(Ok(page)) => page,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "Page number must be given",
+                ))
+            }
+        };
+        args.drain(i..i + 2);
+    }
+    if let Some(i) = args.iter().position(|arg| arg == "--lenient") {
+        options.lenient = true;
+        args.remove(i);
+    }
+    if let Some(i) = args.iter().position(|arg| arg == "--no-crc") {
+        options.skip_crc = true;
+        args.remove(i);
+    }
+    if let Some(i) = args.iter().position(|arg| arg == "--no-auto-orient") {
+        options.auto_orient = false;
+        args.remove(i);
+    }
+
+    let mut sizing = parse_sizing(&mut args)?;
+    let mut output = parse_output(&mut args)?;
+    let edges = match take_value(&mut args, "--edges")? {
+        Some(name) => EdgeMode::parse(&name)?,
+        None => EdgeMode::default(),
+    };
+    let luma = match take_value(&mut args, "--luma")? {
+        Some(name) => Luma::parse(&name)?,
+        None => Luma::default(),
+    };
+    let levels = parse_levels(&mut args, luma)?;
+    let mut effects = parse_effects(&mut args, edges, luma)?;
+    let before_resize = take_flag(&mut args, "--before-resize");
 
     if args.len() < 2 {
         return Err(Error::new(
@@ -40,8 +610,23 @@ fn run() -> io::Result<()> {
         ));
     };
 
-    let (file_name, effect) = match args[1].as_str() {
-        "-h" | "--help" => return Ok(println!("{}", HELP_STR)),
+    let file_name = match args[1].as_str() {
+        "-h" | "--help" => {
+            println!("{}", HELP_STR);
+            return Ok(());
+        }
+        "convert" => return convert(&args[2..], &options, &levels, &effects),
+        "info" => return info(&args[2..], &options),
+        "palette" => return palette(&args[2..], &options, output.default_width),
+        "validate" => {
+            if args.len() < 3 {
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!("Invalid Arguments\n\n{}", HELP_STR),
+                ));
+            }
+            return validate(&args[2]);
+        }
         "blur" => {
             if args.len() < 4 {
                 return Err(Error::new(
@@ -49,18 +634,9 @@ fn run() -> io::Result<()> {
                     format!("Invalid Arguments\n\n{}", HELP_STR),
                 ));
             }
-            (
-                &args[3],
-                Effect::Blur(match args[2].parse::<usize>() {
-                    Ok(intensity) => intensity * 2 + 1,
-                    Err(_) => {
-                        return Err(Error::new(
-                            ErrorKind::InvalidData,
-                            "Blur size must be given",
-                        ))
-                    }
-                }),
-            )
+            let sigma = parse_blur_intensity(&args[2])?;
+            effects.push(Blur::new(sigma, BlurMethod::Gaussian, edges));
+            &args[3]
         }
         "ascii" => {
             if args.len() < 3 {
@@ -69,7 +645,8 @@ fn run() -> io::Result<()> {
                     format!("Invalid Arguments\n\n{}", HELP_STR),
                 ));
             }
-            (&args[2], Effect::ASCII)
+            output.renderer = Renderer::Ascii;
+            &args[2]
         }
         "grayscale" => {
             if args.len() < 3 {
@@ -78,7 +655,8 @@ fn run() -> io::Result<()> {
                     format!("Invalid Arguments\n\n{}", HELP_STR),
                 ));
             }
-            (&args[2], Effect::GrayScale)
+            effects.push(Grayscale::new(luma));
+            &args[2]
         }
         "show" => {
             if args.len() < 3 {
@@ -87,11 +665,82 @@ fn run() -> io::Result<()> {
                     format!("Invalid Arguments\n\n{}", HELP_STR),
                 ));
             }
-            (&args[2], Effect::NoEffect)
+            &args[2]
         }
-        _ => (&args[1], Effect::NoEffect),
+        _ => &args[1],
+    };
+
+    let buffer = read_file(file_name)?;
+    let mut metadata = Metadata::new();
+
+    let mut animation = decode(buffer, file_name, &options, &mut metadata)?;
+
+    let (renderer, colors) = if terminal::no_color() {
+        (output.renderer.without_color(), Colors::Monochrome)
+    } else {
+        // Only the first of a png's suggested palettes is used
+        let colors = match metadata.suggested_palettes().first() {
+            Some(palette) => output.colors.with_palette(palette.colors()),
+            None => output.colors,
+        };
+        (output.renderer, colors)
+    };
+
+    // Pipes and files are sized like a terminal of the default width, with no limit on the height
+    let to_terminal = output.path.is_none() && io::stdout().is_terminal();
+    let (columns, rows) = terminal::dimensions(to_terminal);
+    sizing.columns = columns.or(Some(output.default_width));
+    sizing.rows = rows;
+
+    // Only asked for when the image is shown, as the terminal may be queried
+    if to_terminal && sizing.cell_aspect.is_none() {
+        sizing.cell_aspect = terminal::cell_aspect();
+    }
+
+    let mut out: Box<dyn Write> = match &output.path {
+        Some(path) => Box::new(io::BufWriter::new(fs::File::create(path)?)),
+        None => Box::new(io::BufWriter::new(io::stdout())),
     };
 
+    // Effects are applied to the resized image unless asked otherwise, which is faster, but
+    // then their sizes are in terminal pixels rather than image pixels
+    let prepare = |image| -> io::Result<Image<RGBColor>> {
+        let bkgd = metadata.bkgd();
+        let image = levels.apply(image, bkgd);
+        if before_resize {
+            let image = effects.apply(image, bkgd);
+            auto_downsize_image(image, &renderer, &sizing, metadata.pixel_aspect())
+        } else {
+            let image = auto_downsize_image(image, &renderer, &sizing, metadata.pixel_aspect())?;
+            Ok(effects.apply(image, bkgd))
+        }
+    };
+
+    // Animations can only be played in a terminal, elsewhere the first frame is written
+    if to_terminal && animation.frames.len() > 1 {
+        let mut frames = Vec::with_capacity(animation.frames.len());
+        for frame in animation.frames {
+            frames.push((prepare(frame.image)?, frame.duration));
+        }
+        return play_animation(
+            &mut out,
+            &frames,
+            animation.loop_count,
+            metadata.bkgd(),
+            &renderer,
+            &colors,
+            &sizing,
+        );
+    }
+    let image = prepare(animation.frames.swap_remove(0).image)?;
+
+    let layout = sizing.layout(&image, &renderer);
+    renderer.render(&mut out, &image, metadata.bkgd(), &layout, &colors)?;
+
+    out.flush()
+}
+
+fn read_file(file_name: &str) -> io::Result<Vec<u8>> {
     let mut f = fs::File::open(file_name)?;
 
     // Prevent Vector reallocation because size is too small
@@ -101,35 +750,286 @@ fn run() -> io::Result<()> {
     };
 
     f.read_to_end(&mut buffer)?;
-    println!("Buffer length: {}", buffer.len());
 
-    let mut metadata = Metadata::new();
+    Ok(buffer)
+}
+
+// Like webp::parse_animation, every other format is returned as an animation with a single frame
+fn decode(
+    buffer: Vec<u8>,
+    file_name: &str,
+    options: &DecodeOptions,
+    metadata: &mut Metadata,
+) -> io::Result<webp::Animation> {
+    let still = |image| webp::Animation {
+        frames: vec![webp::Frame { image, duration: 0 }],
+        loop_count: 1,
+    };
 
-    // PNG file signature
-    let image = if buffer[..8] == [137, 80, 78, 71, 13, 10, 26, 10] {
-        png::parse(buffer, &mut metadata)?
+    let mut animation = if buffer.starts_with(&png::SIGNATURE) {
+        metadata.set_format("PNG");
+        still(png::parse_with_options(
+            buffer,
+            metadata,
+            &png::ParseOptions {
+                lenient: options.lenient,
+                skip_crc: options.skip_crc,
+                ..Default::default()
+            },
+        )?)
+    } else if webp::is_webp(&buffer) {
+        metadata.set_format("WebP");
+        webp::parse_animation(buffer, metadata)?
+    } else if tiff::is_tiff(&buffer) {
+        metadata.set_format("TIFF");
+        still(tiff::parse(buffer, metadata, options.page)?)
+    } else if ico::is_ico(&buffer) {
+        metadata.set_format(match ico::resource_type(&buffer) {
+            Some(ico::ResourceType::Cursor) => "CUR",
+            _ => "ICO",
+        });
+        still(ico::parse(buffer, metadata)?)
+    } else if tga::is_tga(&buffer, file_name) {
+        metadata.set_format("TGA");
+        still(tga::parse(buffer, metadata)?)
     } else {
         return Err(Error::new(
             ErrorKind::InvalidInput,
-            "Invalid file signature: The image is either not a png or has been corrupted.",
+            "Invalid file signature: The image is either not a png, webp, tiff, ico, cur or tga or has been corrupted.",
+        ));
+    };
+
+    // The decoders check this themselves, but a mismatch would break the metadata shown by info
+    let image = &animation.frames[0].image;
+    if (image.width() as u32, image.height() as u32) != (metadata.width(), metadata.height()) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Decoded image is {}x{}, but its header says {}x{}",
+                image.width(),
+                image.height(),
+                metadata.width(),
+                metadata.height()
+            ),
         ));
+    }
+
+    // The orientation applies to every frame of an animation
+    if options.auto_orient {
+        if let Some(orientation) = metadata.exif().as_ref().and_then(|exif| exif.orientation) {
+            for frame in &mut animation.frames {
+                let image = std::mem::take(&mut frame.image);
+                frame.image = exif::apply_orientation(image, orientation);
+            }
+            if exif::swaps_axes(orientation) {
+                metadata.set_transposed();
+            }
+        }
+    }
+
+    Ok(animation)
+}
+
+// Prints the image's metadata, and the chunk layout for PNG images
+fn info(args: &[String], options: &DecodeOptions) -> io::Result<()> {
+    let json = args.iter().any(|arg| arg == "--json");
+    let paths: Vec<&String> = args.iter().filter(|arg| *arg != "--json").collect();
+
+    let file_name = match paths[..] {
+        [file_name] => file_name,
+        _ => {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("Invalid Arguments\n\n{}", HELP_STR),
+            ))
+        }
     };
 
-    assert_eq!(image[0].len() as u32, metadata.width());
-    assert_eq!(image.len() as u32, metadata.height());
+    let buffer = read_file(file_name)?;
+    let mut metadata = Metadata::new();
 
-    let image = auto_downsize_image(image, &effect)?;
+    // PNG files can be inspected without decoding the pixels. Damage doesn't stop the report,
+    // so that every chunk's CRC status can be shown
+    let frames = if buffer.starts_with(&png::SIGNATURE) {
+        metadata.set_format("PNG");
+        png::inspect(
+            &buffer,
+            &mut metadata,
+            &png::ParseOptions {
+                lenient: true,
+                skip_crc: options.skip_crc,
+                ..Default::default()
+            },
+        )?;
+        1
+    } else {
+        decode(buffer, file_name, options, &mut metadata)?
+            .frames
+            .len()
+    };
 
-    display_image(&image, metadata.bkgd(), effect);
+    if json {
+        println!("{}", info::json(&metadata, frames));
+    } else {
+        print!("{}", info::report(&metadata, frames));
+    }
 
     Ok(())
 }
 
-fn main() {
-    match run() {
-        Err(e) => {
-            eprintln!("{}", e);
+// Shows the palette of an image as a histogram of how often each color is used
+// Frequencies come from the png histogram chunk when there is one. Otherwise the pixels of the
+// first frame are counted, each as the palette color closest to it, as they have been decoded
+fn palette(args: &[String], options: &DecodeOptions, default_width: usize) -> io::Result<()> {
+    let file_name = match args {
+        [file_name] => file_name,
+        _ => {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("Invalid Arguments\n\n{}", HELP_STR),
+            ))
         }
-        _ => {}
     };
+
+    let buffer = read_file(file_name)?;
+    let mut metadata = Metadata::new();
+    let animation = decode(buffer, file_name, options, &mut metadata)?;
+
+    let palette = match metadata.palette() {
+        Some(palette) => palette,
+        None => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} doesn't have a palette", file_name),
+            ))
+        }
+    };
+
+    let frequencies = match metadata.histogram() {
+        Some(histogram) => histogram
+            .frequencies
+            .iter()
+            .map(|&frequency| frequency as u32)
+            .collect(),
+        None => {
+            let mut counts = vec![0; palette.len()];
+            for &(r, g, b) in animation.frames[0].image.pixels() {
+                if !is_transparent(r, g, b) {
+                    if let Some(i) = render::closest(palette, &(r, g, b)) {
+                        counts[i] += 1;
+                    }
+                }
+            }
+            counts
+        }
+    };
+
+    let to_terminal = io::stdout().is_terminal();
+    let width = terminal::dimensions(to_terminal).0.unwrap_or(default_width);
+    print!(
+        "{}",
+        info::palette_histogram(palette, &frequencies, width, !terminal::no_color())
+    );
+    Ok(())
+}
+
+// Lints a PNG file, failing if any errors were found
+fn validate(file_name: &str) -> io::Result<()> {
+    let issues = png::validate::validate(&read_file(file_name)?);
+    for issue in &issues {
+        println!("{}", issue);
+    }
+
+    let errors = issues
+        .iter()
+        .filter(|issue| issue.severity == png::validate::Severity::Error)
+        .count();
+    let warnings = issues.len() - errors;
+
+    if errors > 0 {
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "{}: {} error(s), {} warning(s)",
+                file_name, errors, warnings
+            ),
+        ))
+    } else {
+        println!("{}: OK, {} warning(s)", file_name, warnings);
+        Ok(())
+    }
+}
+
+// Saves the image with the effects applied at its full size. Only the first frame of an
+// animation is kept
+fn convert(
+    args: &[String],
+    options: &DecodeOptions,
+    levels: &Pipeline,
+    effects: &Pipeline,
+) -> io::Result<()> {
+    let (input, output) = match args {
+        [input, output] => (input, output),
+        _ => {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("Invalid Arguments\n\n{}", HELP_STR),
+            ))
+        }
+    };
+
+    let mut metadata = Metadata::new();
+    let mut animation = decode(read_file(input)?, input, options, &mut metadata)?;
+    let image = animation.frames.swap_remove(0).image;
+
+    let image = effects.apply(levels.apply(image, metadata.bkgd()), metadata.bkgd());
+    let png = png::encode(&image, &[("Software", "viu-rs")])?;
+    fs::write(output, png)
+}
+
+// Frames are drawn over each other by moving the cursor back up to where the image started
+// Each frame is shown for its duration in milliseconds
+fn play_animation(
+    out: &mut impl Write,
+    frames: &[(Image<RGBColor>, u32)],
+    loop_count: u16,
+    bkgd: &RGBColor,
+    renderer: &Renderer,
+    colors: &Colors,
+    sizing: &SizingPolicy,
+) -> io::Result<()> {
+    // The renderer writes an empty line before the image, and the padding around it
+    let layout = sizing.layout(&frames[0].0, renderer);
+    let image = &frames[0].0;
+    let (_, rows) = renderer.pixels_to_cells(image.width(), image.height());
+    let lines = 1 + layout.top + rows + layout.bottom;
+
+    let mut iteration = 0;
+    loop {
+        for (i, (image, duration)) in frames.iter().enumerate() {
+            if iteration > 0 || i > 0 {
+                write!(out, "\x1B[{}A", lines)?;
+            }
+            renderer.render(out, image, bkgd, &layout, colors)?;
+            out.flush()?;
+
+            // Like browsers, very short durations are treated as 100ms
+            let duration = if *duration <= 10 { 100 } else { *duration };
+            thread::sleep(Duration::from_millis(duration as u64));
+        }
+
+        iteration += 1;
+        if loop_count != 0 && iteration >= loop_count {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
 }
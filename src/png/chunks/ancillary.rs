@@ -1,7 +1,19 @@
 use crate::common::*;
+use crate::inflate::Inflater;
 use crate::png::Metadata;
 use std::fmt;
 use std::io;
+use std::str;
+
+// Scales a sample to 8 bits the way the image data is, so that 2 bit gray 1 becomes 85. Values too
+// large for the bit depth are clamped to its maximum
+fn scale_sample(value: u16, bit_depth: u8) -> u8 {
+    if bit_depth == 16 {
+        return (value >> 8) as u8;
+    }
+    let max = (1u32 << bit_depth) - 1;
+    ((value as u32).min(max) * 255 / max) as u8
+}
 
 // IMPORTANT NOTE as per the png 1.2 spec [http://www.libpng.org/pub/png/spec/1.2/png-1.2-pdg.html#C.Anc-chunks]:
 //      Note: when dealing with 16-bit grayscale or truecolor data, it is important to compare both bytes of the
@@ -14,30 +26,17 @@ use std::io;
 // multiple values expected to be distinct will be all treated as transparent.
 pub fn parse_trns(bytes: &[u8], metadata: &Metadata) -> io::Result<AlphaValue> {
     match metadata.color_type() {
-        ColorType::Gray => {
-            let val = from_bytes_u16(bytes);
-            let bit_depth = metadata.bit_depth();
-            Ok(AlphaValue::Gray(if bit_depth == 16 {
-                (val / 256) as u8
-            } else {
-                (val as u8) * 8 / bit_depth
-            }))
-        }
+        ColorType::Gray => Ok(AlphaValue::Gray(scale_sample(
+            from_bytes_u16(bytes),
+            metadata.bit_depth(),
+        ))),
         ColorType::RGB => {
-            let r = from_bytes_u16(&bytes[..2]);
-            let g = from_bytes_u16(&bytes[2..4]);
-            let b = from_bytes_u16(&bytes[4..6]);
             let bit_depth = metadata.bit_depth();
-            let (r, g, b) = if bit_depth == 16 {
-                ((r / 256) as u8, (g / 256) as u8, (b / 256) as u8)
-            } else {
-                (
-                    (r * 8 / bit_depth as u16) as u8,
-                    (g * 8 / bit_depth as u16) as u8,
-                    (b * 8 / bit_depth as u16) as u8,
-                )
-            };
-            Ok(AlphaValue::RGB(r, g, b))
+            Ok(AlphaValue::RGB(
+                scale_sample(from_bytes_u16(&bytes[..2]), bit_depth),
+                scale_sample(from_bytes_u16(&bytes[2..4]), bit_depth),
+                scale_sample(from_bytes_u16(&bytes[4..6]), bit_depth),
+            ))
         }
         ColorType::Palette => {
             let len = match metadata.palette() {
@@ -51,24 +50,16 @@ pub fn parse_trns(bytes: &[u8], metadata: &Metadata) -> io::Result<AlphaValue> {
             };
 
             let mut alpha = Vec::with_capacity(len);
-
-            for byte in bytes {
-                alpha.push(*byte);
-            }
-
-            for _ in bytes.len()..len {
-                alpha.push(255);
-            }
+            alpha.extend_from_slice(bytes);
+            alpha.resize(len, 255);
 
             Ok(AlphaValue::Palette(alpha))
         }
         // RGBA and GrayA already have alpha channels and tRNS chunks are unsupported for them
-        color_type => {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                format!("tRNS chunk not allowed for color type: {:?}", color_type),
-            ))
-        }
+        color_type => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("tRNS chunk not allowed for color type: {:?}", color_type),
+        )),
     }
 }
 
@@ -94,6 +85,236 @@ impl TIMEChunk {
     }
 }
 
+#[derive(Debug, PartialEq, Eq)]
+pub enum PhysicalUnit {
+    // Only the aspect ratio is known
+    Unknown,
+    Metre,
+}
+
+// Pixels per unit along each axis
+pub struct PHYsChunk {
+    pub x: u32,
+    pub y: u32,
+    pub unit: PhysicalUnit,
+}
+
+impl PHYsChunk {
+    pub fn parse(bytes: &[u8]) -> io::Result<PHYsChunk> {
+        if bytes.len() != 9 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("pHYs chunk must be 9 bytes, got {}", bytes.len()),
+            ));
+        }
+
+        let phys = PHYsChunk {
+            x: from_bytes_u32(&bytes[0..4]),
+            y: from_bytes_u32(&bytes[4..8]),
+            unit: match bytes[8] {
+                1 => PhysicalUnit::Metre,
+                _ => PhysicalUnit::Unknown,
+            },
+        };
+
+        if phys.x == 0 || phys.y == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "pHYs pixels per unit cannot be 0",
+            ));
+        }
+
+        Ok(phys)
+    }
+
+    // Width of a pixel divided by its height
+    pub fn pixel_aspect(&self) -> f32 {
+        self.y as f32 / self.x as f32
+    }
+
+    // Only known if the unit is metres
+    pub fn dpi(&self) -> Option<(f32, f32)> {
+        match self.unit {
+            PhysicalUnit::Metre => Some((self.x as f32 * 0.0254, self.y as f32 * 0.0254)),
+            PhysicalUnit::Unknown => None,
+        }
+    }
+}
+
+// Number of bits that were significant in the original samples, one entry per channel in the
+// order they are stored. Palette images describe the precision of the PLTE entries
+pub struct SBITChunk {
+    pub significant: Vec<u8>,
+}
+
+impl SBITChunk {
+    pub fn parse(bytes: &[u8], metadata: &Metadata) -> io::Result<SBITChunk> {
+        let (channels, depth) = match metadata.color_type() {
+            ColorType::Gray => (1, metadata.bit_depth()),
+            ColorType::GrayA => (2, metadata.bit_depth()),
+            ColorType::RGB => (3, metadata.bit_depth()),
+            ColorType::Palette => (3, 8),
+            ColorType::RGBA => (4, metadata.bit_depth()),
+        };
+
+        if bytes.len() != channels {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "sBIT chunk must be {} bytes for color type {:?}, got {}",
+                    channels,
+                    metadata.color_type(),
+                    bytes.len()
+                ),
+            ));
+        }
+        if bytes.iter().any(|&bits| bits == 0 || bits > depth) {
+            return Err(invalid(
+                "sBIT values must be between 1 and the sample depth",
+            ));
+        }
+
+        Ok(SBITChunk {
+            significant: bytes.to_vec(),
+        })
+    }
+
+    // Encoders are supposed to scale low precision samples up with bit replication, but some
+    // just shift them left, so that white is never reached. Only the significant bits are kept
+    // and scaled back to the full range. The value has already been reduced to 8 bits
+    pub fn rescale(&self, channel: usize, value: u8) -> u8 {
+        match self.significant.get(channel) {
+            Some(&bits) if bits < 8 => {
+                let max = (1u16 << bits) - 1;
+                ((value >> (8 - bits)) as u16 * 255 / max) as u8
+            }
+            _ => value,
+        }
+    }
+}
+
+// How often each palette entry is used in the image
+pub struct HISTChunk {
+    pub frequencies: Vec<u16>,
+}
+
+impl HISTChunk {
+    pub fn parse(bytes: &[u8], metadata: &Metadata) -> io::Result<HISTChunk> {
+        let len = match metadata.palette() {
+            Some(pt) => pt.len(),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "PLTE chunk must be present before hIST",
+                ))
+            }
+        };
+
+        if bytes.len() != len * 2 {
+            return Err(invalid("hIST chunk must have one entry per PLTE entry"));
+        }
+
+        Ok(HISTChunk {
+            frequencies: bytes.chunks(2).map(from_bytes_u16).collect(),
+        })
+    }
+}
+
+pub struct SuggestedColor {
+    pub red: u16,
+    pub green: u16,
+    pub blue: u16,
+    pub alpha: u16,
+    // Relative to the other entries of the palette
+    pub frequency: u16,
+}
+
+// A palette suggested by the encoder for displays that cannot show all the colors. There can
+// be several, as long as their names differ
+pub struct SPLTChunk {
+    pub name: String,
+    // 8 or 16, the depth of the sample values
+    pub depth: u8,
+    pub entries: Vec<SuggestedColor>,
+}
+
+impl SPLTChunk {
+    pub fn parse(bytes: &[u8]) -> io::Result<SPLTChunk> {
+        let (name, rest) = TextChunk::split(bytes);
+        if name.is_empty() || name.len() > 79 {
+            return Err(invalid("sPLT palette name must be 1-79 bytes"));
+        }
+        if rest.is_empty() {
+            return Err(invalid("sPLT chunk is missing the sample depth"));
+        }
+
+        let depth = rest[0];
+        let entry_size = match depth {
+            8 => 6,
+            16 => 10,
+            _ => return Err(invalid("sPLT sample depth must be 8 or 16")),
+        };
+        let data = &rest[1..];
+        if data.len() % entry_size != 0 {
+            return Err(invalid("sPLT chunk has a partial entry"));
+        }
+
+        let entries = data
+            .chunks(entry_size)
+            .map(|entry| {
+                if depth == 8 {
+                    SuggestedColor {
+                        red: entry[0] as u16,
+                        green: entry[1] as u16,
+                        blue: entry[2] as u16,
+                        alpha: entry[3] as u16,
+                        frequency: from_bytes_u16(&entry[4..6]),
+                    }
+                } else {
+                    SuggestedColor {
+                        red: from_bytes_u16(&entry[0..2]),
+                        green: from_bytes_u16(&entry[2..4]),
+                        blue: from_bytes_u16(&entry[4..6]),
+                        alpha: from_bytes_u16(&entry[6..8]),
+                        frequency: from_bytes_u16(&entry[8..10]),
+                    }
+                }
+            })
+            .collect();
+
+        Ok(SPLTChunk {
+            name: latin1(name),
+            depth,
+            entries,
+        })
+    }
+
+    // The colors of the entries that aren't fully transparent, as 8 bit samples
+    pub fn colors(&self) -> Vec<RGBColor> {
+        let shift = self.depth - 8;
+        self.entries
+            .iter()
+            .filter(|entry| entry.alpha > 0)
+            .map(|entry| {
+                (
+                    (entry.red >> shift) as u8,
+                    (entry.green >> shift) as u8,
+                    (entry.blue >> shift) as u8,
+                )
+            })
+            .collect()
+    }
+}
+
+impl TIMEChunk {
+    pub fn iso8601(&self) -> String {
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
 impl fmt::Display for TIMEChunk {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -104,65 +325,141 @@ impl fmt::Display for TIMEChunk {
     }
 }
 
-use std::str;
-
+// Text from a tEXt, zTXt or iTXt chunk
 pub struct TextChunk {
     pub key: String,
     pub text: String,
+    // Only iTXt has a language tag (RFC 3066, can be empty) and a translation of the keyword
+    pub language: Option<String>,
+    pub translated_key: Option<String>,
+    pub compressed: bool,
 }
 
 type SplitChunk<'a> = (&'a [u8], &'a [u8]);
 
+// tEXt and zTXt are always Latin-1, which maps directly onto the first 256 code points
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// Guards against small chunks that decompress to huge amounts of text
+const MAX_TEXT_LENGTH: usize = 16 * 1024 * 1024;
+
+fn inflate(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let mut inflater = Inflater::new();
+    inflater.set_limit(MAX_TEXT_LENGTH);
+    inflater.feed(bytes)?;
+    inflater.finish()?;
+    Ok(inflater.into_output())
+}
+
 impl TextChunk {
-    pub fn split(bytes: &[u8]) -> SplitChunk {
-        let mut i = 0;
-        while bytes[i] != 0 {
-            i += 1;
+    // Splits the chunk at the null separator after the keyword, which is in neither part
+    pub fn split(bytes: &[u8]) -> SplitChunk<'_> {
+        match bytes.iter().position(|&b| b == 0) {
+            Some(i) => (&bytes[0..i], &bytes[i + 1..]),
+            None => (bytes, &[]),
+        }
+    }
+
+    fn keyword(bytes: &[u8]) -> io::Result<String> {
+        if bytes.is_empty() || bytes.len() > 79 {
+            return Err(invalid("Text keyword must be 1-79 bytes"));
         }
-        i += 1;
-        (&bytes[0..i], &bytes[i + 1..])
+        Ok(latin1(bytes))
     }
 
-    pub fn parse((keyword_bytes, text_bytes): SplitChunk) -> Result<TextChunk, str::Utf8Error> {
-        let key = str::from_utf8(keyword_bytes)?.to_owned();
-        let text = str::from_utf8(text_bytes)?.to_owned();
+    pub fn parse_text(bytes: &[u8]) -> io::Result<TextChunk> {
+        let (keyword, text) = TextChunk::split(bytes);
 
-        Ok(TextChunk { key, text })
+        Ok(TextChunk {
+            key: TextChunk::keyword(keyword)?,
+            text: latin1(text),
+            language: None,
+            translated_key: None,
+            compressed: false,
+        })
+    }
+
+    pub fn parse_compressed_text(bytes: &[u8]) -> io::Result<TextChunk> {
+        let (keyword, rest) = TextChunk::split(bytes);
+
+        // The text is preceded by the compression method, which can only be zlib
+        match rest.first() {
+            Some(0) => {}
+            _ => return Err(invalid("Unknown zTXt compression method")),
+        }
+
+        Ok(TextChunk {
+            key: TextChunk::keyword(keyword)?,
+            text: latin1(&inflate(&rest[1..])?),
+            language: None,
+            translated_key: None,
+            compressed: true,
+        })
+    }
+
+    // iTXt: keyword, compression flag and method, language tag, translated keyword, UTF-8 text
+    pub fn parse_international_text(bytes: &[u8]) -> io::Result<TextChunk> {
+        let (keyword, rest) = TextChunk::split(bytes);
+        if rest.len() < 2 {
+            return Err(invalid("iTXt chunk is truncated"));
+        }
+
+        let compressed = rest[0] == 1;
+        if compressed && rest[1] != 0 {
+            return Err(invalid("Unknown iTXt compression method"));
+        }
+
+        let (language, rest) = TextChunk::split(&rest[2..]);
+        let (translated_key, text) = TextChunk::split(rest);
+
+        let text = if compressed {
+            inflate(text)?
+        } else {
+            text.to_vec()
+        };
+        let utf8 = |bytes: &[u8]| {
+            str::from_utf8(bytes)
+                .map(|s| s.to_owned())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        };
+
+        Ok(TextChunk {
+            key: TextChunk::keyword(keyword)?,
+            text: utf8(&text)?,
+            // The language tag is ASCII
+            language: Some(latin1(language)),
+            translated_key: Some(utf8(translated_key)?),
+            compressed,
+        })
     }
 }
 
 pub fn parse_bkgd_chunk(bytes: &[u8], metadata: &Metadata) -> io::Result<RGBColor> {
     let (r, g, b) = match metadata.color_type() {
         ColorType::Palette => match metadata.palette() {
-            Some(pt) => pt[bytes[0] as usize],
+            Some(pt) => match pt.get(bytes[0] as usize) {
+                Some(&color) => color,
+                None => return Err(invalid("bKGD palette index is past the end of the palette")),
+            },
             None => return Err(io::Error::new(io::ErrorKind::NotFound, "Palette not found")),
         },
         ColorType::Gray | ColorType::GrayA => {
-            let val = from_bytes_u16(bytes);
-            let bit_depth = metadata.bit_depth();
-            let val = if bit_depth == 16 {
-                (val / 256) as u8
-            } else {
-                (val as u8) * 8 / bit_depth
-            };
+            let val = scale_sample(from_bytes_u16(bytes), metadata.bit_depth());
             (val, val, val)
         }
         ColorType::RGBA | ColorType::RGB => {
-            let r = from_bytes_u16(&bytes[0..2]);
-            let g = from_bytes_u16(&bytes[2..4]);
-            let b = from_bytes_u16(&bytes[2..6]);
-
             let bit_depth = metadata.bit_depth();
-            let (r, g, b) = if bit_depth == 16 {
-                ((r / 256) as u8, (g / 256) as u8, (b / 256) as u8)
-            } else {
-                (
-                    (r * 8 / bit_depth as u16) as u8,
-                    (g * 8 / bit_depth as u16) as u8,
-                    (b * 8 / bit_depth as u16) as u8,
-                )
-            };
-            (r, g, b)
+            (
+                scale_sample(from_bytes_u16(&bytes[0..2]), bit_depth),
+                scale_sample(from_bytes_u16(&bytes[2..4]), bit_depth),
+                scale_sample(from_bytes_u16(&bytes[4..6]), bit_depth),
+            )
         }
     };
     Ok(if is_transparent(r, g, b) {
@@ -171,3 +468,83 @@ pub fn parse_bkgd_chunk(bytes: &[u8], metadata: &Metadata) -> io::Result<RGBColo
         (r, g, b)
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::compress;
+
+    #[test]
+    fn text() {
+        // Latin-1, where 0xe9 is é
+        let chunk = TextChunk::parse_text(b"Author\0Caf\xe9 au lait").unwrap();
+        assert_eq!(chunk.key, "Author");
+        assert_eq!(chunk.text, "Caf\u{e9} au lait");
+        assert_eq!((chunk.language, chunk.translated_key), (None, None));
+        assert!(!chunk.compressed);
+
+        let chunk = TextChunk::parse_text(b"Comment").unwrap();
+        assert_eq!((chunk.key.as_str(), chunk.text.as_str()), ("Comment", ""));
+    }
+
+    #[test]
+    fn keyword_length() {
+        assert!(TextChunk::parse_text(b"\0text").is_err());
+        let long = [&[b'k'; 80][..], b"\0text"].concat();
+        assert!(TextChunk::parse_text(&long).is_err());
+        assert!(TextChunk::parse_text(&long[1..]).is_ok());
+    }
+
+    #[test]
+    fn compressed_text() {
+        let data = [&b"Comment\0\0"[..], &compress(b"na\xefve").unwrap()].concat();
+        let chunk = TextChunk::parse_compressed_text(&data).unwrap();
+        assert_eq!(chunk.key, "Comment");
+        assert_eq!(chunk.text, "na\u{ef}ve");
+        assert!(chunk.compressed);
+
+        // Unknown compression method, and data that isn't zlib
+        let mut unknown_method = data.clone();
+        unknown_method[8] = 1;
+        assert!(TextChunk::parse_compressed_text(&unknown_method).is_err());
+        assert!(TextChunk::parse_compressed_text(b"Comment\0\0not zlib").is_err());
+    }
+
+    #[test]
+    fn international_text() {
+        let data = "Title\0\0\0de\0Titel\0Gr\u{fc}\u{df}e \u{2603}".as_bytes();
+        let chunk = TextChunk::parse_international_text(data).unwrap();
+        assert_eq!(chunk.key, "Title");
+        assert_eq!(chunk.text, "Gr\u{fc}\u{df}e \u{2603}");
+        assert_eq!(chunk.language.as_deref(), Some("de"));
+        assert_eq!(chunk.translated_key.as_deref(), Some("Titel"));
+        assert!(!chunk.compressed);
+
+        // Without a language tag or translated keyword
+        let chunk = TextChunk::parse_international_text(b"Title\0\0\0\0\0Text").unwrap();
+        assert_eq!(chunk.language.as_deref(), Some(""));
+        assert_eq!(chunk.translated_key.as_deref(), Some(""));
+        assert_eq!(chunk.text, "Text");
+    }
+
+    #[test]
+    fn compressed_international_text() {
+        let text = compress("\u{65e5}\u{672c}\u{8a9e}".as_bytes()).unwrap();
+        let data = [&b"Title\0\x01\0ja\0"[..], "\u{984c}\0".as_bytes(), &text].concat();
+        let chunk = TextChunk::parse_international_text(&data).unwrap();
+        assert_eq!(chunk.text, "\u{65e5}\u{672c}\u{8a9e}");
+        assert_eq!(chunk.language.as_deref(), Some("ja"));
+        assert_eq!(chunk.translated_key.as_deref(), Some("\u{984c}"));
+        assert!(chunk.compressed);
+
+        let mut unknown_method = data.clone();
+        unknown_method[7] = 1;
+        assert!(TextChunk::parse_international_text(&unknown_method).is_err());
+    }
+
+    #[test]
+    fn invalid_international_text() {
+        assert!(TextChunk::parse_international_text(b"Title\0\0").is_err());
+        assert!(TextChunk::parse_international_text(b"Title\0\0\0\0\0\xff\xfe").is_err());
+    }
+}
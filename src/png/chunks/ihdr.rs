@@ -7,6 +7,7 @@ pub enum InterlaceMethod {
     Adam7,
 }
 
+#[derive(Default)]
 pub struct IHDRChunk {
     width: u32,
     height: u32,
@@ -17,20 +18,6 @@ pub struct IHDRChunk {
     interlace_method: u8,
 }
 
-impl std::default::Default for IHDRChunk {
-    fn default() -> Self {
-        IHDRChunk {
-            width: 0,
-            height: 0,
-            bit_depth: 0,
-            color_type: 0,
-            compression_method: 0,
-            filter_method: 0,
-            interlace_method: 0,
-        }
-    }
-}
-
 impl IHDRChunk {
     pub fn parse(bytes: &[u8]) -> Result<IHDRChunk> {
         let ihdr = IHDRChunk {
@@ -79,33 +66,26 @@ impl IHDRChunk {
         // At present, only compression method 0 (deflate/inflate compression with a
         // sliding window of at most 32768 bytes) is defined.
         if ihdr.compression_method != 0 {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!("Unknown compression_method: {}", ihdr.compression_method),
-            ));
+            return Err(Error::other(format!(
+                "Unknown compression_method: {}",
+                ihdr.compression_method
+            )));
         }
 
         // At present, only filter method 0 (adaptive filtering with five basic filter types) is defined
         if ihdr.filter_method != 0 {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!("Unknown compression_method: {}", ihdr.compression_method),
-            ));
+            return Err(Error::other(format!(
+                "Unknown compression_method: {}",
+                ihdr.compression_method
+            )));
         }
 
         // Two values are currently defined: 0 (no interlace) or 1 (Adam7 interlace)
         if ihdr.interlace_method >= 2 {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!("Unknown compression_method: {}", ihdr.compression_method),
-            ));
-        }
-
-        if ihdr.interlace_method() == InterlaceMethod::Adam7 {
-            return Err(Error::new(
-                ErrorKind::Other,
-                "Interlacing is currently unsupported",
-            ));
+            return Err(Error::other(format!(
+                "Unknown compression_method: {}",
+                ihdr.compression_method
+            )));
         }
 
         Ok(ihdr)
@@ -155,3 +135,62 @@ impl IHDRChunk {
         }
     }
 }
+
+fn channels(color_type: &ColorType) -> usize {
+    match color_type {
+        ColorType::Gray | ColorType::Palette => 1,
+        ColorType::GrayA => 2,
+        ColorType::RGB => 3,
+        ColorType::RGBA => 4,
+    }
+}
+
+// Number of bytes in each row of every (sub)image, including the filter type byte, and how
+// many rows there are of that size
+pub fn row_layout(
+    width: u32,
+    height: u32,
+    color_type: &ColorType,
+    bit_depth: u8,
+    interlaced: bool,
+) -> Vec<(usize, usize)> {
+    let (width, height) = (width as usize, height as usize);
+    let bits_per_pixel = channels(color_type) * bit_depth as usize;
+    let row_bytes = |w: usize| (w * bits_per_pixel).div_ceil(8) + 1;
+
+    if !interlaced {
+        return vec![(row_bytes(width), height)];
+    }
+
+    // Start and step of each Adam7 pass as (x, y, dx, dy)
+    let passes = [
+        (0, 0, 8, 8),
+        (4, 0, 8, 8),
+        (0, 4, 4, 8),
+        (2, 0, 4, 4),
+        (0, 2, 2, 4),
+        (1, 0, 2, 2),
+        (0, 1, 1, 2),
+    ];
+
+    passes
+        .iter()
+        .filter_map(|&(x, y, dx, dy)| {
+            let w = if width > x {
+                (width - x).div_ceil(dx)
+            } else {
+                0
+            };
+            let h = if height > y {
+                (height - y).div_ceil(dy)
+            } else {
+                0
+            };
+            if w == 0 || h == 0 {
+                None
+            } else {
+                Some((row_bytes(w), h))
+            }
+        })
+        .collect()
+}
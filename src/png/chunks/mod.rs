@@ -2,6 +2,17 @@ pub mod ancillary;
 pub mod ihdr;
 pub mod plte;
 
+// Where a chunk was found in the file, kept for inspecting the file structure
+pub struct ChunkInfo {
+    pub chunk_type: [u8; 4],
+    // Offset of the length field, which is the start of the chunk
+    pub offset: usize,
+    pub length: usize,
+    pub crc: u32,
+    // None if the CRC was not checked
+    pub crc_valid: Option<bool>,
+}
+
 pub mod chunk_types {
     pub static IHDR: [u8; 4] = [73, 72, 68, 82];
     pub static PLTE: [u8; 4] = [80, 76, 84, 69];
@@ -17,4 +28,24 @@ pub mod chunk_types {
     pub static zTXt: [u8; 4] = [122, 84, 88, 116];
     #[allow(non_upper_case_globals)]
     pub static bKGD: [u8; 4] = [98, 75, 71, 68];
+    #[allow(non_upper_case_globals)]
+    pub static cHRM: [u8; 4] = [99, 72, 82, 77];
+    #[allow(non_upper_case_globals)]
+    pub static gAMA: [u8; 4] = [103, 65, 77, 65];
+    #[allow(non_upper_case_globals)]
+    pub static iCCP: [u8; 4] = [105, 67, 67, 80];
+    #[allow(non_upper_case_globals)]
+    pub static sBIT: [u8; 4] = [115, 66, 73, 84];
+    #[allow(non_upper_case_globals)]
+    pub static sRGB: [u8; 4] = [115, 82, 71, 66];
+    #[allow(non_upper_case_globals)]
+    pub static pHYs: [u8; 4] = [112, 72, 89, 115];
+    #[allow(non_upper_case_globals)]
+    pub static sPLT: [u8; 4] = [115, 80, 76, 84];
+    #[allow(non_upper_case_globals)]
+    pub static hIST: [u8; 4] = [104, 73, 83, 84];
+    #[allow(non_upper_case_globals)]
+    pub static iTXt: [u8; 4] = [105, 84, 88, 116];
+    #[allow(non_upper_case_globals)]
+    pub static eXIf: [u8; 4] = [101, 88, 73, 102];
 }
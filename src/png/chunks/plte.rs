@@ -1,7 +1,6 @@
 use crate::common::RGBColor;
 
 pub struct PLTEChunk {
-    pub length: usize,
     pub colors: Vec<RGBColor>,
 }
 
@@ -16,6 +15,6 @@ impl PLTEChunk {
             colors.push((bytes[i * 3], bytes[i * 3 + 1], bytes[i * 3 + 2]));
         }
 
-        PLTEChunk { length, colors }
+        PLTEChunk { colors }
     }
 }
@@ -0,0 +1,334 @@
+use crate::common::*;
+use crate::crc::CRCHandler;
+use crate::png::chunks::chunk_types;
+use crate::png::parse_image::paeth_predictor;
+use crate::png::SIGNATURE;
+use libflate::zlib::Encoder;
+use std::collections::HashMap;
+use std::io::prelude::*;
+use std::io::{Error, ErrorKind, Result};
+
+// Color types as written in IHDR
+const COLOR_TYPE_RGB: u8 = 2;
+const COLOR_TYPE_PALETTE: u8 = 3;
+
+pub(crate) struct ChunkWriter {
+    out: Vec<u8>,
+    crc_handler: CRCHandler,
+}
+
+impl ChunkWriter {
+    pub(crate) fn new() -> ChunkWriter {
+        ChunkWriter {
+            out: SIGNATURE.to_vec(),
+            crc_handler: CRCHandler::new(),
+        }
+    }
+
+    // The CRC covers the chunk type and data, but not the length
+    pub(crate) fn write(&mut self, chunk_type: &[u8; 4], data: &[u8]) {
+        self.out
+            .extend_from_slice(&(data.len() as u32).to_be_bytes());
+        let crc_start = self.out.len();
+        self.out.extend_from_slice(chunk_type);
+        self.out.extend_from_slice(data);
+        let crc = self.crc_handler.crc(&self.out[crc_start..]);
+        self.out.extend_from_slice(&crc.to_be_bytes());
+    }
+
+    pub(crate) fn finish(self) -> Vec<u8> {
+        self.out
+    }
+}
+
+// Images with at most 256 colors (transparency counting as one) are stored with a palette,
+// which is index 0 if the image has any transparent pixels
+fn build_palette(image: &Image<RGBColor>) -> Option<Vec<RGBColor>> {
+    let mut palette = Vec::new();
+    let mut seen = HashMap::new();
+
+    if image.pixels().any(|px| is_transparent(px.0, px.1, px.2)) {
+        seen.insert((0, 0, 0), 0);
+        palette.push((0, 0, 0));
+    }
+
+    for px in image.pixels() {
+        if !seen.contains_key(px) {
+            if palette.len() == 256 {
+                return None;
+            }
+            seen.insert(*px, palette.len());
+            palette.push(*px);
+        }
+    }
+
+    Some(palette)
+}
+
+// Smallest bit depth that can index every entry of the palette
+fn palette_bit_depth(len: usize) -> usize {
+    match len {
+        0..=2 => 1,
+        3..=4 => 2,
+        5..=16 => 4,
+        _ => 8,
+    }
+}
+
+// Converts the image into unfiltered scanlines, without the filter type byte
+fn scanlines(image: &Image<RGBColor>, palette: &Option<Vec<RGBColor>>) -> Vec<Vec<u8>> {
+    match palette {
+        Some(palette) => {
+            let bit_depth = palette_bit_depth(palette.len());
+            let index: HashMap<RGBColor, u8> = palette
+                .iter()
+                .enumerate()
+                .map(|(i, color)| (*color, i as u8))
+                .collect();
+
+            image
+                .rows()
+                .map(|row| {
+                    let mut line = vec![0u8; (row.len() * bit_depth).div_ceil(8)];
+                    for (x, px) in row.iter().enumerate() {
+                        let bit = x * bit_depth;
+                        line[bit / 8] |= index[px] << (8 - bit_depth - bit % 8);
+                    }
+                    line
+                })
+                .collect()
+        }
+        None => image
+            .rows()
+            .map(|row| row.iter().flat_map(|&(r, g, b)| [r, g, b]).collect())
+            .collect(),
+    }
+}
+
+fn filter_row(filter: u8, row: &[u8], prev: &[u8], px_size: usize, out: &mut Vec<u8>) {
+    out.clear();
+    out.push(filter);
+
+    for x in 0..row.len() {
+        let left = if x >= px_size { row[x - px_size] } else { 0 };
+        let top = prev[x];
+        let topleft = if x >= px_size { prev[x - px_size] } else { 0 };
+
+        let prediction = match filter {
+            0 => 0,
+            1 => left,
+            2 => top,
+            3 => ((left as u16 + top as u16) / 2) as u8,
+            _ => paeth_predictor(left as i32, top as i32, topleft as i32),
+        };
+        out.push(row[x].wrapping_sub(prediction));
+    }
+}
+
+// Picks the filter for each row with the minimum sum of absolute differences heuristic
+// recommended by the PNG spec. Palette images are left unfiltered, as the spec also recommends
+fn filter(lines: &[Vec<u8>], px_size: usize, adaptive: bool) -> Vec<u8> {
+    let mut data = Vec::with_capacity(lines.iter().map(|line| line.len() + 1).sum());
+    let mut best = Vec::new();
+    let mut candidate = Vec::new();
+
+    let empty = vec![0; lines.first().map_or(0, |line| line.len())];
+    let mut prev = &empty;
+
+    for line in lines {
+        if adaptive {
+            let mut best_sum = u64::MAX;
+            for filter_type in 0..5 {
+                filter_row(filter_type, line, prev, px_size, &mut candidate);
+                let sum = candidate[1..]
+                    .iter()
+                    .map(|&b| (b as i8).unsigned_abs() as u64)
+                    .sum();
+                if sum < best_sum {
+                    best_sum = sum;
+                    std::mem::swap(&mut best, &mut candidate);
+                }
+            }
+        } else {
+            filter_row(0, line, prev, px_size, &mut best);
+        }
+
+        data.extend_from_slice(&best);
+        prev = line;
+    }
+
+    data
+}
+
+fn latin1(s: &str) -> Result<Vec<u8>> {
+    s.chars()
+        .map(|c| {
+            if (c as u32) <= 0xff {
+                Ok(c as u8)
+            } else {
+                Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "Text chunks can only contain Latin-1 characters, got: {}",
+                        c
+                    ),
+                ))
+            }
+        })
+        .collect()
+}
+
+// Compression, filter and interlace methods are all 0
+pub(crate) fn ihdr_data(width: u32, height: u32, bit_depth: u8, color_type: u8) -> Vec<u8> {
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&width.to_be_bytes());
+    ihdr.extend_from_slice(&height.to_be_bytes());
+    ihdr.extend_from_slice(&[bit_depth, color_type, 0, 0, 0]);
+    ihdr
+}
+
+pub(crate) fn compress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = Encoder::new(Vec::new())?;
+    encoder.write_all(data)?;
+    encoder.finish().into_result()
+}
+
+// Text chunks are (keyword, text) pairs. Both are expected to be Latin-1, and keywords have to
+// be 1-79 characters long
+pub fn encode(image: &Image<RGBColor>, text: &[(&str, &str)]) -> Result<Vec<u8>> {
+    let height = image.height();
+    let width = image.width();
+    if image.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Cannot encode an empty image",
+        ));
+    }
+
+    let palette = build_palette(image);
+    let has_transparency = image.pixels().any(|px| is_transparent(px.0, px.1, px.2));
+
+    let mut writer = ChunkWriter::new();
+
+    let (bit_depth, color_type) = match &palette {
+        Some(palette) => (palette_bit_depth(palette.len()) as u8, COLOR_TYPE_PALETTE),
+        None => (8, COLOR_TYPE_RGB),
+    };
+
+    writer.write(
+        &chunk_types::IHDR,
+        &ihdr_data(width as u32, height as u32, bit_depth, color_type),
+    );
+
+    for (key, value) in text {
+        let mut data = latin1(key)?;
+        if data.is_empty() || data.len() > 79 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid text keyword: {:?}", key),
+            ));
+        }
+        data.push(0);
+        data.extend(latin1(value)?);
+        writer.write(&chunk_types::tEXt, &data);
+    }
+
+    match &palette {
+        Some(palette) => {
+            let plte: Vec<u8> = palette.iter().flat_map(|&(r, g, b)| [r, g, b]).collect();
+            writer.write(&chunk_types::PLTE, &plte);
+            // Only the transparent entry at index 0 needs an alpha value
+            if has_transparency {
+                writer.write(&chunk_types::tRNS, &[0]);
+            }
+        }
+        None => {
+            // Displayed colors are never (0, 0, 0) unless they are transparent
+            if has_transparency {
+                writer.write(&chunk_types::tRNS, &[0; 6]);
+            }
+        }
+    }
+
+    let lines = scanlines(image, &palette);
+    let image_data = filter(
+        &lines,
+        if palette.is_some() { 1 } else { 3 },
+        palette.is_none(),
+    );
+    writer.write(&chunk_types::IDAT, &compress(&image_data)?);
+
+    writer.write(&chunk_types::IEND, &[]);
+
+    Ok(writer.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png;
+
+    fn round_trip(image: &Image<RGBColor>, text: &[(&str, &str)]) -> (Image<RGBColor>, Metadata) {
+        let mut metadata = Metadata::new();
+        let decoded = png::parse(encode(image, text).unwrap(), &mut metadata).unwrap();
+        (decoded, metadata)
+    }
+
+    // 400 different colors, too many for a palette
+    fn gradient() -> Image<RGBColor> {
+        ImageBuffer::from_fn(20, 20, |x, y| (x as u8 * 12, y as u8 * 12, 255))
+    }
+
+    #[test]
+    fn rgb() {
+        let image = gradient();
+        let (decoded, metadata) = round_trip(&image, &[]);
+        assert_eq!(metadata.palette(), &None);
+        assert_eq!(decoded, image);
+    }
+
+    #[test]
+    fn palette() {
+        // 1, 2, 4 and 8 bit indices, with widths that don't fill the last byte of a row
+        for &colors in [2, 3, 5, 17].iter() {
+            let image = ImageBuffer::from_fn(7, 3, |x, y| {
+                let i = (x + y * 7) % colors;
+                (i as u8 * 10, 100, 200)
+            });
+            let (decoded, metadata) = round_trip(&image, &[]);
+            assert_eq!(metadata.palette().as_ref().map(|p| p.len()), Some(colors));
+            assert_eq!(decoded, image);
+        }
+    }
+
+    #[test]
+    fn transparent() {
+        let mut image = gradient();
+        image[(3, 4)] = (0, 0, 0);
+        let (decoded, _) = round_trip(&image, &[]);
+        assert_eq!(decoded, image);
+
+        let image = ImageBuffer::from_fn(3, 2, |x, y| if x == y { (0, 0, 0) } else { (9, 8, 7) });
+        let (decoded, metadata) = round_trip(&image, &[]);
+        assert_eq!(metadata.palette().as_ref().map(|p| p.len()), Some(2));
+        assert_eq!(decoded, image);
+    }
+
+    #[test]
+    fn text() {
+        let image = ImageBuffer::new(1, 1, (1, 2, 3));
+        let (_, metadata) = round_trip(&image, &[("Title", "Caf\u{e9}")]);
+        let text = &metadata.text()[0];
+        assert_eq!(
+            (text.key.as_str(), text.text.as_str()),
+            ("Title", "Caf\u{e9}")
+        );
+    }
+
+    #[test]
+    fn invalid_text() {
+        let image = ImageBuffer::new(1, 1, (1, 2, 3));
+        assert!(encode(&image, &[("", "empty keyword")]).is_err());
+        assert!(encode(&image, &[("Title", "\u{2603}")]).is_err());
+    }
+}
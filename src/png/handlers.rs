@@ -0,0 +1,179 @@
+use crate::common::*;
+use crate::exif;
+use crate::png::chunks::*;
+use std::collections::HashMap;
+use std::io;
+
+// Receives the data of a chunk, and stores whatever it parses in the metadata. An error stops
+// the image from being decoded, unless it is decoded leniently, when the chunk is skipped
+pub type ChunkHandler = Box<dyn Fn(&[u8], &mut Metadata) -> io::Result<()>>;
+
+// Maps chunk types to the handler that parses them. The default set handles all the standard
+// ancillary chunks that are supported, and callers can register their own for private chunks:
+//
+//     let mut options = png::ParseOptions::default();
+//     options.handlers.register(*b"buIl", |data, metadata| {
+//         metadata.set_extension("build_id", String::from_utf8_lossy(data).into_owned());
+//         Ok(())
+//     });
+//
+// Chunks without a handler are skipped
+pub struct ChunkHandlers {
+    handlers: HashMap<[u8; 4], ChunkHandler>,
+}
+
+impl ChunkHandlers {
+    // Without any handlers, only the critical chunks are read
+    pub fn empty() -> ChunkHandlers {
+        ChunkHandlers {
+            handlers: HashMap::new(),
+        }
+    }
+
+    // Replaces the handler for the chunk type if there already is one
+    pub fn register<F>(&mut self, chunk_type: [u8; 4], handler: F)
+    where
+        F: Fn(&[u8], &mut Metadata) -> io::Result<()> + 'static,
+    {
+        self.handlers.insert(chunk_type, Box::new(handler));
+    }
+
+    pub fn unregister(&mut self, chunk_type: [u8; 4]) {
+        self.handlers.remove(&chunk_type);
+    }
+
+    pub fn get(&self, chunk_type: &[u8]) -> Option<&ChunkHandler> {
+        let mut key = [0; 4];
+        key.copy_from_slice(chunk_type);
+        self.handlers.get(&key)
+    }
+}
+
+// bKGD and tRNS have a sample for each color channel, or a palette index or alpha values
+fn check_length(chunk: &str, data: &[u8], metadata: &Metadata) -> io::Result<()> {
+    let valid = match metadata.color_type() {
+        ColorType::Gray | ColorType::GrayA => data.len() == 2,
+        ColorType::RGB | ColorType::RGBA => data.len() == 6,
+        ColorType::Palette if chunk == "bKGD" => data.len() == 1,
+        ColorType::Palette => {
+            !data.is_empty() && data.len() <= metadata.palette().as_ref().map_or(256, Vec::len)
+        }
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} chunk has the wrong length for color type {:?}: {} bytes",
+                chunk,
+                metadata.color_type(),
+                data.len()
+            ),
+        ))
+    }
+}
+
+impl Default for ChunkHandlers {
+    fn default() -> ChunkHandlers {
+        let mut handlers = ChunkHandlers::empty();
+
+        handlers.register(chunk_types::tRNS, |data, metadata| {
+            check_length("tRNS", data, metadata)?;
+            let trns_chunk = ancillary::parse_trns(data, metadata)?;
+            metadata.set_alpha(trns_chunk);
+            Ok(())
+        });
+        handlers.register(chunk_types::tIME, |data, metadata| {
+            if data.len() >= 7 {
+                metadata.set_time(ancillary::TIMEChunk::parse(data));
+            }
+            Ok(())
+        });
+        handlers.register(chunk_types::tEXt, |data, metadata| {
+            metadata.add_text(ancillary::TextChunk::parse_text(data)?);
+            Ok(())
+        });
+        handlers.register(chunk_types::zTXt, |data, metadata| {
+            metadata.add_text(ancillary::TextChunk::parse_compressed_text(data)?);
+            Ok(())
+        });
+        handlers.register(chunk_types::iTXt, |data, metadata| {
+            metadata.add_text(ancillary::TextChunk::parse_international_text(data)?);
+            Ok(())
+        });
+        handlers.register(chunk_types::pHYs, |data, metadata| {
+            metadata.set_physical(ancillary::PHYsChunk::parse(data)?);
+            Ok(())
+        });
+        handlers.register(chunk_types::sBIT, |data, metadata| {
+            let sbit = ancillary::SBITChunk::parse(data, metadata)?;
+            metadata.set_significant_bits(sbit);
+            Ok(())
+        });
+        handlers.register(chunk_types::hIST, |data, metadata| {
+            let hist = ancillary::HISTChunk::parse(data, metadata)?;
+            metadata.set_histogram(hist);
+            Ok(())
+        });
+        handlers.register(chunk_types::sPLT, |data, metadata| {
+            metadata.add_suggested_palette(ancillary::SPLTChunk::parse(data)?);
+            Ok(())
+        });
+        handlers.register(chunk_types::eXIf, |data, metadata| {
+            metadata.set_exif(exif::parse(data)?);
+            Ok(())
+        });
+        handlers.register(chunk_types::bKGD, |data, metadata| {
+            check_length("bKGD", data, metadata)?;
+            let bkgd = ancillary::parse_bkgd_chunk(data, metadata)?;
+            metadata.set_bkgd(bkgd);
+            Ok(())
+        });
+
+        handlers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handle(chunk_type: &[u8; 4], data: &[u8], metadata: &mut Metadata) -> io::Result<()> {
+        ChunkHandlers::default().get(chunk_type).unwrap()(data, metadata)
+    }
+
+    fn metadata(color_type: ColorType) -> Metadata {
+        let mut metadata = Metadata::new();
+        metadata.set_image_info(1, 1, 8, color_type);
+        metadata.set_palette(vec![(1, 2, 3), (4, 5, 6)]);
+        metadata
+    }
+
+    #[test]
+    fn wrong_length() {
+        let cases: [(&[u8; 4], ColorType, &[u8]); 8] = [
+            (&chunk_types::bKGD, ColorType::Gray, &[1]),
+            (&chunk_types::bKGD, ColorType::RGB, &[0, 1, 0, 2]),
+            (&chunk_types::bKGD, ColorType::Palette, &[]),
+            (&chunk_types::bKGD, ColorType::Palette, &[0, 1]),
+            (&chunk_types::tRNS, ColorType::Gray, &[1]),
+            (&chunk_types::tRNS, ColorType::RGB, &[0, 1, 0, 2, 0]),
+            (&chunk_types::tRNS, ColorType::Palette, &[]),
+            (&chunk_types::tRNS, ColorType::Palette, &[0, 0, 0]),
+        ];
+        for (chunk_type, color_type, data) in cases {
+            let mut metadata = metadata(color_type);
+            let error = handle(chunk_type, data, &mut metadata).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn palette_index_past_the_end() {
+        let mut metadata = metadata(ColorType::Palette);
+        assert!(handle(&chunk_types::bKGD, &[2], &mut metadata).is_err());
+        handle(&chunk_types::bKGD, &[1], &mut metadata).unwrap();
+        assert_eq!(metadata.explicit_bkgd(), Some(&(4, 5, 6)));
+    }
+}
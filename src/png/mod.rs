@@ -1,60 +1,136 @@
 pub mod chunks;
+mod encoder;
+mod handlers;
 mod parse_image;
+pub mod validate;
 
 use crate::common::*;
 use crate::crc::CRCHandler;
+use crate::inflate::Inflater;
 use chunks::*;
-use libflate::zlib::Decoder;
+pub use encoder::encode;
+#[cfg(test)]
+pub(crate) use encoder::{compress, ihdr_data, ChunkWriter};
+pub use handlers::{ChunkHandler, ChunkHandlers};
 use parse_image::parse_image;
 use std::io;
-use std::io::prelude::*;
-use std::io::{Error, ErrorKind, Result};
-use std::str;
+use std::io::{Error, ErrorKind};
 
-pub fn parse(buffer: Vec<u8>, metadata: &mut Metadata) -> io::Result<Image<RGBColor>> {
+// PNG file signature
+pub static SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+// Rows that could not be recovered in lenient mode are filled with this
+pub static MISSING_ROW_COLOR: RGBColor = (255, 0, 255);
+
+// Image data may decompress to a little more than IHDR says it should, as some encoders pad the
+// end of it
+const IMAGE_DATA_SLACK: usize = 64 * 1024;
+
+#[derive(Default)]
+pub struct ParseOptions {
+    // Recover as much of a damaged image as possible, printing warnings instead of failing
+    pub lenient: bool,
+    // Don't verify chunk CRCs, which is only worth it for large files that are trusted
+    pub skip_crc: bool,
+    // Parsers for the ancillary chunks, and any private chunks the caller wants to read
+    pub handlers: ChunkHandlers,
+}
+
+fn warn(message: String) {
+    eprintln!("Warning: {}", message);
+}
+
+// Reads all the chunks, filling in the metadata. The contents of the IDAT chunks are passed to
+// image_data as they are read, with the metadata read so far
+fn parse_chunks(
+    buffer: &[u8],
+    metadata: &mut Metadata,
+    options: &ParseOptions,
+    mut image_data: impl FnMut(&[u8], &Metadata),
+) -> io::Result<()> {
     let mut i = 8;
     let crc_handler = CRCHandler::new();
 
     let mut parsed_first = false;
-    let mut zlib_stream: Vec<u8> = Vec::new();
 
     loop {
+        if i + 8 > buffer.len() {
+            if options.lenient && parsed_first {
+                warn("PNG file ended before the IEND chunk".to_owned());
+                break;
+            }
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "PNG file ended before the IEND chunk",
+            ));
+        }
+
+        let chunk_start = i;
         let chunk_length = from_bytes_u32(&buffer[i..i + 4]) as usize;
         i += 4;
         let crc_chunk_start = i;
         let chunk_type = &buffer[i..i + 4];
         i += 4;
-        print!(
-            "Found Chunk with size: {}.\tChunk Type: {}",
-            chunk_length,
-            str::from_utf8(chunk_type).unwrap()
-        );
+
+        if i + chunk_length + 4 > buffer.len() {
+            if options.lenient && parsed_first {
+                warn(format!(
+                    "Chunk {} is truncated",
+                    String::from_utf8_lossy(chunk_type)
+                ));
+                // Whatever is left of the image data can still be partially decoded
+                if chunk_type == chunk_types::IDAT {
+                    image_data(&buffer[i.min(buffer.len())..], metadata);
+                }
+                break;
+            }
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("Chunk {} is truncated", String::from_utf8_lossy(chunk_type)),
+            ));
+        }
+
         let chunk_data = &buffer[i..i + chunk_length];
         i += chunk_length;
         let crc = from_bytes_u32(&buffer[i..i + 4]);
-        match crc_handler.verify(crc, &buffer[crc_chunk_start..i]) {
-            Err(calc_crc) => {
-                return Err(Error::new(
-                    ErrorKind::InvalidData,
-                    format!(
-                        "Invalid Chunk; CRC didnt match -> got: {}   calculated: {}",
-                        crc, calc_crc,
-                    ),
-                ));
+        let crc_result = if options.skip_crc {
+            None
+        } else {
+            Some(crc_handler.verify(crc, &buffer[crc_chunk_start..i]))
+        };
+
+        let mut info = ChunkInfo {
+            chunk_type: [0; 4],
+            offset: chunk_start,
+            length: chunk_length,
+            crc,
+            crc_valid: crc_result.map(|result| result.is_ok()),
+        };
+        info.chunk_type.copy_from_slice(chunk_type);
+        metadata.add_chunk(info);
+
+        if let Some(Err(calc_crc)) = crc_result {
+            let message = format!(
+                "Invalid Chunk {}; CRC didnt match -> got: {}   calculated: {}",
+                String::from_utf8_lossy(chunk_type),
+                crc,
+                calc_crc,
+            );
+            if options.lenient {
+                warn(message);
+            } else {
+                return Err(Error::new(ErrorKind::InvalidData, message));
             }
-            _ => {}
         };
         // i incremented after crc check because crc bytes shouldnt be included in the crc check
         i += 4;
 
-        print!("\tCRC: {}", crc);
-
         if !parsed_first && chunk_type != chunk_types::IHDR {
             return Err(Error::new(
                 ErrorKind::InvalidData,
                 format!(
                     "First chunk needs to be IHDR, got: {}",
-                    str::from_utf8(chunk_type).unwrap()
+                    String::from_utf8_lossy(chunk_type)
                 ),
             ));
         } else {
@@ -63,93 +139,137 @@ pub fn parse(buffer: Vec<u8>, metadata: &mut Metadata) -> io::Result<Image<RGBCo
 
         // Is Upper case => Important, cannot be ignored
         if chunk_type[0] & (1 << 5) == 0 {
-            println!("\t\tIMP");
             if chunk_type == chunk_types::IHDR {
                 metadata.add_ihdr(ihdr::IHDRChunk::parse(chunk_data)?);
-
-                println!("Image size: {}x{}", metadata.width(), metadata.height());
-                println!("Image color type: {:?}", metadata.color_type());
-                println!("Image pixel size: {}", metadata.pixel_size());
             } else if chunk_type == chunk_types::PLTE {
                 let plte_chunk = plte::PLTEChunk::parse(chunk_data, chunk_length);
-
-                println!("Palette length: {}", plte_chunk.length);
-                println!("Palette Colors: {:?}", plte_chunk.colors);
-
                 metadata.set_palette(plte_chunk.colors);
             } else if chunk_type == chunk_types::IDAT {
-                zlib_stream.extend(chunk_data.iter());
+                image_data(chunk_data, metadata);
             } else if chunk_type == chunk_types::IEND {
                 break;
-            } else {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    format!(
-                        "Unknown chunk type: {}",
-                        str::from_utf8(chunk_type).unwrap()
-                    ),
+            } else if let Some(handler) = options.handlers.get(chunk_type) {
+                // Private critical chunks can be read by registering a handler
+                handler(chunk_data, metadata)?;
+            } else if options.lenient {
+                warn(format!(
+                    "Skipping unknown chunk type: {}",
+                    String::from_utf8_lossy(chunk_type)
                 ));
+            } else {
+                return Err(Error::other(format!(
+                    "Unknown chunk type: {}",
+                    String::from_utf8_lossy(chunk_type)
+                )));
             }
-        } else {
-            if chunk_type == chunk_types::tRNS {
-                match ancillary::parse_trns(chunk_data, &metadata) {
-                    Ok(trns_chunk) => metadata.set_alpha(trns_chunk),
-                    Err(e) => eprintln!("{}", e),
+        } else if let Some(handler) = options.handlers.get(chunk_type) {
+            if let Err(e) = handler(chunk_data, metadata) {
+                if options.lenient {
+                    warn(format!(
+                        "Skipping damaged {} chunk: {}",
+                        String::from_utf8_lossy(chunk_type),
+                        e
+                    ));
+                } else {
+                    return Err(e);
                 }
-            } else if chunk_type == chunk_types::tIME {
-                let time_chunk = ancillary::TIMEChunk::parse(chunk_data);
-
-                print!("\nLast Changed: {}", time_chunk);
-            } else if chunk_type == chunk_types::tEXt {
-                match ancillary::TextChunk::parse(ancillary::TextChunk::split(chunk_data)) {
-                    Ok(text_chunk) => {
-                        if text_chunk.key.len() > 0 {
-                            print!("\n{}: {}", text_chunk.key, text_chunk.text);
-                        }
-                    }
-                    Err(e) => eprintln!("{}", e),
-                };
-            } else if chunk_type == chunk_types::zTXt {
-                let (keyword_chunk, text_chunk) = ancillary::TextChunk::split(chunk_data);
-
-                // Ideally errors should be just printed here, instead of programme ending
-                let mut decoder = Decoder::new(&text_chunk[..])?;
-                let mut text_chunk = Vec::new();
-                decoder.read_to_end(&mut text_chunk)?;
-
-                match ancillary::TextChunk::parse((keyword_chunk, &text_chunk[..])) {
-                    Ok(text_chunk) => {
-                        if text_chunk.key.len() > 0 {
-                            print!("\n{}: {}", text_chunk.key, text_chunk.text);
-                        }
-                    }
-                    Err(e) => eprintln!("{}", e),
-                };
-            } else if chunk_type == chunk_types::bKGD {
-                let bkgd = match ancillary::parse_bkgd_chunk(chunk_data, &metadata) {
-                    Ok(bkgd) => bkgd,
-                    Err(e) => {
-                        eprintln!("{}", e);
-                        (0, 0, 0)
-                    }
-                };
-
-                print!("\nGot backround: {:?}", bkgd);
-
-                metadata.set_bkgd(bkgd);
             }
+        }
+    }
+
+    Ok(())
+}
 
-            println!("");
+// Reads the structure and metadata of the image without decoding the pixels
+pub fn inspect(buffer: &[u8], metadata: &mut Metadata, options: &ParseOptions) -> io::Result<()> {
+    parse_chunks(buffer, metadata, options, |_, _| {})
+}
+
+pub fn parse(buffer: Vec<u8>, metadata: &mut Metadata) -> io::Result<Image<RGBColor>> {
+    parse_with_options(buffer, metadata, &ParseOptions::default())
+}
+
+pub fn parse_with_options(
+    buffer: Vec<u8>,
+    metadata: &mut Metadata,
+    options: &ParseOptions,
+) -> io::Result<Image<RGBColor>> {
+    // Decompressing stops at the first error, keeping what was decompressed before it
+    let mut inflater = Inflater::new();
+    let mut inflate_error = None;
+    parse_chunks(&buffer, metadata, options, |data, metadata| {
+        // IHDR comes before any IDAT, so the size of the image data is known by now. Anything
+        // much larger than it is a decompression bomb
+        let size: usize = ihdr::row_layout(
+            metadata.width(),
+            metadata.height(),
+            metadata.color_type(),
+            metadata.bit_depth(),
+            metadata.interlaced(),
+        )
+        .iter()
+        .map(|(row_bytes, rows)| row_bytes * rows)
+        .sum();
+        inflater.set_limit(size + IMAGE_DATA_SLACK);
+
+        if inflate_error.is_none() {
+            inflate_error = inflater.feed(data).err();
+        }
+    })?;
+
+    if metadata.interlaced() {
+        return Err(Error::other("Interlacing is currently unsupported"));
+    }
+
+    let result = match inflate_error {
+        Some(e) => Err(e),
+        None => inflater.finish(),
+    };
+    if let Err(e) = result {
+        if options.lenient {
+            warn(format!(
+                "Image data is damaged, decoded {} bytes: {}",
+                inflater.output().len(),
+                e
+            ));
+        } else {
+            return Err(e);
         }
     }
 
-    println!("got {} bytes of zlib data", zlib_stream.len());
+    parse_image(inflater.into_output(), metadata, options.lenient)
+}
 
-    let mut decoder = Decoder::new(&zlib_stream[..])?;
-    let mut image_data = Vec::new();
-    decoder.read_to_end(&mut image_data)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    println!("got {} bytes of image data", image_data.len());
+    // A 1x1 RGB image with a bKGD chunk cut down to one byte
+    fn truncated_bkgd() -> Vec<u8> {
+        let mut writer = ChunkWriter::new();
+        writer.write(&chunk_types::IHDR, &ihdr_data(1, 1, 8, 2));
+        writer.write(&chunk_types::bKGD, &[0]);
+        writer.write(&chunk_types::IDAT, &compress(&[0, 10, 20, 30]).unwrap());
+        writer.write(&chunk_types::IEND, &[]);
+        writer.finish()
+    }
 
-    Ok(parse_image(image_data, &metadata)?)
+    #[test]
+    fn damaged_ancillary_chunk() {
+        let mut metadata = Metadata::new();
+        let error = parse(truncated_bkgd(), &mut metadata).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn lenient_skips_damaged_ancillary_chunk() {
+        let mut metadata = Metadata::new();
+        let options = ParseOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        let image = parse_with_options(truncated_bkgd(), &mut metadata, &options).unwrap();
+        assert_eq!(image[(0, 0)], (10, 20, 30));
+        assert_eq!(metadata.explicit_bkgd(), None);
+    }
 }
@@ -1,20 +1,73 @@
-use super::chunks::ancillary;
 use crate::common::*;
+use crate::png::MISSING_ROW_COLOR;
 use std::io::{Error, ErrorKind, Result};
 
-pub fn parse_image(mut image_data: Vec<u8>, metadata: &Metadata) -> Result<Image<RGBColor>> {
-    let mut image: Image<RGBColor> = Vec::new();
+// Fills the rows that could not be decoded in lenient mode
+fn fill_missing_rows(image: &mut Image<RGBColor>, metadata: &Metadata) {
+    image.resize_height(metadata.height() as usize, MISSING_ROW_COLOR);
+}
+
+pub fn parse_image(
+    mut image_data: Vec<u8>,
+    metadata: &Metadata,
+    lenient: bool,
+) -> Result<Image<RGBColor>> {
+    let mut image: Image<RGBColor> =
+        ImageBuffer::with_width(metadata.width() as usize, metadata.height() as usize);
 
     // Make sure px_size isnt zero from truncation
     let px_size = metadata.pixel_size().max(1) as usize;
 
-    let scanline_length = metadata.width() * px_size as u32 + 1;
+    // Bit depths below 8 are only allowed for gray and palette images. They pack multiple pixels
+    // into a byte, with each row padded to a whole byte
+    let row_length = if metadata.bit_depth() < 8 {
+        (metadata.width() * metadata.bit_depth() as u32).div_ceil(8)
+    } else {
+        metadata.width() * px_size as u32
+    };
+    let scanline_length = row_length + 1;
+
+    // tRNS is checked against the color type when it is parsed, but a handler could still have
+    // set one that doesn't match
+    let alpha = match (metadata.alpha(), metadata.color_type()) {
+        (Some(AlphaValue::Palette(_)), ColorType::Palette)
+        | (Some(AlphaValue::RGB(..)), ColorType::RGB)
+        | (Some(AlphaValue::Gray(_)), ColorType::Gray) => metadata.alpha().as_ref(),
+        (Some(_), color_type) => {
+            let message = format!("tRNS doesn't match the color type {:?}", color_type);
+            if !lenient {
+                return Err(Error::new(ErrorKind::InvalidData, message));
+            }
+            eprintln!("Warning: {}, so it is ignored", message);
+            None
+        }
+        (None, _) => None,
+    };
+
+    // Pixels whose palette index is past the end of the palette, in lenient mode
+    let mut missing_colors = 0;
 
     for i in 0..metadata.height() {
         let s = (i * scanline_length) as usize;
+        if s + scanline_length as usize > image_data.len() {
+            if !lenient {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "Image data is truncated",
+                ));
+            }
+            eprintln!(
+                "Warning: Image data is truncated, only {} of {} rows could be decoded",
+                i,
+                metadata.height()
+            );
+            fill_missing_rows(&mut image, metadata);
+            break;
+        }
+
         let filter_method = image_data[s];
         let s = s + 1;
-        let e = s + (metadata.width() as usize * px_size);
+        let e = s + row_length as usize;
         match filter_method {
             0 => {}
             1 => {
@@ -74,7 +127,14 @@ pub fn parse_image(mut image_data: Vec<u8>, metadata: &Metadata) -> Result<Image
                     image_data[x] = image_data[x].wrapping_add(paeth_predictor(left, top, topleft));
                 }
             }
-            _ => return Err(Error::new(ErrorKind::Other, "Unrecognised filter method")),
+            _ => {
+                if !lenient {
+                    return Err(Error::other("Unrecognised filter method"));
+                }
+                eprintln!("Warning: Unrecognised filter method in row {}", i);
+                fill_missing_rows(&mut image, metadata);
+                break;
+            }
         };
         let image_data = &image_data[s..e];
         let mut scanline = Vec::new();
@@ -84,24 +144,39 @@ pub fn parse_image(mut image_data: Vec<u8>, metadata: &Metadata) -> Result<Image
         let mut i = 0;
         while i < image_data.len() {
             match metadata.color_type() {
-                ColorType::Palette => {
-                    palette(&image_data[i..i + px_size], metadata, &mut scanline)?
-                }
+                ColorType::Palette => palette(
+                    &image_data[i..i + px_size],
+                    metadata,
+                    alpha,
+                    lenient.then_some(&mut missing_colors),
+                    &mut scanline,
+                )?,
                 ColorType::RGBA => rgba(&image_data[i..i + px_size], metadata, &mut scanline)?,
-                ColorType::RGB => rgb(&image_data[i..i + px_size], metadata, &mut scanline)?,
-                ColorType::Gray => gray(&image_data[i..i + px_size], metadata, &mut scanline)?,
+                ColorType::RGB => rgb(&image_data[i..i + px_size], metadata, alpha, &mut scanline)?,
+                ColorType::Gray => {
+                    gray(&image_data[i..i + px_size], metadata, alpha, &mut scanline)?
+                }
                 ColorType::GrayA => gray_a(&image_data[i..i + px_size], metadata, &mut scanline)?,
             };
             i += px_size;
         }
 
-        image.push(scanline);
+        // Drop the pixels decoded from the padding bits
+        scanline.truncate(metadata.width() as usize);
+        image.push_row(&scanline);
+    }
+
+    if missing_colors > 0 {
+        eprintln!(
+            "Warning: {} pixels have palette indices past the end of the palette",
+            missing_colors
+        );
     }
 
     Ok(image)
 }
 
-fn paeth_predictor(a: i32, b: i32, c: i32) -> u8 {
+pub fn paeth_predictor(a: i32, b: i32, c: i32) -> u8 {
     let p = a + b - c; // initial estimate
     let pa = (p - a).abs(); // distances to a, b, c
     let pb = (p - b).abs();
@@ -118,92 +193,88 @@ fn paeth_predictor(a: i32, b: i32, c: i32) -> u8 {
     }
 }
 
-fn apply_alpha(r: u8, g: u8, b: u8, a: u8) -> RGBColor {
-    let opacity = (a as f32) / 256.0;
+// Undoes any left shift of samples with fewer significant bits than the bit depth
+fn significant(metadata: &Metadata, channel: usize, value: u8) -> u8 {
+    match metadata.significant_bits() {
+        Some(sbit) => sbit.rescale(channel, value),
+        None => value,
+    }
+}
+
+fn palette_color((r, g, b): RGBColor, metadata: &Metadata) -> RGBColor {
     (
-        (r as f32 * opacity) as u8,
-        (g as f32 * opacity) as u8,
-        (b as f32 * opacity) as u8,
+        significant(metadata, 0, r),
+        significant(metadata, 1, g),
+        significant(metadata, 2, b),
     )
 }
 
-fn palette(image_data: &[u8], metadata: &Metadata, scanline: &mut Vec<RGBColor>) -> Result<()> {
+// Pixels with indices past the end of the palette are an error, unless missing is given, in
+// which case they are counted and shown in MISSING_ROW_COLOR
+fn palette(
+    image_data: &[u8],
+    metadata: &Metadata,
+    alpha: Option<&AlphaValue>,
+    missing: Option<&mut usize>,
+    scanline: &mut Vec<RGBColor>,
+) -> Result<()> {
     let pt = match metadata.palette() {
         Some(pt) => pt,
         None => return Err(Error::new(ErrorKind::NotFound, "Palette not found")),
     };
 
-    let alpha = match metadata.alpha() {
-        Some(alpha) => match alpha {
-            AlphaValue::Palette(alpha) => Some(alpha),
-            // Something has gone very wring, program must be aborted
-            _ => panic!("tNRS has been wrongly parsed"),
-        },
-        None => None,
+    let alpha = match alpha {
+        Some(AlphaValue::Palette(alpha)) => Some(alpha),
+        _ => None,
     };
 
-    // for all color.2 = 1: Ansi displays completely transparent if colour is set to (0, 0, 0) [at least for my terminal]
-    //      with rgb colour codes. This make sure that opaque black pizels will be put as black instead
-    //      of transparent
-    //      Blue is increased since its least receptive for the human eye
-    match metadata.bit_depth() {
-        1 => {
-            for i in 0..8 {
-                let i = (image_data[0] >> (7 - i) & 0b1) as usize;
-                let (r, g, mut b) = pt[i];
-                if is_transparent(r, g, b) {
-                    b = 1;
-                }
-                match alpha {
-                    Some(alpha) => scanline.push(apply_alpha(r, g, b, alpha[i])),
-                    None => scanline.push((r, g, b)),
-                }
-            }
-        }
-        2 => {
-            for i in 0..4 {
-                let i = (image_data[0] >> (6 - i * 2) & 0b11) as usize;
-                let (r, g, mut b) = pt[i];
-                if is_transparent(r, g, b) {
-                    b = 1;
-                }
-                match alpha {
-                    Some(alpha) => scanline.push(apply_alpha(r, g, b, alpha[i])),
-                    None => scanline.push((r, g, b)),
-                }
+    let bit_depth = metadata.bit_depth() as usize;
+    if ![1, 2, 4, 8].contains(&bit_depth) {
+        return Err(Error::new(ErrorKind::InvalidData, "invalid bit depth"));
+    }
+
+    // Depths below 8 pack several indices into the byte, starting from its most significant bits
+    let mut missing = missing;
+    let mask = ((1u16 << bit_depth) - 1) as u8;
+    for n in 0..8 / bit_depth {
+        let i = ((image_data[0] >> (8 - bit_depth * (n + 1))) & mask) as usize;
+        let color = match (pt.get(i), missing.as_deref_mut()) {
+            (Some(color), _) => *color,
+            (None, Some(missing)) => {
+                *missing += 1;
+                scanline.push(MISSING_ROW_COLOR);
+                continue;
             }
-        }
-        4 => {
-            for i in 0..2 {
-                let i = (image_data[0] >> (4 - i * 4) & 0b1111) as usize;
-                let (r, g, mut b) = pt[i];
-                if is_transparent(r, g, b) {
-                    b = 1;
-                }
-                match alpha {
-                    Some(alpha) => scanline.push(apply_alpha(r, g, b, alpha[i])),
-                    None => scanline.push((r, g, b)),
-                }
+            (None, None) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "Palette index {} is past the end of the palette, which has {} colors",
+                        i,
+                        pt.len()
+                    ),
+                ))
             }
+        };
+
+        // for all color.2 = 1: Ansi displays completely transparent if colour is set to (0, 0, 0) [at least for my terminal]
+        //      with rgb colour codes. This make sure that opaque black pizels will be put as black instead
+        //      of transparent
+        //      Blue is increased since its least receptive for the human eye
+        let (r, g, mut b) = palette_color(color, metadata);
+        if is_transparent(r, g, b) {
+            b = 1;
         }
-        8 => {
-            let i = image_data[0] as usize;
-            let (r, g, mut b) = pt[i];
-            if is_transparent(r, g, b) {
-                b = 1;
-            }
-            match alpha {
-                Some(alpha) => scanline.push(apply_alpha(r, g, b, alpha[i])),
-                None => scanline.push((r, g, b)),
-            }
+        match alpha {
+            Some(alpha) => scanline.push(rgba_color(r, g, b, alpha.get(i).copied().unwrap_or(255))),
+            None => scanline.push((r, g, b)),
         }
-        _ => return Err(Error::new(ErrorKind::InvalidData, "invalid bit depth")),
-    };
+    }
     Ok(())
 }
 
 fn rgba(image_data: &[u8], metadata: &Metadata, scanline: &mut Vec<RGBColor>) -> Result<()> {
-    let (r, g, mut b, a) = match metadata.bit_depth() {
+    let (r, g, b, a) = match metadata.bit_depth() {
         8 => (image_data[0], image_data[1], image_data[2], image_data[3]),
         16 => (
             (from_bytes_u16(&image_data[..2]) / 256) as u8,
@@ -213,6 +284,12 @@ fn rgba(image_data: &[u8], metadata: &Metadata, scanline: &mut Vec<RGBColor>) ->
         ),
         _ => return Err(Error::new(ErrorKind::InvalidData, "invalid bit depth")),
     };
+    let (r, g, mut b, a) = (
+        significant(metadata, 0, r),
+        significant(metadata, 1, g),
+        significant(metadata, 2, b),
+        significant(metadata, 3, a),
+    );
 
     // Ansi displays completely transparent if colour is set to (0, 0, 0) [at least for my terminal]
     // with rgb colour codes. This make sure that opaque black pizels will be put as black instead
@@ -222,12 +299,17 @@ fn rgba(image_data: &[u8], metadata: &Metadata, scanline: &mut Vec<RGBColor>) ->
         b = 1;
     }
 
-    scanline.push(apply_alpha(r, g, b, a));
+    scanline.push(rgba_color(r, g, b, a));
     Ok(())
 }
 
-fn rgb(image_data: &[u8], metadata: &Metadata, scanline: &mut Vec<RGBColor>) -> Result<()> {
-    let (r, g, mut b) = match metadata.bit_depth() {
+fn rgb(
+    image_data: &[u8],
+    metadata: &Metadata,
+    alpha: Option<&AlphaValue>,
+    scanline: &mut Vec<RGBColor>,
+) -> Result<()> {
+    let (r, g, b) = match metadata.bit_depth() {
         8 => (image_data[0], image_data[1], image_data[2]),
         16 => (
             (from_bytes_u16(&image_data[..2]) / 256) as u8,
@@ -237,38 +319,37 @@ fn rgb(image_data: &[u8], metadata: &Metadata, scanline: &mut Vec<RGBColor>) ->
         _ => return Err(Error::new(ErrorKind::InvalidData, "invalid bit depth")),
     };
 
+    // tRNS holds the stored sample values, so compare before rescaling
+    let is_transparent = match alpha {
+        Some(AlphaValue::RGB(ar, ag, ab)) => *ar == r && *ag == g && *ab == b,
+        _ => false,
+    };
+    if is_transparent {
+        scanline.push((0, 0, 0));
+        return Ok(());
+    }
+
     // Ansi displays completely transparent if colour is set to (0, 0, 0) [at least for my terminal]
     // with rgb colour codes. This make sure that opaque black pizels will be put as black instead
     // of transparent
     // Blue is increased since its least receptive for the human eye
-    if is_transparent(r, g, b) {
-        b = 1;
-    }
-
-    let is_transparent = match metadata.alpha() {
-        Some(alpha) => match alpha {
-            AlphaValue::RGB(ar, ag, ab) => *ar == r && *ag == g && *ab == b,
-            // Something has gone very wring, program must be aborted
-            _ => panic!("tNRS has been wrongly parsed"),
-        },
-        None => false,
-    };
-    if is_transparent {
-        scanline.push((0, 0, 0));
-    } else {
-        scanline.push((r, g, b));
-    };
+    scanline.push(opaque(
+        significant(metadata, 0, r),
+        significant(metadata, 1, g),
+        significant(metadata, 2, b),
+    ));
     Ok(())
 }
 
-fn gray(image_data: &[u8], metadata: &Metadata, scanline: &mut Vec<RGBColor>) -> Result<()> {
-    let alpha = match metadata.alpha() {
-        Some(alpha) => match alpha {
-            AlphaValue::Gray(alpha) => Some(alpha),
-            // Something has gone very wring, program must be aborted
-            _ => panic!("tNRS has been wrongly parsed"),
-        },
-        None => None,
+fn gray(
+    image_data: &[u8],
+    metadata: &Metadata,
+    alpha: Option<&AlphaValue>,
+    scanline: &mut Vec<RGBColor>,
+) -> Result<()> {
+    let alpha = match alpha {
+        Some(AlphaValue::Gray(alpha)) => Some(alpha),
+        _ => None,
     };
 
     // for all val = 1: Ansi displays completely transparent if colour is set to (0, 0, 0) [at least for my terminal]
@@ -282,6 +363,7 @@ fn gray(image_data: &[u8], metadata: &Metadata, scanline: &mut Vec<RGBColor>) ->
                     Some(alpha) => val == *alpha,
                     None => false,
                 };
+                val = significant(metadata, 0, val);
                 if val == 0 {
                     val = 1;
                 }
@@ -299,6 +381,7 @@ fn gray(image_data: &[u8], metadata: &Metadata, scanline: &mut Vec<RGBColor>) ->
                     Some(alpha) => val == *alpha,
                     None => false,
                 };
+                val = significant(metadata, 0, val);
                 if val == 0 {
                     val = 1;
                 }
@@ -316,6 +399,7 @@ fn gray(image_data: &[u8], metadata: &Metadata, scanline: &mut Vec<RGBColor>) ->
                     Some(alpha) => val == *alpha,
                     None => false,
                 };
+                val = significant(metadata, 0, val);
                 if val == 0 {
                     val = 1;
                 }
@@ -332,6 +416,7 @@ fn gray(image_data: &[u8], metadata: &Metadata, scanline: &mut Vec<RGBColor>) ->
                 Some(alpha) => val == *alpha,
                 None => false,
             };
+            val = significant(metadata, 0, val);
             if val == 0 {
                 val = 1;
             }
@@ -347,6 +432,7 @@ fn gray(image_data: &[u8], metadata: &Metadata, scanline: &mut Vec<RGBColor>) ->
                 Some(alpha) => val == *alpha,
                 None => false,
             };
+            val = significant(metadata, 0, val);
             if val == 0 {
                 val = 1;
             }
@@ -362,7 +448,7 @@ fn gray(image_data: &[u8], metadata: &Metadata, scanline: &mut Vec<RGBColor>) ->
 }
 
 fn gray_a(image_data: &[u8], metadata: &Metadata, scanline: &mut Vec<RGBColor>) -> Result<()> {
-    let (mut val, alpha) = match metadata.bit_depth() {
+    let (val, alpha) = match metadata.bit_depth() {
         8 => (image_data[0], image_data[1]),
         16 => (
             (from_bytes_u16(&image_data[2..4]) / 256) as u8,
@@ -370,6 +456,10 @@ fn gray_a(image_data: &[u8], metadata: &Metadata, scanline: &mut Vec<RGBColor>)
         ),
         _ => return Err(Error::new(ErrorKind::InvalidData, "invalid bit depth")),
     };
+    let (mut val, alpha) = (
+        significant(metadata, 0, val),
+        significant(metadata, 1, alpha),
+    );
 
     // Ansi displays completely transparent if colour is set to (0, 0, 0) [at least for my terminal]
     // with rgb colour codes. This make sure that opaque black pizels will be put as black instead
@@ -378,6 +468,6 @@ fn gray_a(image_data: &[u8], metadata: &Metadata, scanline: &mut Vec<RGBColor>)
         val = 1;
     }
 
-    scanline.push(apply_alpha(val, val, val, alpha));
+    scanline.push(rgba_color(val, val, val, alpha));
     Ok(())
 }
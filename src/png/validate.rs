@@ -0,0 +1,593 @@
+use crate::common::*;
+use crate::crc::CRCHandler;
+use crate::inflate;
+use crate::png::chunks::{chunk_types, ihdr};
+use crate::png::SIGNATURE;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+pub struct Issue {
+    pub severity: Severity,
+    // Offset of the chunk the issue was found in, if any
+    pub offset: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        match self.offset {
+            Some(offset) => write!(
+                f,
+                "{} at offset 0x{:05x}: {}",
+                severity, offset, self.message
+            ),
+            None => write!(f, "{}: {}", severity, self.message),
+        }
+    }
+}
+
+struct Issues {
+    list: Vec<Issue>,
+    offset: Option<usize>,
+}
+
+impl Issues {
+    fn error(&mut self, message: String) {
+        self.list.push(Issue {
+            severity: Severity::Error,
+            offset: self.offset,
+            message,
+        });
+    }
+
+    fn warning(&mut self, message: String) {
+        self.list.push(Issue {
+            severity: Severity::Warning,
+            offset: self.offset,
+            message,
+        });
+    }
+}
+
+fn contains(list: &[&[u8; 4]], chunk_type: &[u8; 4]) -> bool {
+    list.contains(&chunk_type)
+}
+
+// Chunks that can appear at most once
+fn is_singleton(chunk_type: &[u8; 4]) -> bool {
+    contains(
+        &[
+            &chunk_types::IHDR,
+            &chunk_types::PLTE,
+            &chunk_types::IEND,
+            &chunk_types::tRNS,
+            &chunk_types::bKGD,
+            &chunk_types::cHRM,
+            &chunk_types::gAMA,
+            &chunk_types::iCCP,
+            &chunk_types::sBIT,
+            &chunk_types::sRGB,
+            &chunk_types::pHYs,
+            &chunk_types::hIST,
+            &chunk_types::tIME,
+            &chunk_types::eXIf,
+        ],
+        chunk_type,
+    )
+}
+
+fn must_precede_plte(chunk_type: &[u8; 4]) -> bool {
+    contains(
+        &[
+            &chunk_types::cHRM,
+            &chunk_types::gAMA,
+            &chunk_types::iCCP,
+            &chunk_types::sBIT,
+            &chunk_types::sRGB,
+        ],
+        chunk_type,
+    )
+}
+
+fn must_follow_plte(chunk_type: &[u8; 4]) -> bool {
+    contains(
+        &[&chunk_types::tRNS, &chunk_types::bKGD, &chunk_types::hIST],
+        chunk_type,
+    )
+}
+
+fn must_precede_idat(chunk_type: &[u8; 4]) -> bool {
+    must_precede_plte(chunk_type)
+        || must_follow_plte(chunk_type)
+        || contains(&[&chunk_types::pHYs, &chunk_types::sPLT], chunk_type)
+}
+
+fn check_image_data(zlib_stream: &[u8], ihdr: &ihdr::IHDRChunk, issues: &mut Issues) {
+    let image_data = match inflate::decompress(zlib_stream) {
+        Ok(image_data) => image_data,
+        Err(e) => {
+            issues.error(format!("zlib stream is corrupt: {}", e));
+            return;
+        }
+    };
+
+    let layout = ihdr::row_layout(
+        ihdr.width(),
+        ihdr.height(),
+        &ihdr.color_type(),
+        ihdr.bit_depth(),
+        ihdr.interlace_method() == ihdr::InterlaceMethod::Adam7,
+    );
+    let expected: usize = layout
+        .iter()
+        .map(|(row_bytes, rows)| row_bytes * rows)
+        .sum();
+
+    if image_data.len() < expected {
+        issues.error(format!(
+            "image data is too short: got {} bytes, expected {}",
+            image_data.len(),
+            expected
+        ));
+    } else if image_data.len() > expected {
+        issues.warning(format!(
+            "{} bytes of extra image data after the last row",
+            image_data.len() - expected
+        ));
+    }
+
+    let mut i = 0;
+    for (row_bytes, rows) in layout {
+        for _ in 0..rows {
+            match image_data.get(i) {
+                Some(&filter) if filter > 4 => {
+                    issues.error(format!("invalid filter type {} at byte {}", filter, i));
+                    return;
+                }
+                Some(_) => i += row_bytes,
+                None => return,
+            }
+        }
+    }
+}
+
+// Checks the structure of a PNG file against the spec, without decoding the pixels
+pub fn validate(buffer: &[u8]) -> Vec<Issue> {
+    let mut issues = Issues {
+        list: Vec::new(),
+        offset: None,
+    };
+
+    if !buffer.starts_with(&SIGNATURE) {
+        issues.error("missing PNG signature".to_owned());
+        return issues.list;
+    }
+
+    let crc_handler = CRCHandler::new();
+
+    let mut i = 8;
+    let mut seen: Vec<[u8; 4]> = Vec::new();
+    let mut ihdr_chunk: Option<ihdr::IHDRChunk> = None;
+    let mut palette_len: Option<usize> = None;
+    let mut zlib_stream = Vec::new();
+    let mut idat_ended = false;
+    let mut iend_found = false;
+    let mut truncated = false;
+
+    while i < buffer.len() {
+        issues.offset = Some(i);
+
+        if i + 12 > buffer.len() {
+            issues.error("chunk header is truncated".to_owned());
+            truncated = true;
+            break;
+        }
+
+        let length = from_bytes_u32(&buffer[i..i + 4]) as usize;
+        let mut chunk_type = [0; 4];
+        chunk_type.copy_from_slice(&buffer[i + 4..i + 8]);
+        let name = String::from_utf8_lossy(&chunk_type).into_owned();
+
+        if length > i32::MAX as usize {
+            issues.error(format!("{} length {} exceeds 2^31 - 1", name, length));
+            truncated = true;
+            break;
+        }
+        if i + 12 + length > buffer.len() {
+            issues.error(format!("{} chunk is truncated", name));
+            truncated = true;
+            break;
+        }
+
+        let data = &buffer[i + 8..i + 8 + length];
+        let crc = from_bytes_u32(&buffer[i + 8 + length..i + 12 + length]);
+        // The CRC covers the chunk type and data
+        let crc_result = crc_handler.verify(crc, &buffer[i + 4..i + 8 + length]);
+        i += 12 + length;
+
+        if !chunk_type.iter().all(|b| b.is_ascii_alphabetic()) {
+            issues.error(format!("invalid chunk name {:?}", name));
+        } else if chunk_type[2] & (1 << 5) != 0 {
+            issues.error(format!("reserved bit set in chunk name {}", name));
+        }
+
+        if let Err(calc_crc) = crc_result {
+            issues.error(format!(
+                "{} CRC mismatch: stored {:08x}, calculated {:08x}",
+                name, crc, calc_crc
+            ));
+        }
+
+        if seen.is_empty() && chunk_type != chunk_types::IHDR {
+            issues.error(format!("first chunk must be IHDR, got {}", name));
+        }
+
+        if is_singleton(&chunk_type) && seen.contains(&chunk_type) {
+            issues.error(format!("duplicate {} chunk", name));
+        }
+
+        let idat_started = seen.contains(&chunk_types::IDAT);
+        if chunk_type == chunk_types::IDAT {
+            if idat_ended {
+                issues.error("IDAT chunks must be consecutive".to_owned());
+            }
+            zlib_stream.extend_from_slice(data);
+        } else if idat_started {
+            idat_ended = true;
+        }
+
+        if must_precede_plte(&chunk_type) && palette_len.is_some() {
+            issues.error(format!("{} must come before PLTE", name));
+        }
+        if must_precede_idat(&chunk_type) && idat_started {
+            issues.error(format!("{} must come before IDAT", name));
+        }
+
+        let color_type = ihdr_chunk.as_ref().map(|ihdr| ihdr.color_type());
+        let is_palette = matches!(color_type, Some(ColorType::Palette));
+
+        if must_follow_plte(&chunk_type) && is_palette && palette_len.is_none() {
+            issues.error(format!("{} must come after PLTE", name));
+        }
+
+        if chunk_type == chunk_types::IHDR {
+            if length != 13 {
+                issues.error(format!("IHDR length is {}, expected 13", length));
+            } else {
+                match ihdr::IHDRChunk::parse(data) {
+                    Ok(ihdr) => {
+                        if ihdr.width() == 0 || ihdr.height() == 0 {
+                            issues.error(format!(
+                                "invalid image dimensions {}x{}",
+                                ihdr.width(),
+                                ihdr.height()
+                            ));
+                        } else if seen.is_empty() {
+                            ihdr_chunk = Some(ihdr);
+                        }
+                    }
+                    Err(e) => issues.error(format!("invalid IHDR: {}", e)),
+                }
+            }
+        } else if chunk_type == chunk_types::PLTE {
+            let entries = length / 3;
+            if idat_started {
+                issues.error("PLTE must come before IDAT".to_owned());
+            }
+            if !length.is_multiple_of(3) || entries == 0 || entries > 256 {
+                issues.error(format!("invalid PLTE length {}", length));
+            }
+            match (&color_type, &ihdr_chunk) {
+                (Some(ColorType::Gray), _) | (Some(ColorType::GrayA), _) => {
+                    issues.error("PLTE is not allowed in grayscale images".to_owned())
+                }
+                (Some(ColorType::Palette), Some(ihdr)) if entries > 1 << ihdr.bit_depth() => issues
+                    .error(format!(
+                        "PLTE has {} entries, more than bit depth {} can index",
+                        entries,
+                        ihdr.bit_depth()
+                    )),
+                _ => {}
+            }
+            palette_len = Some(entries);
+        } else if chunk_type == chunk_types::tRNS {
+            match color_type {
+                Some(ColorType::Gray) if length != 2 => {
+                    issues.error(format!("invalid tRNS length {}", length))
+                }
+                Some(ColorType::RGB) if length != 6 => {
+                    issues.error(format!("invalid tRNS length {}", length))
+                }
+                Some(ColorType::Palette) if length > palette_len.unwrap_or(256) => {
+                    issues.error("tRNS has more entries than PLTE".to_owned())
+                }
+                Some(ColorType::GrayA) | Some(ColorType::RGBA) => {
+                    issues.error("tRNS is not allowed in images with an alpha channel".to_owned())
+                }
+                _ => {}
+            }
+        } else if chunk_type == chunk_types::hIST {
+            if !is_palette {
+                issues.warning("hIST is only meaningful in palette images".to_owned());
+            } else if palette_len.is_some_and(|len| length != len * 2) {
+                issues.error("hIST must have one entry per PLTE entry".to_owned());
+            }
+        } else if chunk_type == chunk_types::sRGB {
+            if seen.contains(&chunk_types::iCCP) {
+                issues.warning("both iCCP and sRGB are present".to_owned());
+            }
+        } else if chunk_type == chunk_types::iCCP {
+            if seen.contains(&chunk_types::sRGB) {
+                issues.warning("both iCCP and sRGB are present".to_owned());
+            }
+        } else if chunk_type == chunk_types::IEND {
+            if length != 0 {
+                issues.error(format!("IEND length is {}, expected 0", length));
+            }
+            iend_found = true;
+        } else if chunk_type[0] & (1 << 5) == 0 && chunk_type != chunk_types::IDAT {
+            issues.error(format!("unknown critical chunk {}", name));
+        }
+
+        if chunk_type == chunk_types::tEXt
+            || chunk_type == chunk_types::zTXt
+            || chunk_type == chunk_types::iTXt
+            || chunk_type == chunk_types::iCCP
+        {
+            match data.iter().position(|&b| b == 0) {
+                Some(len) if (1..=79).contains(&len) => {
+                    // Compression method, zTXt and iCCP always have one and iTXt only if flagged
+                    let method = if chunk_type == chunk_types::iTXt {
+                        if data.get(len + 1) == Some(&1) {
+                            data.get(len + 2)
+                        } else {
+                            None
+                        }
+                    } else if chunk_type == chunk_types::tEXt {
+                        None
+                    } else {
+                        data.get(len + 1)
+                    };
+                    if let Some(method) = method {
+                        if *method != 0 {
+                            issues.error(format!("unknown {} compression method {}", name, method));
+                        }
+                    }
+                }
+                _ => issues.error(format!("{} keyword must be 1-79 bytes", name)),
+            }
+        }
+
+        seen.push(chunk_type);
+
+        if iend_found {
+            break;
+        }
+    }
+
+    issues.offset = None;
+
+    if !truncated {
+        if !iend_found {
+            issues.error("missing IEND chunk".to_owned());
+        } else if i < buffer.len() {
+            issues.error(format!(
+                "{} bytes of trailing data after IEND",
+                buffer.len() - i
+            ));
+        }
+    }
+
+    if let Some(ihdr) = &ihdr_chunk {
+        if matches!(ihdr.color_type(), ColorType::Palette) && palette_len.is_none() {
+            issues.error("palette image has no PLTE chunk".to_owned());
+        }
+
+        // The image data of a truncated file is missing or cut short, which was already reported
+        if !truncated {
+            if !seen.contains(&chunk_types::IDAT) {
+                issues.error("no IDAT chunks".to_owned());
+            } else {
+                check_image_data(&zlib_stream, ihdr, &mut issues);
+            }
+        }
+    }
+
+    issues.list
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::{compress, ihdr_data, ChunkWriter};
+
+    // A 2x1 RGB image, with chunks inserted before IDAT and IEND
+    fn rgb_png(before_idat: &[(&[u8; 4], &[u8])], after_idat: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+        let mut writer = ChunkWriter::new();
+        writer.write(&chunk_types::IHDR, &ihdr_data(2, 1, 8, 2));
+        for (chunk_type, data) in before_idat {
+            writer.write(chunk_type, data);
+        }
+        writer.write(
+            &chunk_types::IDAT,
+            &compress(&[0, 1, 2, 3, 4, 5, 6]).unwrap(),
+        );
+        for (chunk_type, data) in after_idat {
+            writer.write(chunk_type, data);
+        }
+        writer.write(&chunk_types::IEND, &[]);
+        writer.finish()
+    }
+
+    fn messages(buffer: &[u8]) -> Vec<String> {
+        validate(buffer)
+            .into_iter()
+            .map(|issue| issue.message)
+            .collect()
+    }
+
+    #[test]
+    fn valid() {
+        assert!(validate(&rgb_png(&[(&chunk_types::gAMA, &[0, 0, 177, 143])], &[])).is_empty());
+    }
+
+    #[test]
+    fn chunk_order() {
+        let gama: &[u8] = &[0, 0, 177, 143];
+        assert_eq!(
+            messages(&rgb_png(&[], &[(&chunk_types::gAMA, gama)])),
+            ["gAMA must come before IDAT"]
+        );
+        assert_eq!(
+            messages(&rgb_png(
+                &[(&chunk_types::gAMA, gama), (&chunk_types::gAMA, gama)],
+                &[]
+            )),
+            ["duplicate gAMA chunk"]
+        );
+
+        // Palette images need their PLTE between the chunks that go before and after it
+        let mut writer = ChunkWriter::new();
+        writer.write(&chunk_types::IHDR, &ihdr_data(1, 1, 8, 3));
+        writer.write(&chunk_types::tRNS, &[0]);
+        writer.write(&chunk_types::PLTE, &[1, 2, 3]);
+        writer.write(&chunk_types::gAMA, gama);
+        writer.write(&chunk_types::IDAT, &compress(&[0, 0]).unwrap());
+        writer.write(&chunk_types::IEND, &[]);
+        assert_eq!(
+            messages(&writer.finish()),
+            ["tRNS must come after PLTE", "gAMA must come before PLTE"]
+        );
+    }
+
+    #[test]
+    fn split_image_data() {
+        let zlib_stream = compress(&[0, 1, 2, 3, 4, 5, 6]).unwrap();
+        let mut writer = ChunkWriter::new();
+        writer.write(&chunk_types::IHDR, &ihdr_data(2, 1, 8, 2));
+        writer.write(&chunk_types::IDAT, &zlib_stream[..4]);
+        writer.write(&chunk_types::tEXt, b"Title\0Test");
+        writer.write(&chunk_types::IDAT, &zlib_stream[4..]);
+        writer.write(&chunk_types::IEND, &[]);
+        assert_eq!(
+            messages(&writer.finish()),
+            ["IDAT chunks must be consecutive"]
+        );
+    }
+
+    #[test]
+    fn first_chunk() {
+        let mut buffer = SIGNATURE.to_vec();
+        let png = rgb_png(&[], &[]);
+        // The IDAT and IEND chunks, without IHDR
+        buffer.extend_from_slice(&png[8 + 25..]);
+        let messages = messages(&buffer);
+        assert_eq!(messages[0], "first chunk must be IHDR, got IDAT");
+    }
+
+    #[test]
+    fn crc_mismatch() {
+        let mut buffer = rgb_png(&[], &[]);
+        // The last byte of the IHDR CRC
+        buffer[8 + 24] ^= 1;
+        let issues = validate(&buffer);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].severity, Severity::Error);
+        assert_eq!(issues[0].offset, Some(8));
+        assert!(issues[0].message.starts_with("IHDR CRC mismatch"));
+        assert!(issues[0]
+            .to_string()
+            .starts_with("error at offset 0x00008: IHDR CRC"));
+    }
+
+    #[test]
+    fn missing_iend() {
+        let buffer = rgb_png(&[], &[]);
+        assert_eq!(
+            messages(&buffer[..buffer.len() - 12]),
+            ["missing IEND chunk"]
+        );
+
+        let mut trailing = buffer.clone();
+        trailing.extend_from_slice(b"junk");
+        assert_eq!(messages(&trailing), ["4 bytes of trailing data after IEND"]);
+
+        assert_eq!(
+            messages(&buffer[..buffer.len() - 4]),
+            ["chunk header is truncated"]
+        );
+        // Into the IDAT data
+        assert_eq!(
+            messages(&buffer[..buffer.len() - 20]),
+            ["IDAT chunk is truncated"]
+        );
+    }
+
+    #[test]
+    fn bad_ihdr() {
+        let with_ihdr = |data: &[u8]| {
+            let mut writer = ChunkWriter::new();
+            writer.write(&chunk_types::IHDR, data);
+            writer.write(&chunk_types::IEND, &[]);
+            messages(&writer.finish())
+        };
+
+        assert_eq!(
+            with_ihdr(&ihdr_data(0, 1, 8, 2)),
+            ["invalid image dimensions 0x1"]
+        );
+        assert_eq!(
+            with_ihdr(&ihdr_data(1, 1, 8, 2)[..12]),
+            ["IHDR length is 12, expected 13"]
+        );
+        let messages = with_ihdr(&ihdr_data(1, 1, 3, 2));
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].starts_with("invalid IHDR"));
+    }
+
+    #[test]
+    fn bad_image_data() {
+        let with_image_data = |image_data: &[u8]| {
+            let mut writer = ChunkWriter::new();
+            writer.write(&chunk_types::IHDR, &ihdr_data(2, 1, 8, 2));
+            writer.write(&chunk_types::IDAT, &compress(image_data).unwrap());
+            writer.write(&chunk_types::IEND, &[]);
+            let issues = validate(&writer.finish());
+            issues
+                .iter()
+                .map(|issue| (issue.severity, issue.message.clone()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            with_image_data(&[0, 1, 2, 3]),
+            [(
+                Severity::Error,
+                "image data is too short: got 4 bytes, expected 7".to_owned()
+            )]
+        );
+        assert_eq!(
+            with_image_data(&[0, 1, 2, 3, 4, 5, 6, 7]),
+            [(
+                Severity::Warning,
+                "1 bytes of extra image data after the last row".to_owned()
+            )]
+        );
+        assert_eq!(
+            with_image_data(&[5, 1, 2, 3, 4, 5, 6]),
+            [(
+                Severity::Error,
+                "invalid filter type 5 at byte 0".to_owned()
+            )]
+        );
+    }
+}
@@ -0,0 +1,318 @@
+// Draws images in the terminal. Effects are applied to the image before it gets here, so the
+// renderer only decides which characters and colors stand for its pixels
+use crate::common::*;
+use crate::effects::replace_with_bg;
+use crate::sizing::Layout;
+use std::io::{self, Error, ErrorKind, Write};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Renderer {
+    // ▀ with the top pixel as the foreground and the bottom one as the background
+    #[default]
+    Blocks,
+    // Characters of increasing density, two per pixel
+    Ascii,
+    // Dots of braille characters, 2x4 per cell
+    Braille,
+}
+
+// for ASCII
+const CHARS: [char; 32] = [
+    ' ', '.', '\'', '`', ',', ':', '-', '=', '~', '"', '+', '*', '>', '<', '}', '{', 'f', 'j', 'n',
+    'v', 'z', 'u', 'k', 'U', 'O', '#', 'M', 'W', '&', '%', '$', '@',
+];
+
+// Bit of each dot of a braille character, indexed by [y][x]
+const BRAILLE_DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+fn brightness(&(r, g, b): &RGBColor) -> usize {
+    (r as usize + g as usize + b as usize) / 3
+}
+
+// How the colors of the pixels are written
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Colors {
+    // Exact colors, for terminals with 24 bit color
+    #[default]
+    TrueColor,
+    // The 256 colors of xterm. Given a palette, such as a PNG's suggested palette, pixels are
+    // first matched to its closest color, so that only the colors it suggests are shown
+    Ansi256(Option<Vec<RGBColor>>),
+    // No colors at all
+    Monochrome,
+}
+
+fn distance(a: &RGBColor, b: &RGBColor) -> i32 {
+    let (dr, dg, db) = (
+        a.0 as i32 - b.0 as i32,
+        a.1 as i32 - b.1 as i32,
+        a.2 as i32 - b.2 as i32,
+    );
+    dr * dr + dg * dg + db * db
+}
+
+pub fn closest(palette: &[RGBColor], col: &RGBColor) -> Option<usize> {
+    (0..palette.len()).min_by_key(|&i| distance(&palette[i], col))
+}
+
+// Levels of each channel in the 6x6x6 color cube of the 256 colors
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+// Closest of the color cube (16 to 231) and the gray ramp (232 to 255). The first 16 colors
+// are left out, as terminal themes change them
+fn ansi256(col: &RGBColor) -> u8 {
+    let level = |value: u8| match value {
+        0..=47 => 0,
+        48..=114 => 1,
+        value => (value - 35) / 40,
+    };
+    let (r, g, b) = (level(col.0), level(col.1), level(col.2));
+    let cube = (
+        CUBE_LEVELS[r as usize],
+        CUBE_LEVELS[g as usize],
+        CUBE_LEVELS[b as usize],
+    );
+
+    let average = (col.0 as u32 + col.1 as u32 + col.2 as u32) / 3;
+    let step = (average.saturating_sub(3) / 10).min(23) as u8;
+    let value = 8 + step * 10;
+
+    if distance(&(value, value, value), col) < distance(&cube, col) {
+        232 + step
+    } else {
+        16 + 36 * r + 6 * g + b
+    }
+}
+
+impl Colors {
+    // Palettes are only known once the image is decoded, so are added with with_palette
+    pub fn parse(name: &str) -> io::Result<Colors> {
+        match name {
+            "truecolor" => Ok(Colors::TrueColor),
+            "256" => Ok(Colors::Ansi256(None)),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unknown colors: {}, expected truecolor or 256", name),
+            )),
+        }
+    }
+
+    // Limits 256 colors to the palette, other colors are kept as they are
+    pub fn with_palette(self, palette: Vec<RGBColor>) -> Colors {
+        match self {
+            Colors::Ansi256(None) if !palette.is_empty() => Colors::Ansi256(Some(palette)),
+            colors => colors,
+        }
+    }
+
+    // Parameters of the escape code after the 38 or 48 that says if it is the foreground or
+    // the background
+    fn code(&self, col: &RGBColor) -> String {
+        match self {
+            Colors::Ansi256(palette) => {
+                let col = match palette {
+                    Some(palette) => closest(palette, col).map_or(*col, |i| palette[i]),
+                    None => *col,
+                };
+                format!("5;{}", ansi256(&col))
+            }
+            _ => format!("2;{};{};{}", col.0, col.1, col.2),
+        }
+    }
+}
+
+impl Renderer {
+    pub fn parse(name: &str) -> io::Result<Renderer> {
+        match name {
+            "blocks" => Ok(Renderer::Blocks),
+            "ascii" => Ok(Renderer::Ascii),
+            "braille" => Ok(Renderer::Braille),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Unknown renderer: {}, expected one of blocks, ascii or braille",
+                    name
+                ),
+            )),
+        }
+    }
+
+    // Blocks need colors to be seen, so ASCII is used instead
+    pub fn without_color(self) -> Renderer {
+        match self {
+            Renderer::Blocks => Renderer::Ascii,
+            renderer => renderer,
+        }
+    }
+
+    // How many image pixels fit in the given number of cells
+    pub fn cells_to_pixels(&self, columns: usize, rows: usize) -> (usize, usize) {
+        match self {
+            Renderer::Blocks => (columns, rows * 2),
+            Renderer::Ascii => (columns / 2, rows),
+            Renderer::Braille => (columns * 2, rows * 4),
+        }
+    }
+
+    // How many cells an image of the given size takes up
+    pub fn pixels_to_cells(&self, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            Renderer::Blocks => (width, height.div_ceil(2)),
+            Renderer::Ascii => (width * 2, height),
+            Renderer::Braille => (width.div_ceil(2), height.div_ceil(4)),
+        }
+    }
+
+    // An empty line is written before the image, then the padding of the layout around it.
+    // Only braille can be drawn both with and without colors
+    pub fn render(
+        &self,
+        out: &mut impl Write,
+        image: &Image<RGBColor>,
+        bkgd: &RGBColor,
+        layout: &Layout,
+        colors: &Colors,
+    ) -> io::Result<()> {
+        writeln!(out)?;
+        for _ in 0..layout.top {
+            writeln!(out)?;
+        }
+        let margin = " ".repeat(layout.left);
+
+        match self {
+            Renderer::Blocks => render_blocks(out, image, bkgd, &margin, colors)?,
+            Renderer::Ascii => render_ascii(out, image, bkgd, &margin)?,
+            Renderer::Braille => render_braille(out, image, bkgd, &margin, colors)?,
+        }
+
+        for _ in 0..layout.bottom {
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+}
+
+fn render_blocks(
+    out: &mut impl Write,
+    image: &Image<RGBColor>,
+    bkgd: &RGBColor,
+    margin: &str,
+    colors: &Colors,
+) -> io::Result<()> {
+    let is_bg_transparent = is_transparent(bkgd.0, bkgd.1, bkgd.2);
+    let w = image.width();
+    let h = image.height();
+
+    // use ▀▄ as 4 pixels
+    let mut y = 0;
+    while y < h {
+        write!(out, "{}", margin)?;
+        for x in 0..w {
+            let top = replace_with_bg(&image[(x, y)], bkgd);
+            let bottom = if y + 1 == h {
+                (0, 0, 0)
+            } else {
+                replace_with_bg(&image[(x, y + 1)], bkgd)
+            };
+
+            let s = if is_transparent(top.0, top.1, top.2) && is_bg_transparent {
+                " "
+            } else {
+                "▀"
+            };
+
+            write!(
+                out,
+                "\x1B[38;{};48;{}m{}\x1B[0m",
+                colors.code(&top),
+                colors.code(&bottom),
+                s
+            )?;
+        }
+        writeln!(out)?;
+        y += 2;
+    }
+    Ok(())
+}
+
+fn render_ascii(
+    out: &mut impl Write,
+    image: &Image<RGBColor>,
+    bkgd: &RGBColor,
+    margin: &str,
+) -> io::Result<()> {
+    // use @@ as one pixel
+    for scaline in image.rows() {
+        write!(out, "{}", margin)?;
+        for col in scaline {
+            let (r, g, b) = replace_with_bg(col, bkgd);
+
+            let idx = (r as usize + g as usize + b as usize) / 24;
+            write!(out, "{}{}", CHARS[idx], CHARS[idx])?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+// In color, a dot is shown for the pixels at least as bright as the rest of their cell, in their
+// average color. Without colors, for the pixels brighter than middle gray
+fn render_braille(
+    out: &mut impl Write,
+    image: &Image<RGBColor>,
+    bkgd: &RGBColor,
+    margin: &str,
+    colors: &Colors,
+) -> io::Result<()> {
+    let color = *colors != Colors::Monochrome;
+    let w = image.width();
+    let h = image.height();
+
+    for cell_y in (0..h).step_by(4) {
+        write!(out, "{}", margin)?;
+        for cell_x in (0..w).step_by(2) {
+            let mut pixels = Vec::with_capacity(8);
+            for (dy, row) in BRAILLE_DOTS.iter().enumerate() {
+                for (dx, &dot) in row.iter().enumerate() {
+                    if let Some(col) = image.get(cell_x + dx, cell_y + dy) {
+                        let col = replace_with_bg(col, bkgd);
+                        if !is_transparent(col.0, col.1, col.2) {
+                            pixels.push((dot, col));
+                        }
+                    }
+                }
+            }
+
+            let threshold = if color && !pixels.is_empty() {
+                pixels.iter().map(|(_, col)| brightness(col)).sum::<usize>() / pixels.len()
+            } else {
+                128
+            };
+            let lit: Vec<&(u32, RGBColor)> = pixels
+                .iter()
+                .filter(|(_, col)| brightness(col) >= threshold)
+                .collect();
+
+            if lit.is_empty() {
+                write!(out, " ")?;
+                continue;
+            }
+
+            let dots = lit.iter().fold(0, |dots, (dot, _)| dots | dot);
+            let ch = char::from_u32(0x2800 + dots).unwrap_or(' ');
+
+            if color {
+                let n = lit.len() as u32;
+                let sum = lit.iter().fold((0, 0, 0), |(r, g, b), (_, col)| {
+                    (r + col.0 as u32, g + col.1 as u32, b + col.2 as u32)
+                });
+                let (r, g, b) = opaque((sum.0 / n) as u8, (sum.1 / n) as u8, (sum.2 / n) as u8);
+                write!(out, "\x1B[38;{}m{}\x1B[0m", colors.code(&(r, g, b)), ch)?;
+            } else {
+                write!(out, "{}", ch)?;
+            }
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
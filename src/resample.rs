@@ -0,0 +1,302 @@
+// Resizes images with a choice of reconstruction filters. Rows are resized first and then
+// columns, which gives the same result as the 2D filter for a fraction of the work
+use crate::common::*;
+use std::f32::consts::PI;
+use std::io::{Error, ErrorKind, Result};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Filter {
+    // Picks the closest pixel, keeping hard edges
+    Nearest,
+    // Averages the pixels that are covered
+    #[default]
+    Box,
+    // Linear interpolation, also called triangle or tent
+    Bilinear,
+    // Cubic with B = C = 1/3, a compromise between blurring and ringing
+    Mitchell,
+    // Cubic with B = 0 and C = 1/2, sharper than Mitchell
+    CatmullRom,
+    // Windowed sinc with 3 lobes, the sharpest but can ring around edges
+    Lanczos3,
+}
+
+impl Filter {
+    pub fn parse(name: &str) -> Result<Filter> {
+        match name {
+            "nearest" => Ok(Filter::Nearest),
+            "box" => Ok(Filter::Box),
+            "bilinear" | "triangle" => Ok(Filter::Bilinear),
+            "mitchell" | "bicubic" => Ok(Filter::Mitchell),
+            "catmull-rom" => Ok(Filter::CatmullRom),
+            "lanczos3" | "lanczos" => Ok(Filter::Lanczos3),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Unknown filter: {}, expected one of nearest, box, bilinear, mitchell, catmull-rom or lanczos3",
+                    name
+                ),
+            )),
+        }
+    }
+
+    // How far from the center the filter is non zero, in source pixels when upscaling
+    fn support(&self) -> f32 {
+        match self {
+            Filter::Nearest | Filter::Box => 0.5,
+            Filter::Bilinear => 1.0,
+            Filter::Mitchell | Filter::CatmullRom => 2.0,
+            Filter::Lanczos3 => 3.0,
+        }
+    }
+
+    fn weight(&self, x: f32) -> f32 {
+        let x = x.abs();
+        match self {
+            Filter::Nearest | Filter::Box => {
+                if x <= 0.5 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Filter::Bilinear => (1.0 - x).max(0.0),
+            Filter::Mitchell => cubic(x, 1.0 / 3.0, 1.0 / 3.0),
+            Filter::CatmullRom => cubic(x, 0.0, 0.5),
+            Filter::Lanczos3 => {
+                if x < 3.0 {
+                    sinc(x) * sinc(x / 3.0)
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+// Mitchell-Netravali cubics, as defined by their B and C parameters
+fn cubic(x: f32, b: f32, c: f32) -> f32 {
+    if x < 1.0 {
+        ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+            + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+            + (6.0 - 2.0 * b))
+            / 6.0
+    } else if x < 2.0 {
+        ((-b - 6.0 * c) * x * x * x
+            + (6.0 * b + 30.0 * c) * x * x
+            + (-12.0 * b - 48.0 * c) * x
+            + (8.0 * b + 24.0 * c))
+            / 6.0
+    } else {
+        0.0
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x == 0.0 {
+        1.0
+    } else {
+        let x = x * PI;
+        x.sin() / x
+    }
+}
+
+// The source pixels that make up one destination pixel, and how much each of them counts
+struct Contribution {
+    start: usize,
+    weights: Vec<f32>,
+}
+
+fn contributions(src_len: usize, dst_len: usize, filter: Filter) -> Vec<Contribution> {
+    let scale = src_len as f32 / dst_len as f32;
+    // When downscaling, the filter is stretched to cover all the source pixels
+    let filter_scale = scale.max(1.0);
+    let support = filter.support() * filter_scale;
+
+    (0..dst_len)
+        .map(|i| {
+            let center = (i as f32 + 0.5) * scale;
+
+            if filter == Filter::Nearest {
+                return Contribution {
+                    start: (center as usize).min(src_len - 1),
+                    weights: vec![1.0],
+                };
+            }
+
+            let start = (center - support).floor().max(0.0) as usize;
+            let end = ((center + support).ceil() as usize).min(src_len);
+            let mut weights: Vec<f32> = (start..end)
+                .map(|j| filter.weight((j as f32 + 0.5 - center) / filter_scale))
+                .collect();
+
+            let total: f32 = weights.iter().sum();
+            if total != 0.0 {
+                for weight in &mut weights {
+                    *weight /= total;
+                }
+            } else {
+                // Only happens for tiny box filters that fall between pixel centers
+                weights = vec![1.0];
+                return Contribution {
+                    start: (center as usize).min(src_len - 1),
+                    weights,
+                };
+            }
+
+            Contribution { start, weights }
+        })
+        .collect()
+}
+
+fn convolve(samples: impl Iterator<Item = Sample>, weights: &[f32]) -> Sample {
+    let mut out = [0.0; 4];
+    // Weights go first, so that no sample past the last weight is read
+    for (&weight, sample) in weights.iter().zip(samples) {
+        for c in 0..4 {
+            out[c] += sample[c] * weight;
+        }
+    }
+    out
+}
+
+pub fn resize(
+    image: &Image<RGBColor>,
+    width: usize,
+    height: usize,
+    filter: Filter,
+) -> Image<RGBColor> {
+    if image.is_empty() || width == 0 || height == 0 {
+        return ImageBuffer::default();
+    }
+    if image.width() == width && image.height() == height {
+        return image.clone();
+    }
+
+    let samples = image.map(|px| to_sample(&px));
+
+    let columns = contributions(image.width(), width, filter);
+    let horizontal = ImageBuffer::from_fn(width, image.height(), |x, y| {
+        let contribution = &columns[x];
+        let row = &samples.row(y)[contribution.start..];
+        convolve(row.iter().copied(), &contribution.weights)
+    });
+
+    let rows = contributions(image.height(), height, filter);
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let contribution = &rows[y];
+        let column = (contribution.start..).map(|j| horizontal[(x, j)]);
+        to_color(&convolve(column, &contribution.weights))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILTERS: [Filter; 6] = [
+        Filter::Nearest,
+        Filter::Box,
+        Filter::Bilinear,
+        Filter::Mitchell,
+        Filter::CatmullRom,
+        Filter::Lanczos3,
+    ];
+
+    // Sizes to downscale and upscale from 24x18 to
+    const SIZES: [(usize, usize); 2] = [(7, 5), (61, 43)];
+
+    #[test]
+    fn constant() {
+        let color = (100, 150, 200);
+        let image = ImageBuffer::new(24, 18, color);
+        for filter in FILTERS {
+            for (width, height) in SIZES {
+                let resized = resize(&image, width, height, filter);
+                assert_eq!((resized.width(), resized.height()), (width, height));
+                assert!(
+                    resized.pixels().all(|&px| px == color),
+                    "{:?} to {}x{}",
+                    filter,
+                    width,
+                    height
+                );
+            }
+        }
+    }
+
+    // A gray ramp from left to right stays a ramp, with its ends where the ramp would have them
+    #[test]
+    fn gradient() {
+        let value = |x: f32| 20.0 + x * 8.0;
+        let image = ImageBuffer::from_fn(24, 18, |x, _| {
+            let v = value(x as f32) as u8;
+            (v, v, v)
+        });
+        for filter in FILTERS {
+            for (width, height) in SIZES {
+                let resized = resize(&image, width, height, filter);
+                for row in resized.rows() {
+                    assert!(
+                        row.windows(2).all(|pair| pair[0].0 <= pair[1].0),
+                        "{:?} to {}x{}: {:?}",
+                        filter,
+                        width,
+                        height,
+                        row
+                    );
+                }
+
+                // The source position of the centers of the first and last pixels, within half
+                // a step of the ramp
+                let scale = 24.0 / width as f32;
+                let first = value((0.5 * scale - 0.5).max(0.0));
+                let last = value(((width as f32 - 0.5) * scale - 0.5).min(23.0));
+                let row = resized.row(0);
+                assert!(
+                    (row[0].0 as f32 - first).abs() <= 4.0,
+                    "{:?} to {}x{}: first {:?}, expected {}",
+                    filter,
+                    width,
+                    height,
+                    row[0],
+                    first
+                );
+                assert!(
+                    (row[width - 1].0 as f32 - last).abs() <= 4.0,
+                    "{:?} to {}x{}: last {:?}, expected {}",
+                    filter,
+                    width,
+                    height,
+                    row[width - 1],
+                    last
+                );
+            }
+        }
+    }
+
+    // Every 2x2 block of the checkerboard has two of each color, which average to their middle
+    #[test]
+    fn checkerboard() {
+        let image = ImageBuffer::from_fn(8, 6, |x, y| {
+            if (x + y) % 2 == 0 {
+                (20, 20, 20)
+            } else {
+                (220, 220, 220)
+            }
+        });
+        let resized = resize(&image, 4, 3, Filter::Box);
+        assert_eq!(resized, ImageBuffer::new(4, 3, (120, 120, 120)));
+    }
+
+    #[test]
+    fn nearest_keeps_edges() {
+        let (left, right) = ((200, 10, 10), (10, 10, 200));
+        let image = ImageBuffer::from_fn(4, 2, |x, _| if x < 2 { left } else { right });
+        let resized = resize(&image, 12, 5, Filter::Nearest);
+        for row in resized.rows() {
+            assert_eq!(&row[..6], &[left; 6]);
+            assert_eq!(&row[6..], &[right; 6]);
+        }
+    }
+}
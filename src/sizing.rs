@@ -0,0 +1,326 @@
+// Decides how big an image is shown, and where. Sizes given by the user are in terminal cells,
+// which are converted to pixels depending on how the image is drawn
+use crate::common::*;
+use crate::render::Renderer;
+use crate::resample::Filter;
+use crate::terminal::DEFAULT_CELL_ASPECT;
+use std::io::{Error, ErrorKind, Result};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Length {
+    Cells(usize),
+    // Of the terminal size
+    Percent(f32),
+}
+
+impl Length {
+    // Either a number of cells, or a percentage like "50%"
+    pub fn parse(value: &str) -> Result<Length> {
+        let invalid = || {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid size: {}, expected cells or a percentage", value),
+            )
+        };
+
+        let length = match value.strip_suffix('%') {
+            Some(percent) => Length::Percent(percent.parse().map_err(|_| invalid())?),
+            None => Length::Cells(value.parse().map_err(|_| invalid())?),
+        };
+
+        match length {
+            Length::Cells(0) => Err(invalid()),
+            Length::Percent(percent) if !(percent > 0.0 && percent.is_finite()) => Err(invalid()),
+            length => Ok(length),
+        }
+    }
+
+    fn cells(&self, available: Option<usize>) -> Result<usize> {
+        match *self {
+            Length::Cells(cells) => Ok(cells),
+            Length::Percent(percent) => match available {
+                Some(available) => Ok(((available as f32 * percent / 100.0) as usize).max(1)),
+                None => Err(Error::new(
+                    ErrorKind::NotFound,
+                    "Percentage sizes need the terminal size, which is unknown",
+                )),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FitMode {
+    // The whole image is shown, as big as fits in the size
+    #[default]
+    Fit,
+    // The size is covered, cropping whatever is left over
+    Fill,
+    // The image is made exactly the size, ignoring its aspect ratio
+    Stretch,
+}
+
+// Where the image goes in the terminal, in cells
+pub struct Layout {
+    pub left: usize,
+    pub top: usize,
+    pub bottom: usize,
+}
+
+pub struct SizingPolicy {
+    // Size of the terminal, or of the output when it isn't one. A missing dimension is unlimited
+    pub columns: Option<usize>,
+    pub rows: Option<usize>,
+    pub width: Option<Length>,
+    pub height: Option<Length>,
+    pub mode: FitMode,
+    // Shows one image pixel per pixel when false, even if it doesn't fit
+    pub resize: bool,
+    // Horizontally, within the terminal
+    pub center: bool,
+    // Empty cells kept around the image
+    pub padding: usize,
+    pub filter: Filter,
+    // Width divided by height of a terminal cell, if known
+    pub cell_aspect: Option<f32>,
+}
+
+impl SizingPolicy {
+    // Fits the image in the terminal, without making it any bigger
+    pub fn new(columns: Option<usize>, rows: Option<usize>) -> SizingPolicy {
+        SizingPolicy {
+            columns,
+            rows,
+            width: None,
+            height: None,
+            mode: FitMode::default(),
+            resize: true,
+            center: false,
+            padding: 0,
+            filter: Filter::default(),
+            cell_aspect: None,
+        }
+    }
+
+    // Width divided by height of an image pixel once drawn. Every renderer draws a pixel as twice
+    // as wide as a cell is relative to its height, which is square for 1:2 cells
+    pub fn drawn_pixel_aspect(&self) -> f32 {
+        self.cell_aspect.unwrap_or(DEFAULT_CELL_ASPECT) * 2.0
+    }
+
+    // Whether the user asked for a size, which the image is scaled up to if it is smaller
+    pub fn is_explicit(&self) -> bool {
+        self.width.is_some() || self.height.is_some()
+    }
+
+    // The space the image has to fit in, in pixels. A missing dimension is unconstrained
+    pub fn target(&self, renderer: &Renderer) -> Result<(Option<usize>, Option<usize>)> {
+        let available =
+            |cells: Option<usize>| cells.map(|cells| cells.saturating_sub(self.padding * 2).max(1));
+        let (available_columns, available_rows) = (available(self.columns), available(self.rows));
+
+        let (columns, rows) = if self.is_explicit() {
+            (
+                match &self.width {
+                    Some(width) => Some(width.cells(available_columns)?),
+                    None => None,
+                },
+                match &self.height {
+                    Some(height) => Some(height.cells(available_rows)?),
+                    None => None,
+                },
+            )
+        } else if available_columns.is_none() && available_rows.is_none() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                "Failed to get Terminal size",
+            ));
+        } else {
+            (available_columns, available_rows)
+        };
+
+        let (width, height) = renderer.cells_to_pixels(columns.unwrap_or(0), rows.unwrap_or(0));
+        Ok((columns.map(|_| width.max(1)), rows.map(|_| height.max(1))))
+    }
+
+    pub fn layout(&self, image: &Image<RGBColor>, renderer: &Renderer) -> Layout {
+        let (columns, _) = renderer.pixels_to_cells(image.width(), image.height());
+
+        let left = match self.columns {
+            Some(terminal_columns) if self.center => {
+                (terminal_columns.saturating_sub(columns) / 2).max(self.padding)
+            }
+            _ => self.padding,
+        };
+
+        Layout {
+            left,
+            top: self.padding,
+            bottom: self.padding,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 40x20 pixels, which is 40x10 cells drawn with blocks
+    fn image() -> Image<RGBColor> {
+        ImageBuffer::new(40, 20, (1, 2, 3))
+    }
+
+    fn resized(policy: &SizingPolicy, image: Image<RGBColor>) -> (usize, usize) {
+        let image = auto_downsize_image(image, &Renderer::Blocks, policy, 1.0).unwrap();
+        (image.width(), image.height())
+    }
+
+    fn sized(width: Length, height: Length, mode: FitMode) -> SizingPolicy {
+        let mut policy = SizingPolicy::new(Some(80), Some(24));
+        policy.width = Some(width);
+        policy.height = Some(height);
+        policy.mode = mode;
+        policy
+    }
+
+    #[test]
+    fn parse_length() {
+        assert_eq!(Length::parse("12").unwrap(), Length::Cells(12));
+        assert_eq!(Length::parse("50%").unwrap(), Length::Percent(50.0));
+        assert_eq!(Length::parse("12.5%").unwrap(), Length::Percent(12.5));
+        for value in ["0", "0%", "-5%", "inf%", "12.5", "abc", "%", ""].iter() {
+            let e = Length::parse(value).err().unwrap();
+            assert_eq!(e.kind(), ErrorKind::InvalidInput, "{}", value);
+        }
+    }
+
+    #[test]
+    fn percentage() {
+        assert_eq!(Length::Percent(50.0).cells(Some(81)).unwrap(), 40);
+        // Never rounded down to nothing
+        assert_eq!(Length::Percent(1.0).cells(Some(10)).unwrap(), 1);
+        assert_eq!(Length::Cells(7).cells(None).unwrap(), 7);
+        assert!(Length::Percent(50.0).cells(None).is_err());
+
+        let mut policy = SizingPolicy::new(Some(40), Some(24));
+        policy.width = Some(Length::Percent(50.0));
+        assert_eq!(resized(&policy, image()), (20, 10));
+    }
+
+    #[test]
+    fn fit_in_terminal() {
+        assert_eq!(
+            resized(&SizingPolicy::new(Some(20), Some(20)), image()),
+            (20, 10)
+        );
+        // Blocks draw two pixels per row
+        assert_eq!(
+            resized(&SizingPolicy::new(Some(80), Some(5)), image()),
+            (20, 10)
+        );
+        // Only one dimension is known
+        assert_eq!(
+            resized(&SizingPolicy::new(Some(10), None), image()),
+            (10, 5)
+        );
+        // Small images aren't made bigger
+        assert_eq!(
+            resized(&SizingPolicy::new(Some(80), Some(24)), image()),
+            (40, 20)
+        );
+
+        let mut policy = SizingPolicy::new(Some(20), Some(20));
+        policy.resize = false;
+        assert_eq!(resized(&policy, image()), (40, 20));
+
+        assert!(SizingPolicy::new(None, None)
+            .target(&Renderer::Blocks)
+            .is_err());
+    }
+
+    #[test]
+    fn padding() {
+        let mut policy = SizingPolicy::new(Some(22), Some(24));
+        policy.padding = 1;
+        assert_eq!(
+            policy.target(&Renderer::Blocks).unwrap(),
+            (Some(20), Some(44))
+        );
+        assert_eq!(resized(&policy, image()), (20, 10));
+    }
+
+    #[test]
+    fn fit_modes() {
+        // A 10x5 cell box, which is 10x10 pixels
+        let (width, height) = (Length::Cells(10), Length::Cells(5));
+        assert_eq!(
+            resized(&sized(width, height, FitMode::Fit), image()),
+            (10, 5)
+        );
+        assert_eq!(
+            resized(&sized(width, height, FitMode::Fill), image()),
+            (10, 10)
+        );
+        assert_eq!(
+            resized(&sized(width, height, FitMode::Stretch), image()),
+            (10, 10)
+        );
+
+        // Explicit sizes make small images bigger
+        let small = ImageBuffer::new(4, 2, (1, 2, 3));
+        let (width, height) = (Length::Cells(8), Length::Cells(8));
+        assert_eq!(
+            resized(&sized(width, height, FitMode::Fit), small.clone()),
+            (8, 4)
+        );
+        assert_eq!(
+            resized(&sized(width, height, FitMode::Fill), small.clone()),
+            (8, 16)
+        );
+        assert_eq!(
+            resized(&sized(width, height, FitMode::Stretch), small),
+            (8, 16)
+        );
+    }
+
+    #[test]
+    fn fill_crops_the_center() {
+        // Left half red and right half blue, cropped to the middle columns
+        let image =
+            ImageBuffer::from_fn(8, 2, |x, _| if x < 4 { (255, 0, 0) } else { (0, 0, 255) });
+        let policy = sized(Length::Cells(2), Length::Cells(1), FitMode::Fill);
+        let filled = auto_downsize_image(image, &Renderer::Blocks, &policy, 1.0).unwrap();
+        assert_eq!((filled.width(), filled.height()), (2, 2));
+        assert_eq!(filled.row(0), [(255, 0, 0), (0, 0, 255)]);
+    }
+
+    #[test]
+    fn cell_aspect() {
+        // Square cells make each pixel twice as wide as it is tall, which is corrected by halving
+        // the width
+        let mut policy = SizingPolicy::new(Some(80), Some(24));
+        policy.cell_aspect = Some(1.0);
+        assert_eq!(resized(&policy, image()), (20, 20));
+
+        // Narrower cells than usual shrink the height instead
+        policy.cell_aspect = Some(0.25);
+        assert_eq!(resized(&policy, image()), (40, 10));
+
+        // Image pixels twice as tall as they are wide make up for the narrow cells
+        let image = auto_downsize_image(image(), &Renderer::Blocks, &policy, 0.5).unwrap();
+        assert_eq!((image.width(), image.height()), (40, 20));
+    }
+
+    #[test]
+    fn layout() {
+        let mut policy = SizingPolicy::new(Some(80), Some(24));
+        policy.center = true;
+        let layout = policy.layout(&image(), &Renderer::Blocks);
+        assert_eq!((layout.left, layout.top, layout.bottom), (20, 0, 0));
+
+        policy.center = false;
+        policy.padding = 2;
+        let layout = policy.layout(&image(), &Renderer::Blocks);
+        assert_eq!((layout.left, layout.top, layout.bottom), (2, 2, 2));
+    }
+}
@@ -0,0 +1,204 @@
+// Queries about the terminal the image is shown in
+use std::env;
+use std::io::{Error, ErrorKind, Result};
+
+// Width divided by height of a cell in most fonts, used when the terminal doesn't report it
+pub const DEFAULT_CELL_ASPECT: f32 = 0.5;
+
+// Columns used when the output isn't a terminal and COLUMNS isn't set
+pub const DEFAULT_WIDTH: usize = 80;
+
+// Columns and rows of the terminal, when printing to one. Otherwise, like in pipes and CI, they
+// are taken from COLUMNS and LINES if those are set
+pub fn dimensions(to_terminal: bool) -> (Option<usize>, Option<usize>) {
+    if to_terminal {
+        if let Some((columns, rows)) = term_size::dimensions_stdout() {
+            return (Some(columns), Some(rows));
+        }
+    }
+    (env_cells("COLUMNS"), env_cells("LINES"))
+}
+
+fn env_cells(name: &str) -> Option<usize> {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .filter(|&cells| cells > 0)
+}
+
+// Whether colors are turned off, as asked for by a non empty NO_COLOR (https://no-color.org)
+pub fn no_color() -> bool {
+    env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+}
+
+// Either a number like "0.5", or a width and height like "8:17"
+pub fn parse_cell_aspect(value: &str) -> Result<f32> {
+    let aspect = match value.split_once(':') {
+        Some((width, height)) => match (width.parse::<f32>(), height.parse::<f32>()) {
+            (Ok(width), Ok(height)) => width / height,
+            _ => f32::NAN,
+        },
+        None => value.parse().unwrap_or(f32::NAN),
+    };
+
+    if aspect > 0.0 && aspect.is_finite() {
+        Ok(aspect)
+    } else {
+        Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Invalid cell aspect: {}, expected a ratio like 0.5 or 8:16",
+                value
+            ),
+        ))
+    }
+}
+
+// Width divided by height of a cell, from the size of the terminal in pixels. The window size
+// is asked for first, and terminals that leave its pixel size empty are asked with CSI 14 t
+pub fn cell_aspect() -> Option<f32> {
+    let (columns, rows, width, height) = platform::window_size()?;
+    if columns == 0 || rows == 0 {
+        return None;
+    }
+
+    let (width, height) = if width > 0 && height > 0 {
+        (width, height)
+    } else {
+        platform::query_text_area()?
+    };
+
+    let aspect = (width as f32 / columns as f32) / (height as f32 / rows as f32);
+    if aspect > 0.0 && aspect.is_finite() {
+        Some(aspect)
+    } else {
+        None
+    }
+}
+
+// The reply to CSI 14 t is CSI 4 ; height ; width t
+fn parse_text_area(reply: &[u8]) -> Option<(usize, usize)> {
+    let start = reply.windows(4).position(|w| w == b"\x1B[4;")? + 4;
+    let end = start + reply[start..].iter().position(|&b| b == b't')?;
+    let reply = std::str::from_utf8(&reply[start..end]).ok()?;
+
+    let (height, width) = reply.split_once(';')?;
+    Some((width.parse().ok()?, height.parse().ok()?))
+}
+
+#[cfg(unix)]
+mod platform {
+    use std::fs::OpenOptions;
+    use std::io::{Read, Write};
+    use std::os::unix::io::AsRawFd;
+
+    // Columns, rows, width and height in pixels, which some terminals leave as 0
+    pub fn window_size() -> Option<(usize, usize, usize, usize)> {
+        // Only the terminal the image is printed to is of interest
+        if unsafe { libc::isatty(libc::STDOUT_FILENO) } != 1 {
+            return None;
+        }
+
+        let mut window: libc::winsize = unsafe { std::mem::zeroed() };
+        if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut window) } != 0 {
+            return None;
+        }
+
+        Some((
+            window.ws_col as usize,
+            window.ws_row as usize,
+            window.ws_xpixel as usize,
+            window.ws_ypixel as usize,
+        ))
+    }
+
+    // Asks the terminal for the size of its text area in pixels. Device attributes are asked
+    // for after it, which nearly every terminal answers, so that terminals that ignore the first
+    // query don't have to be waited on until the timeout
+    pub fn query_text_area() -> Option<(usize, usize)> {
+        let mut tty = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/tty")
+            .ok()?;
+        let fd = tty.as_raw_fd();
+
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(fd, &mut original) } != 0 {
+            return None;
+        }
+
+        // Replies are read as they arrive without being echoed, giving up after 100ms of silence
+        let mut raw = original;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+        raw.c_cc[libc::VMIN] = 0;
+        raw.c_cc[libc::VTIME] = 1;
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) } != 0 {
+            return None;
+        }
+
+        let mut reply = Vec::new();
+        if tty
+            .write_all(b"\x1B[14t\x1B[c")
+            .and_then(|_| tty.flush())
+            .is_ok()
+        {
+            let mut buffer = [0; 64];
+            while let Ok(n) = tty.read(&mut buffer) {
+                if n == 0 {
+                    break;
+                }
+                reply.extend_from_slice(&buffer[..n]);
+                // The device attributes reply is CSI ? ... c, and comes last
+                if let Some(start) = reply.windows(3).position(|w| w == b"\x1B[?") {
+                    if reply[start..].contains(&b'c') {
+                        break;
+                    }
+                }
+                if reply.len() > 1024 {
+                    break;
+                }
+            }
+        }
+
+        unsafe { libc::tcsetattr(fd, libc::TCSANOW, &original) };
+
+        super::parse_text_area(&reply)
+    }
+}
+
+#[cfg(not(unix))]
+mod platform {
+    pub fn window_size() -> Option<(usize, usize, usize, usize)> {
+        None
+    }
+
+    pub fn query_text_area() -> Option<(usize, usize)> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cell_aspect_ratio() {
+        assert_eq!(parse_cell_aspect("0.5").unwrap(), 0.5);
+        assert_eq!(parse_cell_aspect("8:16").unwrap(), 0.5);
+        assert_eq!(parse_cell_aspect("9.5:19").unwrap(), 0.5);
+        assert_eq!(parse_cell_aspect("1").unwrap(), 1.0);
+    }
+
+    #[test]
+    fn invalid_cell_aspect() {
+        for value in [
+            "0", "-0.5", "8:0", "0:16", "-8:16", "inf", "nan", "8:", ":16", "a:b", "",
+        ]
+        .iter()
+        {
+            let e = parse_cell_aspect(value).err().unwrap();
+            assert_eq!(e.kind(), ErrorKind::InvalidInput, "{}", value);
+        }
+    }
+}
@@ -0,0 +1,370 @@
+use crate::common::*;
+use std::io::{Error, ErrorKind, Result};
+
+// TGA files have no magic number at the start. Version 2 files end with this footer signature,
+// version 1 files can only be recognised by their extension
+pub static FOOTER_SIGNATURE: &[u8] = b"TRUEVISION-XFILE.\0";
+
+const HEADER_SIZE: usize = 18;
+
+// Image types as per the Truevision TGA 2.0 spec. The RLE variants have bit 3 set
+const COLOR_MAPPED: u8 = 1;
+const TRUE_COLOR: u8 = 2;
+const GRAYSCALE: u8 = 3;
+const RLE_FLAG: u8 = 8;
+
+pub fn is_tga(buffer: &[u8], file_name: &str) -> bool {
+    buffer.ends_with(FOOTER_SIGNATURE) || file_name.to_lowercase().ends_with(".tga")
+}
+
+struct Header {
+    id_length: u8,
+    color_map_type: u8,
+    image_type: u8,
+    color_map_start: u16,
+    color_map_length: u16,
+    color_map_entry_size: u8,
+    width: u16,
+    height: u16,
+    pixel_depth: u8,
+    descriptor: u8,
+}
+
+impl Header {
+    fn parse(bytes: &[u8]) -> Header {
+        Header {
+            id_length: bytes[0],
+            color_map_type: bytes[1],
+            image_type: bytes[2],
+            color_map_start: from_bytes_u16_le(&bytes[3..5]),
+            color_map_length: from_bytes_u16_le(&bytes[5..7]),
+            color_map_entry_size: bytes[7],
+            // bytes 8..12 are the x and y origin of the image on screen, which aren't used here
+            width: from_bytes_u16_le(&bytes[12..14]),
+            height: from_bytes_u16_le(&bytes[14..16]),
+            pixel_depth: bytes[16],
+            descriptor: bytes[17],
+        }
+    }
+
+    fn alpha_bits(&self) -> u8 {
+        self.descriptor & 0b1111
+    }
+
+    fn right_to_left(&self) -> bool {
+        self.descriptor & (1 << 4) != 0
+    }
+
+    fn top_to_bottom(&self) -> bool {
+        self.descriptor & (1 << 5) != 0
+    }
+
+    fn is_rle(&self) -> bool {
+        self.image_type & RLE_FLAG != 0
+    }
+
+    fn base_type(&self) -> u8 {
+        self.image_type & !RLE_FLAG
+    }
+}
+
+fn truncated() -> Error {
+    Error::new(ErrorKind::UnexpectedEof, "TGA image data is truncated")
+}
+
+pub fn parse(buffer: Vec<u8>, metadata: &mut Metadata) -> Result<Image<RGBColor>> {
+    if buffer.len() < HEADER_SIZE {
+        return Err(truncated());
+    }
+
+    let header = Header::parse(&buffer[..HEADER_SIZE]);
+    let has_alpha = header.alpha_bits() > 0;
+
+    match (header.base_type(), header.pixel_depth) {
+        (COLOR_MAPPED, 8) | (COLOR_MAPPED, 16) => {}
+        (TRUE_COLOR, 15) | (TRUE_COLOR, 16) | (TRUE_COLOR, 24) | (TRUE_COLOR, 32) => {}
+        (GRAYSCALE, 8) | (GRAYSCALE, 16) => {}
+        (0, _) => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "TGA file contains no image data",
+            ))
+        }
+        (_, pixel_depth) => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Unsupported TGA image type {} with pixel depth {}",
+                    header.image_type, pixel_depth
+                ),
+            ))
+        }
+    };
+
+    let mut i = HEADER_SIZE + header.id_length as usize;
+
+    // The color map is present even for true color images in some files, so it always has to be
+    // skipped over
+    let palette = if header.color_map_type == 1 {
+        if ![15, 16, 24, 32].contains(&header.color_map_entry_size) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Unsupported TGA color map entry size: {}",
+                    header.color_map_entry_size
+                ),
+            ));
+        }
+        let entry_size = (header.color_map_entry_size as usize).div_ceil(8);
+        let end = i + header.color_map_length as usize * entry_size;
+        let bytes = buffer.get(i..end).ok_or_else(truncated)?;
+        i = end;
+
+        let mut palette = Vec::with_capacity(header.color_map_length as usize);
+        for entry in bytes.chunks_exact(entry_size) {
+            palette.push(true_color(entry, header.color_map_entry_size, has_alpha)?);
+        }
+        Some(palette)
+    } else {
+        None
+    };
+
+    let width = header.width as usize;
+    let height = header.height as usize;
+    let pixel_size = (header.pixel_depth as usize).div_ceil(8);
+    let data_length = width * height * pixel_size;
+
+    // RLE data is expanded first, so that both variants share the same pixel decoding
+    let data = if header.is_rle() {
+        decode_rle(
+            buffer.get(i..).ok_or_else(truncated)?,
+            pixel_size,
+            data_length,
+        )?
+    } else {
+        buffer
+            .get(i..i + data_length)
+            .ok_or_else(truncated)?
+            .to_vec()
+    };
+
+    let mut pixels = Vec::with_capacity(width * height);
+    for px in data.chunks_exact(pixel_size) {
+        let (r, g, b, a) = match header.base_type() {
+            COLOR_MAPPED => {
+                let index = if pixel_size == 1 {
+                    px[0] as usize
+                } else {
+                    from_bytes_u16_le(px) as usize
+                };
+                let palette = match &palette {
+                    Some(palette) => palette,
+                    None => {
+                        return Err(Error::new(
+                            ErrorKind::NotFound,
+                            "Color mapped TGA has no color map",
+                        ))
+                    }
+                };
+                match index
+                    .checked_sub(header.color_map_start as usize)
+                    .and_then(|i| palette.get(i))
+                {
+                    Some(color) => *color,
+                    None => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidData,
+                            format!("Color map index out of bounds: {}", index),
+                        ))
+                    }
+                }
+            }
+            TRUE_COLOR => true_color(px, header.pixel_depth, has_alpha)?,
+            _ => {
+                let a = if pixel_size == 2 && has_alpha {
+                    px[1]
+                } else {
+                    255
+                };
+                (px[0], px[0], px[0], a)
+            }
+        };
+        pixels.push(rgba_color(r, g, b, a));
+    }
+
+    // Pixels are stored bottom to top and left to right unless the descriptor says otherwise
+    let mut image: Image<RGBColor> = ImageBuffer::with_width(width, height);
+    for y in 0..height {
+        let src_y = if header.top_to_bottom() {
+            y
+        } else {
+            height - 1 - y
+        };
+        let mut scanline = pixels[src_y * width..(src_y + 1) * width].to_vec();
+        if header.right_to_left() {
+            scanline.reverse();
+        }
+        image.push_row(&scanline);
+    }
+
+    let (bit_depth, color_type) = match header.base_type() {
+        COLOR_MAPPED => (header.pixel_depth, ColorType::Palette),
+        GRAYSCALE if pixel_size == 2 => (8, ColorType::GrayA),
+        GRAYSCALE => (8, ColorType::Gray),
+        _ if header.pixel_depth <= 16 => (5, ColorType::RGB),
+        _ if has_alpha => (8, ColorType::RGBA),
+        _ => (8, ColorType::RGB),
+    };
+    metadata.set_image_info(width as u32, height as u32, bit_depth, color_type);
+    if let Some(palette) = palette {
+        metadata.set_palette(palette.iter().map(|&(r, g, b, _)| (r, g, b)).collect());
+    }
+
+    Ok(image)
+}
+
+// Decodes a little endian BGR(A) pixel. 15 and 16 bit pixels are stored as ARRRRRGG GGGBBBBB,
+// where the attribute bit is only an alpha bit if the descriptor says so
+fn true_color(bytes: &[u8], depth: u8, has_alpha: bool) -> Result<(u8, u8, u8, u8)> {
+    match depth {
+        15 | 16 => {
+            let val = from_bytes_u16_le(bytes);
+            let scale = |c: u16| ((c << 3) | (c >> 2)) as u8;
+            let a = if depth == 16 && has_alpha && val & 0x8000 == 0 {
+                0
+            } else {
+                255
+            };
+            Ok((
+                scale((val >> 10) & 0x1f),
+                scale((val >> 5) & 0x1f),
+                scale(val & 0x1f),
+                a,
+            ))
+        }
+        24 => Ok((bytes[2], bytes[1], bytes[0], 255)),
+        32 => Ok((
+            bytes[2],
+            bytes[1],
+            bytes[0],
+            if has_alpha { bytes[3] } else { 255 },
+        )),
+        _ => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Unsupported TGA color depth: {}", depth),
+        )),
+    }
+}
+
+// Each packet starts with a header byte, the lower 7 bits are the number of pixels - 1. If the
+// high bit is set, the next pixel is repeated that many times, otherwise that many raw pixels follow
+fn decode_rle(bytes: &[u8], pixel_size: usize, data_length: usize) -> Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(data_length);
+    let mut i = 0;
+
+    while data.len() < data_length {
+        let packet = *bytes.get(i).ok_or_else(truncated)?;
+        i += 1;
+        let count = (packet & 0x7f) as usize + 1;
+
+        if packet & 0x80 != 0 {
+            let px = bytes.get(i..i + pixel_size).ok_or_else(truncated)?;
+            for _ in 0..count {
+                data.extend_from_slice(px);
+            }
+            i += pixel_size;
+        } else {
+            let len = count * pixel_size;
+            data.extend_from_slice(bytes.get(i..i + len).ok_or_else(truncated)?);
+            i += len;
+        }
+    }
+
+    // The last packet may run past the end of the image
+    data.truncate(data_length);
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Header for an image without an ID, and a color map of the given length and entry size
+    fn header(
+        image_type: u8,
+        color_map: (u16, u8),
+        size: (u16, u16),
+        depth: u8,
+        descriptor: u8,
+    ) -> Vec<u8> {
+        let mut bytes = vec![0, (color_map.0 > 0) as u8, image_type, 0, 0];
+        bytes.extend_from_slice(&color_map.0.to_le_bytes());
+        bytes.push(color_map.1);
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(&size.0.to_le_bytes());
+        bytes.extend_from_slice(&size.1.to_le_bytes());
+        bytes.extend_from_slice(&[depth, descriptor]);
+        bytes
+    }
+
+    fn decode(buffer: Vec<u8>) -> Result<Image<RGBColor>> {
+        parse(buffer, &mut Metadata::new())
+    }
+
+    // Top to bottom, so rows are stored in the order they are shown
+    const TOP_LEFT: u8 = 1 << 5;
+
+    #[test]
+    fn true_color() {
+        let mut buffer = header(TRUE_COLOR, (0, 0), (2, 1), 24, TOP_LEFT);
+        // Blue, green, red
+        buffer.extend_from_slice(&[30, 20, 10, 60, 50, 40]);
+        assert_eq!(
+            decode(buffer).unwrap().row(0),
+            &[(10, 20, 30), (40, 50, 60)]
+        );
+    }
+
+    #[test]
+    fn bottom_up() {
+        let mut buffer = header(GRAYSCALE, (0, 0), (2, 2), 8, 0);
+        buffer.extend_from_slice(&[10, 20, 30, 40]);
+        let image = decode(buffer).unwrap();
+        assert_eq!(image.row(0), &[(30, 30, 30), (40, 40, 40)]);
+        assert_eq!(image.row(1), &[(10, 10, 10), (20, 20, 20)]);
+    }
+
+    #[test]
+    fn rle() {
+        let mut buffer = header(TRUE_COLOR | RLE_FLAG, (0, 0), (3, 2), 24, TOP_LEFT);
+        // 4 copies of one pixel, running into the second row, then 2 raw pixels
+        buffer.extend_from_slice(&[0x83, 30, 20, 10, 1, 3, 2, 1, 6, 5, 4]);
+        let image = decode(buffer).unwrap();
+        assert_eq!(image.row(0), &[(10, 20, 30); 3]);
+        assert_eq!(image.row(1), &[(10, 20, 30), (1, 2, 3), (4, 5, 6)]);
+    }
+
+    #[test]
+    fn color_mapped() {
+        let mut buffer = header(COLOR_MAPPED, (2, 24), (3, 1), 8, TOP_LEFT);
+        buffer.extend_from_slice(&[30, 20, 10, 60, 50, 40]);
+        buffer.extend_from_slice(&[1, 0, 1]);
+        let mut metadata = Metadata::new();
+        let image = parse(buffer, &mut metadata).unwrap();
+        assert_eq!(image.row(0), &[(40, 50, 60), (10, 20, 30), (40, 50, 60)]);
+        assert_eq!(metadata.palette(), &Some(vec![(10, 20, 30), (40, 50, 60)]));
+
+        let mut buffer = header(COLOR_MAPPED, (2, 24), (1, 1), 8, TOP_LEFT);
+        buffer.extend_from_slice(&[30, 20, 10, 60, 50, 40, 2]);
+        assert_eq!(decode(buffer).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn invalid_color_map_entry_size() {
+        for entry_size in [0, 8] {
+            let mut buffer = header(COLOR_MAPPED, (2, entry_size), (1, 1), 8, TOP_LEFT);
+            buffer.extend_from_slice(&[0; 8]);
+            assert_eq!(decode(buffer).unwrap_err().kind(), ErrorKind::InvalidData);
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use crate::inflate::Inflater;
+use std::io::{Error, ErrorKind, Result};
+
+const LZW_CLEAR: usize = 256;
+const LZW_EOI: usize = 257;
+const LZW_FIRST_CODE: usize = 258;
+const LZW_MAX_CODES: usize = 4096;
+
+// Codes are read starting from the most significant bit
+struct MsbBitReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    value: u32,
+    nbits: u32,
+}
+
+impl<'a> MsbBitReader<'a> {
+    fn read_bits(&mut self, n: u32) -> Option<usize> {
+        while self.nbits < n {
+            let byte = *self.bytes.get(self.pos)?;
+            self.pos += 1;
+            self.value = (self.value << 8) | byte as u32;
+            self.nbits += 8;
+        }
+        self.nbits -= n;
+        Some(((self.value >> self.nbits) & ((1 << n) - 1)) as usize)
+    }
+}
+
+// TIFF flavoured LZW: codes are MSB first and the code width increases one code early
+pub fn lzw(bytes: &[u8], expected_len: usize) -> Result<Vec<u8>> {
+    let mut reader = MsbBitReader {
+        bytes,
+        pos: 0,
+        value: 0,
+        nbits: 0,
+    };
+    let mut out: Vec<u8> = Vec::with_capacity(expected_len);
+
+    // Every string in the table has already been written to the output, so entries are stored as
+    // the offset and length of their first occurrence
+    let mut table: Vec<(usize, usize)> = Vec::with_capacity(LZW_MAX_CODES);
+    let reset = |table: &mut Vec<(usize, usize)>| {
+        table.clear();
+        // Literals and the clear and end of information codes, which have no string
+        table.extend((0..LZW_FIRST_CODE).map(|i| (i, 1)));
+    };
+    reset(&mut table);
+
+    let mut width = 9;
+    let mut prev: Option<(usize, usize)> = None;
+
+    // A missing end of information code is common, so running out of data just ends the strip
+    while let Some(code) = reader.read_bits(width) {
+        if code == LZW_EOI {
+            break;
+        }
+        if code == LZW_CLEAR {
+            reset(&mut table);
+            width = 9;
+            prev = None;
+            continue;
+        }
+
+        let start = out.len();
+        if code < LZW_CLEAR {
+            out.push(code as u8);
+        } else if code < table.len() {
+            let (offset, len) = table[code];
+            out.extend_from_within(offset..offset + len);
+        } else if code == table.len() {
+            // The string being defined by this code, the previous string plus its first byte
+            let (offset, len) = match prev {
+                Some(prev) => prev,
+                None => return Err(Error::new(ErrorKind::InvalidData, "Invalid LZW code")),
+            };
+            out.extend_from_within(offset..offset + len);
+            out.push(out[offset]);
+        } else {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid LZW code"));
+        }
+
+        if let Some((offset, len)) = prev {
+            if table.len() < LZW_MAX_CODES {
+                table.push((offset, len + 1));
+            }
+        }
+        prev = Some((start, out.len() - start));
+
+        if table.len() + 1 >= 1 << width && width < 12 {
+            width += 1;
+        }
+
+        if out.len() >= expected_len {
+            break;
+        }
+    }
+
+    Ok(out)
+}
+
+// Each run starts with a signed count, n >= 0 copies the next n + 1 bytes and n < 0 repeats the
+// next byte 1 - n times. -128 is a no-op
+pub fn packbits(bytes: &[u8], expected_len: usize) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(expected_len);
+    let mut i = 0;
+
+    while i < bytes.len() && out.len() < expected_len {
+        let n = bytes[i] as i8;
+        i += 1;
+
+        if n >= 0 {
+            let len = n as usize + 1;
+            match bytes.get(i..i + len) {
+                Some(run) => out.extend_from_slice(run),
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "PackBits data is truncated",
+                    ))
+                }
+            }
+            i += len;
+        } else if n != -128 {
+            match bytes.get(i) {
+                Some(&byte) => out.extend(std::iter::repeat_n(byte, (1 - n as isize) as usize)),
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "PackBits data is truncated",
+                    ))
+                }
+            }
+            i += 1;
+        }
+    }
+
+    Ok(out)
+}
+
+pub fn deflate(bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut inflater = Inflater::new();
+    inflater.feed(bytes)?;
+    inflater.finish()?;
+    Ok(inflater.into_output())
+}
@@ -0,0 +1,206 @@
+use std::io::{Error, ErrorKind, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    LittleEndian,
+    BigEndian,
+}
+
+impl ByteOrder {
+    pub fn u16(self, bytes: &[u8]) -> u16 {
+        match self {
+            ByteOrder::LittleEndian => u16::from_le_bytes([bytes[0], bytes[1]]),
+            ByteOrder::BigEndian => u16::from_be_bytes([bytes[0], bytes[1]]),
+        }
+    }
+
+    pub fn u32(self, bytes: &[u8]) -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        match self {
+            ByteOrder::LittleEndian => u32::from_le_bytes(bytes),
+            ByteOrder::BigEndian => u32::from_be_bytes(bytes),
+        }
+    }
+}
+
+// Field types as per the TIFF 6.0 spec
+pub const BYTE: u16 = 1;
+pub const ASCII: u16 = 2;
+pub const SHORT: u16 = 3;
+pub const LONG: u16 = 4;
+pub const RATIONAL: u16 = 5;
+pub const UNDEFINED: u16 = 7;
+pub const SRATIONAL: u16 = 10;
+
+fn type_size(field_type: u16) -> Option<usize> {
+    match field_type {
+        1 | 2 | 6 | 7 => Some(1),
+        3 | 8 => Some(2),
+        4 | 9 | 11 => Some(4),
+        5 | 10 | 12 => Some(8),
+        _ => None,
+    }
+}
+
+fn truncated() -> Error {
+    Error::new(ErrorKind::UnexpectedEof, "TIFF structure is truncated")
+}
+
+pub struct Entry<'a> {
+    pub tag: u16,
+    pub field_type: u16,
+    order: ByteOrder,
+    // The value itself, whether it was stored inline or at an offset
+    pub data: &'a [u8],
+}
+
+impl<'a> Entry<'a> {
+    // Only integer types can be read this way
+    pub fn values(&self) -> Option<Vec<u32>> {
+        match self.field_type {
+            BYTE | UNDEFINED => Some(self.data.iter().map(|&b| b as u32).collect()),
+            SHORT => Some(
+                self.data
+                    .chunks_exact(2)
+                    .map(|b| self.order.u16(b) as u32)
+                    .collect(),
+            ),
+            LONG => Some(
+                self.data
+                    .chunks_exact(4)
+                    .map(|b| self.order.u32(b))
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+
+    pub fn value(&self) -> Option<u32> {
+        self.values().and_then(|values| values.first().copied())
+    }
+
+    // Strings are null terminated, though not every writer includes the terminator
+    pub fn ascii(&self) -> Option<String> {
+        if self.field_type != ASCII {
+            return None;
+        }
+        let end = self
+            .data
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(self.data.len());
+        Some(
+            String::from_utf8_lossy(&self.data[..end])
+                .trim_end()
+                .to_owned(),
+        )
+    }
+
+    pub fn rationals(&self) -> Option<Vec<f64>> {
+        let values = self
+            .data
+            .chunks_exact(8)
+            .map(|b| (self.order.u32(&b[0..4]), self.order.u32(&b[4..8])));
+        let values: Vec<f64> = match self.field_type {
+            RATIONAL => values.map(|(n, d)| n as f64 / d as f64).collect(),
+            SRATIONAL => values
+                .map(|(n, d)| n as i32 as f64 / d as i32 as f64)
+                .collect(),
+            _ => return None,
+        };
+        Some(values)
+    }
+}
+
+pub struct Ifd<'a> {
+    pub entries: Vec<Entry<'a>>,
+    // Offset of the next IFD, 0 if this is the last one
+    pub next: usize,
+}
+
+impl<'a> Ifd<'a> {
+    // Offsets are relative to the start of buffer, which is the start of the TIFF header
+    pub fn parse(buffer: &'a [u8], offset: usize, order: ByteOrder) -> Result<Ifd<'a>> {
+        let count = order.u16(buffer.get(offset..offset + 2).ok_or_else(truncated)?) as usize;
+        let mut entries = Vec::with_capacity(count);
+
+        for n in 0..count {
+            let s = offset + 2 + n * 12;
+            let bytes = buffer.get(s..s + 12).ok_or_else(truncated)?;
+            let tag = order.u16(&bytes[0..2]);
+            let field_type = order.u16(&bytes[2..4]);
+            let count = order.u32(&bytes[4..8]) as usize;
+
+            // Unknown types have to be skipped, as their size isnt known
+            let size = match type_size(field_type) {
+                Some(size) => size * count,
+                None => continue,
+            };
+
+            // Values that fit in 4 bytes are stored in place of the offset
+            let data = if size <= 4 {
+                &bytes[8..8 + size]
+            } else {
+                let value_offset = order.u32(&bytes[8..12]) as usize;
+                match buffer.get(value_offset..value_offset + size) {
+                    Some(data) => data,
+                    None => continue,
+                }
+            };
+
+            entries.push(Entry {
+                tag,
+                field_type,
+                order,
+                data,
+            });
+        }
+
+        let s = offset + 2 + count * 12;
+        let next = buffer
+            .get(s..s + 4)
+            .map(|bytes| order.u32(bytes) as usize)
+            .unwrap_or(0);
+
+        Ok(Ifd { entries, next })
+    }
+
+    pub fn get(&self, tag: u16) -> Option<&Entry<'a>> {
+        self.entries.iter().find(|entry| entry.tag == tag)
+    }
+
+    pub fn values(&self, tag: u16) -> Option<Vec<u32>> {
+        self.get(tag).and_then(|entry| entry.values())
+    }
+
+    pub fn value(&self, tag: u16) -> Option<u32> {
+        self.get(tag).and_then(|entry| entry.value())
+    }
+}
+
+// Returns the byte order and offset of the first IFD
+pub fn parse_header(buffer: &[u8]) -> Result<(ByteOrder, usize)> {
+    if buffer.len() < 8 {
+        return Err(truncated());
+    }
+
+    let order = match &buffer[0..2] {
+        b"II" => ByteOrder::LittleEndian,
+        b"MM" => ByteOrder::BigEndian,
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Invalid TIFF byte order",
+            ))
+        }
+    };
+
+    if order.u16(&buffer[2..4]) != 42 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "Invalid TIFF magic number",
+        ));
+    }
+
+    Ok((order, order.u32(&buffer[4..8]) as usize))
+}
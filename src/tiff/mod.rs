@@ -0,0 +1,636 @@
+mod compression;
+pub mod ifd;
+
+use crate::common::*;
+use ifd::{ByteOrder, Ifd};
+use std::io::{Error, ErrorKind, Result};
+
+pub mod tags {
+    pub const IMAGE_WIDTH: u16 = 256;
+    pub const IMAGE_LENGTH: u16 = 257;
+    pub const BITS_PER_SAMPLE: u16 = 258;
+    pub const COMPRESSION: u16 = 259;
+    pub const PHOTOMETRIC_INTERPRETATION: u16 = 262;
+    pub const STRIP_OFFSETS: u16 = 273;
+    pub const SAMPLES_PER_PIXEL: u16 = 277;
+    pub const ROWS_PER_STRIP: u16 = 278;
+    pub const STRIP_BYTE_COUNTS: u16 = 279;
+    pub const PLANAR_CONFIGURATION: u16 = 284;
+    pub const PREDICTOR: u16 = 317;
+    pub const COLOR_MAP: u16 = 320;
+    pub const TILE_WIDTH: u16 = 322;
+    pub const TILE_LENGTH: u16 = 323;
+    pub const TILE_OFFSETS: u16 = 324;
+    pub const TILE_BYTE_COUNTS: u16 = 325;
+    pub const EXTRA_SAMPLES: u16 = 338;
+    pub const SAMPLE_FORMAT: u16 = 339;
+}
+
+mod compression_types {
+    pub const NONE: u32 = 1;
+    pub const LZW: u32 = 5;
+    pub const DEFLATE: u32 = 8;
+    pub const PACKBITS: u32 = 32773;
+    // Used by older versions of libtiff, identical to DEFLATE
+    pub const DEFLATE_OLD: u32 = 32946;
+}
+
+mod photometric {
+    pub const WHITE_IS_ZERO: u32 = 0;
+    pub const BLACK_IS_ZERO: u32 = 1;
+    pub const RGB: u32 = 2;
+    pub const PALETTE: u32 = 3;
+}
+
+const HORIZONTAL_PREDICTOR: u32 = 2;
+
+// Color channels and the extra samples after them, far more than any real image has
+const MAX_SAMPLES_PER_PIXEL: usize = 16;
+
+// Values of the ExtraSamples tag for an alpha channel
+const ASSOCIATED_ALPHA: u32 = 1;
+const UNASSOCIATED_ALPHA: u32 = 2;
+
+pub fn is_tiff(buffer: &[u8]) -> bool {
+    buffer.starts_with(b"II*\0") || buffer.starts_with(b"MM\0*")
+}
+
+fn missing(tag: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("TIFF is missing the {} tag", tag),
+    )
+}
+
+fn unsupported(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+// Each page has its own IFD, and they are stored as a linked list
+fn page_offsets(buffer: &[u8]) -> Result<(ByteOrder, Vec<usize>)> {
+    let (order, mut offset) = ifd::parse_header(buffer)?;
+    let mut offsets = Vec::new();
+
+    // Malformed files can have cycles in the list
+    while offset != 0 && !offsets.contains(&offset) {
+        offsets.push(offset);
+        offset = Ifd::parse(buffer, offset, order)?.next;
+    }
+
+    Ok((order, offsets))
+}
+
+fn decompress(bytes: &[u8], compression: u32, expected_len: usize) -> Result<Vec<u8>> {
+    match compression {
+        compression_types::NONE => Ok(bytes.to_vec()),
+        compression_types::LZW => compression::lzw(bytes, expected_len),
+        compression_types::DEFLATE | compression_types::DEFLATE_OLD => compression::deflate(bytes),
+        compression_types::PACKBITS => compression::packbits(bytes, expected_len),
+        _ => Err(unsupported(format!(
+            "Unsupported TIFF compression: {}",
+            compression
+        ))),
+    }
+}
+
+// Predictor 2 stores each sample as the difference from the same sample of the previous pixel
+fn undo_predictor(
+    data: &mut [u8],
+    row_bytes: usize,
+    samples_per_pixel: usize,
+    bit_depth: usize,
+    order: ByteOrder,
+) -> Result<()> {
+    for row in data.chunks_mut(row_bytes) {
+        match bit_depth {
+            8 => {
+                for i in samples_per_pixel..row.len() {
+                    row[i] = row[i].wrapping_add(row[i - samples_per_pixel]);
+                }
+            }
+            16 => {
+                let samples = row.len() / 2;
+                for i in samples_per_pixel..samples {
+                    let prev = order.u16(&row[(i - samples_per_pixel) * 2..]);
+                    let val = order.u16(&row[i * 2..]).wrapping_add(prev);
+                    let bytes = match order {
+                        ByteOrder::LittleEndian => val.to_le_bytes(),
+                        ByteOrder::BigEndian => val.to_be_bytes(),
+                    };
+                    row[i * 2..i * 2 + 2].copy_from_slice(&bytes);
+                }
+            }
+            _ => {
+                return Err(unsupported(format!(
+                    "Horizontal predictor is unsupported for bit depth {}",
+                    bit_depth
+                )))
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn parse(buffer: Vec<u8>, metadata: &mut Metadata, page: usize) -> Result<Image<RGBColor>> {
+    let (order, offsets) = page_offsets(&buffer)?;
+    let offset = match offsets.get(page) {
+        Some(offset) => *offset,
+        None => {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "Page {} not found, the image has {} page(s)",
+                    page,
+                    offsets.len()
+                ),
+            ))
+        }
+    };
+
+    let ifd = Ifd::parse(&buffer, offset, order)?;
+
+    let width = ifd
+        .value(tags::IMAGE_WIDTH)
+        .ok_or_else(|| missing("ImageWidth"))? as usize;
+    let height = ifd
+        .value(tags::IMAGE_LENGTH)
+        .ok_or_else(|| missing("ImageLength"))? as usize;
+    let photometric = ifd
+        .value(tags::PHOTOMETRIC_INTERPRETATION)
+        .ok_or_else(|| missing("PhotometricInterpretation"))?;
+    let samples_per_pixel = ifd.value(tags::SAMPLES_PER_PIXEL).unwrap_or(1) as usize;
+    let compression = ifd
+        .value(tags::COMPRESSION)
+        .unwrap_or(compression_types::NONE);
+    let predictor = ifd.value(tags::PREDICTOR).unwrap_or(1);
+
+    let bits = ifd.values(tags::BITS_PER_SAMPLE).unwrap_or_else(|| vec![1]);
+    let bit_depth = bits[0] as usize;
+    if bits.iter().any(|&b| b as usize != bit_depth) {
+        return Err(unsupported(
+            "TIFF samples with different bit depths are unsupported".to_owned(),
+        ));
+    }
+
+    if ifd.value(tags::SAMPLE_FORMAT).unwrap_or(1) != 1 {
+        return Err(unsupported(
+            "Only unsigned integer TIFF samples are supported".to_owned(),
+        ));
+    }
+
+    if samples_per_pixel > 1 && ifd.value(tags::PLANAR_CONFIGURATION).unwrap_or(1) != 1 {
+        return Err(unsupported("Planar TIFF images are unsupported".to_owned()));
+    }
+
+    let color_channels = match (photometric, bit_depth) {
+        (photometric::WHITE_IS_ZERO, 1)
+        | (photometric::WHITE_IS_ZERO, 2)
+        | (photometric::WHITE_IS_ZERO, 4)
+        | (photometric::WHITE_IS_ZERO, 8)
+        | (photometric::WHITE_IS_ZERO, 16)
+        | (photometric::BLACK_IS_ZERO, 1)
+        | (photometric::BLACK_IS_ZERO, 2)
+        | (photometric::BLACK_IS_ZERO, 4)
+        | (photometric::BLACK_IS_ZERO, 8)
+        | (photometric::BLACK_IS_ZERO, 16)
+        | (photometric::PALETTE, 1)
+        | (photometric::PALETTE, 2)
+        | (photometric::PALETTE, 4)
+        | (photometric::PALETTE, 8) => 1,
+        (photometric::RGB, 8) | (photometric::RGB, 16) => 3,
+        _ => {
+            return Err(unsupported(format!(
+                "Unsupported TIFF photometric interpretation {} with bit depth {}",
+                photometric, bit_depth
+            )))
+        }
+    };
+
+    if samples_per_pixel < color_channels {
+        return Err(unsupported(format!(
+            "Not enough samples per pixel: {}",
+            samples_per_pixel
+        )));
+    }
+
+    let extra_sample = ifd
+        .values(tags::EXTRA_SAMPLES)
+        .and_then(|values| values.first().copied());
+    let has_alpha = samples_per_pixel > color_channels
+        && (extra_sample == Some(ASSOCIATED_ALPHA) || extra_sample == Some(UNASSOCIATED_ALPHA));
+    let associated_alpha = extra_sample == Some(ASSOCIATED_ALPHA);
+
+    let palette = if photometric == photometric::PALETTE {
+        let map = ifd
+            .values(tags::COLOR_MAP)
+            .ok_or_else(|| missing("ColorMap"))?;
+        let n = 1 << bit_depth;
+        if map.len() < n * 3 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "TIFF ColorMap is too short",
+            ));
+        }
+        // All the reds come first, then the greens and blues. Values are 16 bit
+        (0..n)
+            .map(|i| {
+                (
+                    (map[i] >> 8) as u8,
+                    (map[n + i] >> 8) as u8,
+                    (map[2 * n + i] >> 8) as u8,
+                )
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    // Limits the size of the buffer the samples are decoded into, so a header can't ask for more
+    // memory than there is
+    checked_pixels(width, height)?;
+    if samples_per_pixel > MAX_SAMPLES_PER_PIXEL {
+        return Err(unsupported(format!(
+            "Too many samples per pixel: {}",
+            samples_per_pixel
+        )));
+    }
+    let bits_per_pixel = samples_per_pixel * bit_depth;
+    let row_bytes = (width * bits_per_pixel).div_ceil(8);
+    let mut raw = vec![0u8; row_bytes * height];
+
+    let chunk = |offset: u32, count: u32| -> Result<&[u8]> {
+        buffer
+            .get(offset as usize..offset as usize + count as usize)
+            .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "TIFF image data is truncated"))
+    };
+
+    if let Some(tile_offsets) = ifd.values(tags::TILE_OFFSETS) {
+        let tile_width = ifd
+            .value(tags::TILE_WIDTH)
+            .ok_or_else(|| missing("TileWidth"))? as usize;
+        let tile_length = ifd
+            .value(tags::TILE_LENGTH)
+            .ok_or_else(|| missing("TileLength"))? as usize;
+        let byte_counts = ifd
+            .values(tags::TILE_BYTE_COUNTS)
+            .ok_or_else(|| missing("TileByteCounts"))?;
+        if tile_width == 0 || tile_length == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid TIFF tile size: {}x{}", tile_width, tile_length),
+            ));
+        }
+        checked_pixels(tile_width, tile_length)?;
+
+        // Tile widths are a multiple of 16, so tiles always start on a byte boundary
+        let tiles_across = width.div_ceil(tile_width);
+        let tile_row_bytes = (tile_width * bits_per_pixel).div_ceil(8);
+
+        for (i, (&offset, &count)) in tile_offsets.iter().zip(byte_counts.iter()).enumerate() {
+            let mut data = decompress(
+                chunk(offset, count)?,
+                compression,
+                tile_row_bytes * tile_length,
+            )?;
+            data.resize(tile_row_bytes * tile_length, 0);
+            if predictor == HORIZONTAL_PREDICTOR {
+                undo_predictor(
+                    &mut data,
+                    tile_row_bytes,
+                    samples_per_pixel,
+                    bit_depth,
+                    order,
+                )?;
+            }
+
+            let (tx, ty) = (i % tiles_across, i / tiles_across);
+            let x_start = tx * tile_row_bytes;
+            if x_start >= row_bytes {
+                continue;
+            }
+            let len = tile_row_bytes.min(row_bytes - x_start);
+
+            for row in 0..tile_length {
+                let y = ty * tile_length + row;
+                if y >= height {
+                    break;
+                }
+                let dst = y * row_bytes + x_start;
+                raw[dst..dst + len]
+                    .copy_from_slice(&data[row * tile_row_bytes..row * tile_row_bytes + len]);
+            }
+        }
+    } else {
+        let strip_offsets = ifd
+            .values(tags::STRIP_OFFSETS)
+            .ok_or_else(|| missing("StripOffsets"))?;
+        let rows_per_strip = (ifd.value(tags::ROWS_PER_STRIP).unwrap_or(height as u32) as usize)
+            .min(height)
+            .max(1);
+
+        // Some writers leave out the byte counts for uncompressed images
+        let byte_counts = match ifd.values(tags::STRIP_BYTE_COUNTS) {
+            Some(counts) => counts,
+            None if compression == compression_types::NONE => {
+                vec![(rows_per_strip * row_bytes) as u32; strip_offsets.len()]
+            }
+            None => return Err(missing("StripByteCounts")),
+        };
+
+        for (i, (&offset, &count)) in strip_offsets.iter().zip(byte_counts.iter()).enumerate() {
+            let y = i * rows_per_strip;
+            if y >= height {
+                break;
+            }
+            let len = rows_per_strip.min(height - y) * row_bytes;
+
+            let mut data = decompress(chunk(offset, count)?, compression, len)?;
+            data.resize(len, 0);
+            if predictor == HORIZONTAL_PREDICTOR {
+                undo_predictor(&mut data, row_bytes, samples_per_pixel, bit_depth, order)?;
+            }
+
+            raw[y * row_bytes..y * row_bytes + len].copy_from_slice(&data);
+        }
+    }
+
+    // Raw value of sample c of pixel x in row
+    let sample = |row: &[u8], x: usize, c: usize| -> u32 {
+        let index = x * samples_per_pixel + c;
+        match bit_depth {
+            8 => row[index] as u32,
+            16 => order.u16(&row[index * 2..]) as u32,
+            _ => {
+                let bit = index * bit_depth;
+                ((row[bit / 8] >> (8 - bit_depth - bit % 8)) & ((1 << bit_depth) - 1)) as u32
+            }
+        }
+    };
+
+    // Scales a raw sample to 8 bits
+    let scale = |val: u32| -> u8 {
+        match bit_depth {
+            8 => val as u8,
+            16 => (val >> 8) as u8,
+            _ => (val * 255 / ((1 << bit_depth) - 1)) as u8,
+        }
+    };
+
+    let mut image: Image<RGBColor> = ImageBuffer::with_width(width, height);
+    for y in 0..height {
+        let row = &raw[y * row_bytes..(y + 1) * row_bytes];
+        let mut scanline = Vec::with_capacity(width);
+
+        for x in 0..width {
+            let (r, g, b) = match photometric {
+                photometric::WHITE_IS_ZERO => {
+                    let val = 255 - scale(sample(row, x, 0));
+                    (val, val, val)
+                }
+                photometric::BLACK_IS_ZERO => {
+                    let val = scale(sample(row, x, 0));
+                    (val, val, val)
+                }
+                photometric::RGB => (
+                    scale(sample(row, x, 0)),
+                    scale(sample(row, x, 1)),
+                    scale(sample(row, x, 2)),
+                ),
+                _ => palette[sample(row, x, 0) as usize],
+            };
+
+            let a = if has_alpha {
+                scale(sample(row, x, color_channels))
+            } else {
+                255
+            };
+
+            scanline.push(if !associated_alpha {
+                rgba_color(r, g, b, a)
+            } else if a == 0 {
+                (0, 0, 0)
+            } else {
+                // Associated alpha is already premultiplied, which is how colors are displayed
+                opaque(r, g, b)
+            });
+        }
+
+        image.push_row(&scanline);
+    }
+
+    let color_type = match photometric {
+        photometric::PALETTE => ColorType::Palette,
+        photometric::RGB if has_alpha => ColorType::RGBA,
+        photometric::RGB => ColorType::RGB,
+        _ if has_alpha => ColorType::GrayA,
+        _ => ColorType::Gray,
+    };
+    metadata.set_image_info(width as u32, height as u32, bit_depth as u8, color_type);
+    if !palette.is_empty() {
+        metadata.set_palette(palette);
+    }
+
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A little endian TIFF with the image data right after the header, and one IFD after that.
+    // Entries are written as LONGs, out of line when there is more than one value
+    fn tiff(entries: &[(u16, &[u32])], data: &[u8]) -> Vec<u8> {
+        let ifd_offset = 8 + data.len();
+        let mut extra_offset = ifd_offset + 2 + entries.len() * 12 + 4;
+        let mut extra = Vec::new();
+
+        let mut out = b"II*\0".to_vec();
+        out.extend_from_slice(&(ifd_offset as u32).to_le_bytes());
+        out.extend_from_slice(data);
+        out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        for (tag, values) in entries {
+            out.extend_from_slice(&tag.to_le_bytes());
+            out.extend_from_slice(&ifd::LONG.to_le_bytes());
+            out.extend_from_slice(&(values.len() as u32).to_le_bytes());
+            if values.len() == 1 {
+                out.extend_from_slice(&values[0].to_le_bytes());
+            } else {
+                out.extend_from_slice(&(extra_offset as u32).to_le_bytes());
+                for value in values.iter() {
+                    extra.extend_from_slice(&value.to_le_bytes());
+                }
+                extra_offset += values.len() * 4;
+            }
+        }
+        out.extend_from_slice(&0u32.to_le_bytes());
+        out.extend(extra);
+        out
+    }
+
+    // 8 bit gray, with the strips stored one after the other
+    fn gray_strips(
+        width: u32,
+        height: u32,
+        compression: u32,
+        rows_per_strip: u32,
+        strips: &[&[u8]],
+    ) -> Vec<u8> {
+        let counts: Vec<u32> = strips.iter().map(|strip| strip.len() as u32).collect();
+        let offsets: Vec<u32> = (0..strips.len())
+            .map(|i| 8 + counts[..i].iter().sum::<u32>())
+            .collect();
+        tiff(
+            &[
+                (tags::IMAGE_WIDTH, &[width]),
+                (tags::IMAGE_LENGTH, &[height]),
+                (tags::BITS_PER_SAMPLE, &[8]),
+                (tags::COMPRESSION, &[compression]),
+                (
+                    tags::PHOTOMETRIC_INTERPRETATION,
+                    &[photometric::BLACK_IS_ZERO],
+                ),
+                (tags::STRIP_OFFSETS, &offsets),
+                (tags::ROWS_PER_STRIP, &[rows_per_strip]),
+                (tags::STRIP_BYTE_COUNTS, &counts),
+            ],
+            &strips.concat(),
+        )
+    }
+
+    fn grays(image: &Image<RGBColor>) -> Vec<u8> {
+        image.pixels().map(|px| px.0).collect()
+    }
+
+    fn decode(buffer: Vec<u8>) -> Result<Image<RGBColor>> {
+        parse(buffer, &mut Metadata::new(), 0)
+    }
+
+    #[test]
+    fn strips() {
+        let buffer = gray_strips(
+            3,
+            3,
+            compression_types::NONE,
+            2,
+            &[&[10, 20, 30, 40, 50, 60], &[70, 80, 90]],
+        );
+        let image = decode(buffer).unwrap();
+        assert_eq!((image.width(), image.height()), (3, 3));
+        assert_eq!(grays(&image), [10, 20, 30, 40, 50, 60, 70, 80, 90]);
+    }
+
+    #[test]
+    fn packbits() {
+        // 3 copies of 10, then the 2 literal bytes 20 and 30, a no-op, and 3 copies of 40
+        let strip = [0xFE, 10, 1, 20, 30, 0x80, 0xFE, 40];
+        let buffer = gray_strips(4, 2, compression_types::PACKBITS, 2, &[&strip]);
+        assert_eq!(
+            grays(&decode(buffer).unwrap()),
+            [10, 10, 10, 20, 30, 40, 40, 40]
+        );
+    }
+
+    // Codes of 9 bits, packed starting from the most significant bit
+    fn pack_codes(codes: &[u16]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let (mut value, mut bits) = (0u32, 0);
+        for &code in codes {
+            value = (value << 9) | code as u32;
+            bits += 9;
+            while bits >= 8 {
+                bits -= 8;
+                out.push((value >> bits) as u8);
+            }
+        }
+        if bits > 0 {
+            out.push((value << (8 - bits)) as u8);
+        }
+        out
+    }
+
+    #[test]
+    fn lzw() {
+        // ABABABA: A, B, then 258 for AB, and 260 for ABA, which is defined by its own use
+        let strip = pack_codes(&[256, 65, 66, 258, 260, 257]);
+        let buffer = gray_strips(7, 1, compression_types::LZW, 1, &[&strip]);
+        assert_eq!(grays(&decode(buffer).unwrap()), b"ABABABA");
+
+        let strip = pack_codes(&[256, 65, 300, 257]);
+        let buffer = gray_strips(2, 1, compression_types::LZW, 1, &[&strip]);
+        assert_eq!(decode(buffer).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn predictor() {
+        // Each sample is the difference from the same channel of the pixel to its left
+        let data = [100, 50, 20, 10, 5, 246];
+        let buffer = tiff(
+            &[
+                (tags::IMAGE_WIDTH, &[2]),
+                (tags::IMAGE_LENGTH, &[1]),
+                (tags::BITS_PER_SAMPLE, &[8, 8, 8]),
+                (tags::PHOTOMETRIC_INTERPRETATION, &[photometric::RGB]),
+                (tags::STRIP_OFFSETS, &[8]),
+                (tags::SAMPLES_PER_PIXEL, &[3]),
+                (tags::STRIP_BYTE_COUNTS, &[6]),
+                (tags::PREDICTOR, &[HORIZONTAL_PREDICTOR]),
+            ],
+            &data,
+        );
+        let image = decode(buffer).unwrap();
+        assert_eq!(image.row(0), &[(100, 50, 20), (110, 55, 10)]);
+    }
+
+    // 4 tiles over a 20x3 image, numbered in the top left byte of each. With 16x2 tiles, the
+    // ones on the right and bottom are cut off
+    fn gray_tiles(tile_width: u32, tile_length: u32) -> Vec<u8> {
+        let tile_size = (tile_width * tile_length) as usize;
+        let data: Vec<u8> = (0..4)
+            .flat_map(|tile| (0..tile_size).map(move |i| (tile * 50 + i) as u8))
+            .collect();
+        let offsets: Vec<u32> = (0..4).map(|tile| 8 + tile * tile_size as u32).collect();
+        tiff(
+            &[
+                (tags::IMAGE_WIDTH, &[20]),
+                (tags::IMAGE_LENGTH, &[3]),
+                (tags::BITS_PER_SAMPLE, &[8]),
+                (
+                    tags::PHOTOMETRIC_INTERPRETATION,
+                    &[photometric::BLACK_IS_ZERO],
+                ),
+                (tags::TILE_WIDTH, &[tile_width]),
+                (tags::TILE_LENGTH, &[tile_length]),
+                (tags::TILE_OFFSETS, &offsets),
+                (tags::TILE_BYTE_COUNTS, &[tile_size as u32; 4]),
+            ],
+            &data,
+        )
+    }
+
+    #[test]
+    fn tiles() {
+        let image = decode(gray_tiles(16, 2)).unwrap();
+        assert_eq!((image.width(), image.height()), (20, 3));
+        // Tile 0 covers the top left, tile 1 the top right, and so on
+        assert_eq!(image[(0, 0)].0, 0);
+        assert_eq!(image[(15, 1)].0, 31);
+        assert_eq!(image[(16, 0)].0, 50);
+        assert_eq!(image[(19, 1)].0, 50 + 16 + 3);
+        assert_eq!(image[(0, 2)].0, 100);
+        assert_eq!(image[(19, 2)].0, 150 + 3);
+    }
+
+    #[test]
+    fn zero_tile_size() {
+        for (tile_width, tile_length) in [(0, 2), (16, 0)] {
+            let error = decode(gray_tiles(tile_width, tile_length)).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn too_large() {
+        let buffer = gray_strips(u32::MAX, u32::MAX, compression_types::NONE, 1, &[&[0]]);
+        assert_eq!(decode(buffer).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,50 @@
+use std::io::{Error, ErrorKind, Result};
+
+// Reads bits starting from the least significant bit of each byte, as used by VP8L
+pub struct BitReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    value: u64,
+    nbits: u32,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> BitReader<'a> {
+        BitReader {
+            bytes,
+            pos: 0,
+            value: 0,
+            nbits: 0,
+        }
+    }
+
+    fn fill(&mut self) {
+        while self.nbits <= 56 && self.pos < self.bytes.len() {
+            self.value |= (self.bytes[self.pos] as u64) << self.nbits;
+            self.pos += 1;
+            self.nbits += 8;
+        }
+    }
+
+    // n must be at most 32
+    pub fn read_bits(&mut self, n: u32) -> Result<u32> {
+        if n == 0 {
+            return Ok(0);
+        }
+
+        if self.nbits < n {
+            self.fill();
+            if self.nbits < n {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "WebP bitstream is truncated",
+                ));
+            }
+        }
+
+        let bits = (self.value & ((1u64 << n) - 1)) as u32;
+        self.value >>= n;
+        self.nbits -= n;
+        Ok(bits)
+    }
+}
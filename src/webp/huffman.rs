@@ -0,0 +1,102 @@
+use super::bit_reader::BitReader;
+use std::io::{Error, ErrorKind, Result};
+
+const MAX_LENGTH: usize = 15;
+
+// Canonical prefix code, decoded one bit at a time
+pub struct HuffmanCode {
+    // Number of codes of each length
+    counts: [u16; MAX_LENGTH + 1],
+    // Symbols ordered by their codes
+    symbols: Vec<u16>,
+    // When only one symbol is used, it is decoded without reading any bits
+    single: Option<u16>,
+}
+
+impl HuffmanCode {
+    pub fn from_lengths(lengths: &[u8]) -> Result<HuffmanCode> {
+        let mut counts = [0u16; MAX_LENGTH + 1];
+        let mut used = None;
+        let mut num_used = 0;
+
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length as usize > MAX_LENGTH {
+                return Err(Error::new(ErrorKind::InvalidData, "Invalid code length"));
+            }
+            if length > 0 {
+                counts[length as usize] += 1;
+                used = Some(symbol as u16);
+                num_used += 1;
+            }
+        }
+
+        if num_used == 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "Empty prefix code"));
+        }
+
+        if num_used == 1 {
+            return Ok(HuffmanCode {
+                counts,
+                symbols: Vec::new(),
+                single: used,
+            });
+        }
+
+        // Make sure the code isnt over-subscribed
+        let mut left = 1i32;
+        for &count in counts.iter().skip(1) {
+            left = (left << 1) - count as i32;
+            if left < 0 {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "Over-subscribed prefix code",
+                ));
+            }
+        }
+
+        // Offsets of the first symbol of each length in the sorted symbols
+        let mut offsets = [0u16; MAX_LENGTH + 2];
+        for len in 1..=MAX_LENGTH {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+
+        let mut symbols = vec![0; num_used];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length > 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+
+        Ok(HuffmanCode {
+            counts,
+            symbols,
+            single: None,
+        })
+    }
+
+    pub fn decode(&self, reader: &mut BitReader) -> Result<u16> {
+        if let Some(symbol) = self.single {
+            return Ok(symbol);
+        }
+
+        // code is the bits read so far, first the first code of the current length and index the
+        // position of that code in the sorted symbols
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+
+        for len in 1..=MAX_LENGTH {
+            code |= reader.read_bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err(Error::new(ErrorKind::InvalidData, "Invalid prefix code"))
+    }
+}
@@ -0,0 +1,349 @@
+mod bit_reader;
+mod huffman;
+mod transform;
+mod vp8l;
+
+use crate::common::*;
+use crate::exif;
+use std::io::{Error, ErrorKind, Result};
+
+// VP8X feature flags
+const ANIMATION_FLAG: u8 = 0x02;
+const ALPHA_FLAG: u8 = 0x10;
+
+// ANMF frame flags
+const DISPOSE_FLAG: u8 = 0x01;
+const NO_BLEND_FLAG: u8 = 0x02;
+
+pub mod chunk_types {
+    pub static VP8: [u8; 4] = *b"VP8 ";
+    pub static VP8L: [u8; 4] = *b"VP8L";
+    pub static VP8X: [u8; 4] = *b"VP8X";
+    pub static ANIM: [u8; 4] = *b"ANIM";
+    pub static ANMF: [u8; 4] = *b"ANMF";
+    pub static EXIF: [u8; 4] = *b"EXIF";
+}
+
+pub fn is_webp(buffer: &[u8]) -> bool {
+    buffer.len() >= 12 && &buffer[0..4] == b"RIFF" && &buffer[8..12] == b"WEBP"
+}
+
+pub struct Frame {
+    pub image: Image<RGBColor>,
+    // In milliseconds
+    pub duration: u32,
+}
+
+pub struct Animation {
+    pub frames: Vec<Frame>,
+    // 0 means the animation loops forever
+    pub loop_count: u16,
+}
+
+struct Chunk<'a> {
+    fourcc: [u8; 4],
+    data: &'a [u8],
+}
+
+fn truncated() -> Error {
+    Error::new(ErrorKind::UnexpectedEof, "WebP file is truncated")
+}
+
+fn from_bytes_u24_le(bytes: &[u8]) -> usize {
+    bytes[0] as usize | (bytes[1] as usize) << 8 | (bytes[2] as usize) << 16
+}
+
+// RIFF chunks are a FourCC, a little endian size and the data padded to an even length
+fn read_chunks(bytes: &[u8]) -> Result<Vec<Chunk<'_>>> {
+    let mut chunks = Vec::new();
+    let mut i = 0;
+
+    while i + 8 <= bytes.len() {
+        let mut fourcc = [0; 4];
+        fourcc.copy_from_slice(&bytes[i..i + 4]);
+        let size = from_bytes_u32_le(&bytes[i + 4..i + 8]) as usize;
+        i += 8;
+
+        let data = bytes.get(i..i + size).ok_or_else(truncated)?;
+        chunks.push(Chunk { fourcc, data });
+        i += size + (size & 1);
+    }
+
+    Ok(chunks)
+}
+
+fn find<'a, 'b>(chunks: &'b [Chunk<'a>], fourcc: &[u8; 4]) -> Option<&'b Chunk<'a>> {
+    chunks.iter().find(|chunk| &chunk.fourcc == fourcc)
+}
+
+// Decodes the image data of a still image or an animation frame into straight ARGB
+fn decode_bitstream(chunks: &[Chunk]) -> Result<vp8l::VP8LImage> {
+    if let Some(chunk) = find(chunks, &chunk_types::VP8L) {
+        vp8l::decode(chunk.data)
+    } else if find(chunks, &chunk_types::VP8).is_some() {
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            "Lossy (VP8) WebP images are currently unsupported",
+        ))
+    } else {
+        Err(Error::new(ErrorKind::NotFound, "WebP image data not found"))
+    }
+}
+
+fn to_image(width: usize, height: usize, pixels: &[u32]) -> Image<RGBColor> {
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let p = pixels[y * width + x];
+        rgba_color((p >> 16) as u8, (p >> 8) as u8, p as u8, (p >> 24) as u8)
+    })
+}
+
+// Alpha blends src over dst, both being straight (not premultiplied) ARGB
+fn blend(src: u32, dst: u32) -> u32 {
+    let src_a = src >> 24;
+    if src_a == 255 {
+        return src;
+    }
+    if src_a == 0 {
+        return dst;
+    }
+
+    let dst_a = (dst >> 24) * (255 - src_a) / 255;
+    let a = src_a + dst_a;
+
+    let mut out = a << 24;
+    for shift in [0, 8, 16].iter() {
+        let s = (src >> shift) & 0xff;
+        let d = (dst >> shift) & 0xff;
+        out |= ((s * src_a + d * dst_a) / a) << shift;
+    }
+    out
+}
+
+// Still images are returned as an animation with a single frame
+pub fn parse_animation(buffer: Vec<u8>, metadata: &mut Metadata) -> Result<Animation> {
+    if !is_webp(&buffer) {
+        return Err(Error::new(ErrorKind::InvalidData, "Invalid WebP header"));
+    }
+
+    // The RIFF size covers everything after the size field itself
+    let riff_end = (from_bytes_u32_le(&buffer[4..8]) as usize + 8).min(buffer.len());
+    let chunks = read_chunks(&buffer[12..riff_end])?;
+
+    let first = match chunks.first() {
+        Some(chunk) => chunk,
+        None => return Err(truncated()),
+    };
+
+    if first.fourcc != chunk_types::VP8X {
+        let image = decode_bitstream(&chunks)?;
+        metadata.set_image_info(
+            image.width as u32,
+            image.height as u32,
+            8,
+            if image.has_alpha {
+                ColorType::RGBA
+            } else {
+                ColorType::RGB
+            },
+        );
+
+        return Ok(Animation {
+            frames: vec![Frame {
+                image: to_image(image.width, image.height, &image.pixels),
+                duration: 0,
+            }],
+            loop_count: 1,
+        });
+    }
+
+    if first.data.len() < 10 {
+        return Err(truncated());
+    }
+
+    let flags = first.data[0];
+    let width = from_bytes_u24_le(&first.data[4..7]) + 1;
+    let height = from_bytes_u24_le(&first.data[7..10]) + 1;
+    checked_pixels(width, height)?;
+
+    metadata.set_image_info(
+        width as u32,
+        height as u32,
+        8,
+        if flags & ALPHA_FLAG != 0 {
+            ColorType::RGBA
+        } else {
+            ColorType::RGB
+        },
+    );
+
+    // Only extended files can have metadata chunks
+    if let Some(chunk) = find(&chunks, &chunk_types::EXIF) {
+        match exif::parse(chunk.data) {
+            Ok(exif) => metadata.set_exif(exif),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
+    if flags & ANIMATION_FLAG == 0 {
+        let image = decode_bitstream(&chunks)?;
+        if (image.width, image.height) != (width, height) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "WebP image is {}x{}, but its canvas is {}x{}",
+                    image.width, image.height, width, height
+                ),
+            ));
+        }
+        return Ok(Animation {
+            frames: vec![Frame {
+                image: to_image(image.width, image.height, &image.pixels),
+                duration: 0,
+            }],
+            loop_count: 1,
+        });
+    }
+
+    // The background color in ANIM is only a hint, the canvas starts out transparent so that it
+    // matches the terminal background
+    let loop_count = match find(&chunks, &chunk_types::ANIM) {
+        Some(anim) if anim.data.len() >= 6 => from_bytes_u16_le(&anim.data[4..6]),
+        _ => 0,
+    };
+
+    let mut canvas = vec![0u32; width * height];
+    let mut frames = Vec::new();
+
+    for chunk in chunks.iter().filter(|c| c.fourcc == chunk_types::ANMF) {
+        if chunk.data.len() < 16 {
+            return Err(truncated());
+        }
+
+        let x_offset = from_bytes_u24_le(&chunk.data[0..3]) * 2;
+        let y_offset = from_bytes_u24_le(&chunk.data[3..6]) * 2;
+        let duration = from_bytes_u24_le(&chunk.data[12..15]) as u32;
+        let frame_flags = chunk.data[15];
+
+        let frame = decode_bitstream(&read_chunks(&chunk.data[16..])?)?;
+
+        if x_offset + frame.width > width || y_offset + frame.height > height {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Animation frame is outside the canvas",
+            ));
+        }
+
+        for y in 0..frame.height {
+            for x in 0..frame.width {
+                let src = frame.pixels[y * frame.width + x];
+                let dst = &mut canvas[(y + y_offset) * width + x + x_offset];
+                *dst = if frame_flags & NO_BLEND_FLAG != 0 {
+                    src
+                } else {
+                    blend(src, *dst)
+                };
+            }
+        }
+
+        frames.push(Frame {
+            image: to_image(width, height, &canvas),
+            duration,
+        });
+
+        // Disposal happens after the frame has been shown, before the next one is drawn
+        if frame_flags & DISPOSE_FLAG != 0 {
+            for y in y_offset..y_offset + frame.height {
+                for p in &mut canvas[y * width + x_offset..y * width + x_offset + frame.width] {
+                    *p = 0;
+                }
+            }
+        }
+    }
+
+    if frames.is_empty() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            "Animated WebP has no frames",
+        ));
+    }
+
+    Ok(Animation { frames, loop_count })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::vp8l::tests::encode;
+    use super::*;
+
+    fn chunk(fourcc: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut bytes = fourcc.to_vec();
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            bytes.push(0);
+        }
+        bytes
+    }
+
+    fn riff(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body = chunks.concat();
+        let mut bytes = b"RIFF".to_vec();
+        bytes.extend_from_slice(&(body.len() as u32 + 4).to_le_bytes());
+        bytes.extend_from_slice(b"WEBP");
+        bytes.extend_from_slice(&body);
+        bytes
+    }
+
+    fn vp8x(flags: u8, width: usize, height: usize) -> Vec<u8> {
+        let mut data = vec![flags, 0, 0, 0];
+        data.extend_from_slice(&(width as u32 - 1).to_le_bytes()[..3]);
+        data.extend_from_slice(&(height as u32 - 1).to_le_bytes()[..3]);
+        chunk(&chunk_types::VP8X, &data)
+    }
+
+    // A frame at the top left of the canvas
+    fn anmf(width: usize, height: usize, flags: u8, pixels: &[u32]) -> Vec<u8> {
+        let mut data = vec![0; 6];
+        data.extend_from_slice(&(width as u32 - 1).to_le_bytes()[..3]);
+        data.extend_from_slice(&(height as u32 - 1).to_le_bytes()[..3]);
+        data.extend_from_slice(&[100, 0, 0, flags]);
+        data.extend(chunk(
+            &chunk_types::VP8L,
+            &encode(width, height, |_| {}, pixels),
+        ));
+        chunk(&chunk_types::ANMF, &data)
+    }
+
+    #[test]
+    fn animation() {
+        let (red, blue) = (0xffff0000, 0xff0000ff);
+        let green = 0x8000ff00;
+        let buffer = riff(&[
+            vp8x(ANIMATION_FLAG | ALPHA_FLAG, 2, 1),
+            chunk(&chunk_types::ANIM, &[0, 0, 0, 0, 3, 0]),
+            anmf(2, 1, 0, &[red, blue]),
+            // Blended over the first frame, then cleared
+            anmf(1, 1, DISPOSE_FLAG, &[green]),
+            // Replaces the cleared pixel
+            anmf(1, 1, NO_BLEND_FLAG, &[green]),
+        ]);
+
+        let mut metadata = Metadata::new();
+        let animation = parse_animation(buffer, &mut metadata).unwrap();
+        assert_eq!(animation.loop_count, 3);
+
+        let frames: Vec<_> = animation.frames.iter().map(|f| &f.image).collect();
+        assert_eq!(frames[0], &to_image(2, 1, &[red, blue]));
+        assert_eq!(frames[1], &to_image(2, 1, &[0xff7f8000, blue]));
+        assert_eq!(frames[2], &to_image(2, 1, &[green, blue]));
+        assert!(animation.frames.iter().all(|f| f.duration == 100));
+    }
+
+    #[test]
+    fn too_large() {
+        let buffer = riff(&[vp8x(0, 1 << 24, 1 << 24)]);
+        let mut metadata = Metadata::new();
+        let e = parse_animation(buffer, &mut metadata).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,230 @@
+// Pixels are stored as ARGB in a u32 throughout the lossless decoder
+
+pub enum Transform {
+    Predictor {
+        size_bits: u32,
+        width: usize,
+        data: Vec<u32>,
+    },
+    Color {
+        size_bits: u32,
+        width: usize,
+        data: Vec<u32>,
+    },
+    SubtractGreen,
+    ColorIndexing {
+        width_bits: u32,
+        // Width of the image before the pixels were packed
+        width: usize,
+        table: Vec<u32>,
+    },
+}
+
+pub fn subsample_size(size: usize, bits: u32) -> usize {
+    (size + (1 << bits) - 1) >> bits
+}
+
+// Adds each of the channels separately modulo 256
+pub fn add_pixels(a: u32, b: u32) -> u32 {
+    let alpha_green = (a & 0xff00ff00).wrapping_add(b & 0xff00ff00) & 0xff00ff00;
+    let red_blue = (a & 0x00ff00ff).wrapping_add(b & 0x00ff00ff) & 0x00ff00ff;
+    alpha_green | red_blue
+}
+
+fn channels(p: u32) -> [i32; 4] {
+    [
+        (p >> 24) as i32,
+        ((p >> 16) & 0xff) as i32,
+        ((p >> 8) & 0xff) as i32,
+        (p & 0xff) as i32,
+    ]
+}
+
+fn from_channels(c: [i32; 4]) -> u32 {
+    ((c[0] as u32) << 24) | ((c[1] as u32) << 16) | ((c[2] as u32) << 8) | (c[3] as u32)
+}
+
+fn average2(a: u32, b: u32) -> u32 {
+    (((a ^ b) & 0xfefefefe) >> 1) + (a & b)
+}
+
+fn select(l: u32, t: u32, tl: u32) -> u32 {
+    let (cl, ct, ctl) = (channels(l), channels(t), channels(tl));
+    let mut pl = 0;
+    let mut pt = 0;
+    for i in 0..4 {
+        let p = cl[i] + ct[i] - ctl[i];
+        pl += (p - cl[i]).abs();
+        pt += (p - ct[i]).abs();
+    }
+
+    if pl < pt {
+        l
+    } else {
+        t
+    }
+}
+
+fn clamp_add_subtract_full(a: u32, b: u32, c: u32) -> u32 {
+    let (ca, cb, cc) = (channels(a), channels(b), channels(c));
+    let mut out = [0; 4];
+    for i in 0..4 {
+        out[i] = (ca[i] + cb[i] - cc[i]).clamp(0, 255);
+    }
+    from_channels(out)
+}
+
+fn clamp_add_subtract_half(a: u32, b: u32) -> u32 {
+    let (ca, cb) = (channels(a), channels(b));
+    let mut out = [0; 4];
+    for i in 0..4 {
+        out[i] = (ca[i] + (ca[i] - cb[i]) / 2).clamp(0, 255);
+    }
+    from_channels(out)
+}
+
+fn color_transform_delta(t: u8, c: u8) -> i32 {
+    ((t as i8 as i32) * (c as i8 as i32)) >> 5
+}
+
+impl Transform {
+    // Transforms are undone in the reverse order they were read in
+    pub fn apply_inverse(&self, pixels: Vec<u32>, height: usize) -> Vec<u32> {
+        match self {
+            Transform::Predictor {
+                size_bits,
+                width,
+                data,
+            } => predictor(pixels, *width, height, *size_bits, data),
+            Transform::Color {
+                size_bits,
+                width,
+                data,
+            } => color(pixels, *width, height, *size_bits, data),
+            Transform::SubtractGreen => pixels
+                .into_iter()
+                .map(|p| {
+                    let green = (p >> 8) & 0xff;
+                    add_pixels(p, (green << 16) | green)
+                })
+                .collect(),
+            Transform::ColorIndexing {
+                width_bits,
+                width,
+                table,
+            } => color_indexing(pixels, *width, height, *width_bits, table),
+        }
+    }
+}
+
+fn predictor(
+    mut pixels: Vec<u32>,
+    width: usize,
+    height: usize,
+    bits: u32,
+    data: &[u32],
+) -> Vec<u32> {
+    let block_width = subsample_size(width, bits);
+
+    for y in 0..height {
+        for x in 0..width {
+            let pos = y * width + x;
+
+            // The top row and left column have fixed predictors
+            let prediction = if y == 0 {
+                if x == 0 {
+                    0xff000000
+                } else {
+                    pixels[pos - 1]
+                }
+            } else if x == 0 {
+                pixels[pos - width]
+            } else {
+                let mode = (data[(y >> bits) * block_width + (x >> bits)] >> 8) & 0xf;
+                let l = pixels[pos - 1];
+                let t = pixels[pos - width];
+                let tl = pixels[pos - width - 1];
+                // For the rightmost column, this is the leftmost pixel of the current row
+                let tr = pixels[pos - width + 1];
+
+                match mode {
+                    1 => l,
+                    2 => t,
+                    3 => tr,
+                    4 => tl,
+                    5 => average2(average2(l, tr), t),
+                    6 => average2(l, tl),
+                    7 => average2(l, t),
+                    8 => average2(tl, t),
+                    9 => average2(t, tr),
+                    10 => average2(average2(l, tl), average2(t, tr)),
+                    11 => select(l, t, tl),
+                    12 => clamp_add_subtract_full(l, t, tl),
+                    13 => clamp_add_subtract_half(average2(l, t), tl),
+                    // 0 and the unused modes 14 and 15
+                    _ => 0xff000000,
+                }
+            };
+
+            pixels[pos] = add_pixels(pixels[pos], prediction);
+        }
+    }
+
+    pixels
+}
+
+fn color(mut pixels: Vec<u32>, width: usize, height: usize, bits: u32, data: &[u32]) -> Vec<u32> {
+    let block_width = subsample_size(width, bits);
+
+    for y in 0..height {
+        for x in 0..width {
+            let pos = y * width + x;
+            let element = data[(y >> bits) * block_width + (x >> bits)];
+            let green_to_red = element as u8;
+            let green_to_blue = (element >> 8) as u8;
+            let red_to_blue = (element >> 16) as u8;
+
+            let p = pixels[pos];
+            let green = (p >> 8) as u8;
+            let red =
+                (((p >> 16) & 0xff) as i32 + color_transform_delta(green_to_red, green)) & 0xff;
+            let blue = ((p & 0xff) as i32
+                + color_transform_delta(green_to_blue, green)
+                + color_transform_delta(red_to_blue, red as u8))
+                & 0xff;
+
+            pixels[pos] = (p & 0xff00ff00) | ((red as u32) << 16) | (blue as u32);
+        }
+    }
+
+    pixels
+}
+
+// Small color tables pack multiple indices into the green channel of one pixel
+fn color_indexing(
+    pixels: Vec<u32>,
+    width: usize,
+    height: usize,
+    width_bits: u32,
+    table: &[u32],
+) -> Vec<u32> {
+    // Indices past the end of the table are transparent black
+    let mut lookup = table.to_vec();
+    lookup.resize(256, 0);
+
+    let packed_width = subsample_size(width, width_bits);
+    let bits_per_pixel = 8 >> width_bits;
+    let mask = (1 << bits_per_pixel) - 1;
+    let per_pixel_mask = (1 << width_bits) - 1;
+
+    let mut out = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let packed = (pixels[y * packed_width + (x >> width_bits)] >> 8) & 0xff;
+            let index = (packed >> ((x & per_pixel_mask) as u32 * bits_per_pixel)) & mask;
+            out.push(lookup[index as usize]);
+        }
+    }
+
+    out
+}
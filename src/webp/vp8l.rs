@@ -0,0 +1,686 @@
+use super::bit_reader::BitReader;
+use super::huffman::HuffmanCode;
+use super::transform::*;
+use std::io::{Error, ErrorKind, Result};
+
+const SIGNATURE: u8 = 0x2f;
+
+const NUM_LITERAL_CODES: usize = 256;
+const NUM_LENGTH_CODES: usize = 24;
+const NUM_DISTANCE_CODES: usize = 40;
+const MAX_CACHE_BITS: u32 = 11;
+
+// Order in which the code lengths of the code length code are stored
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    17, 18, 0, 1, 2, 3, 4, 5, 16, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+// The first 120 distance codes refer to (x, y) offsets of nearby pixels
+#[rustfmt::skip]
+const DISTANCE_MAP: [(i8, i8); 120] = [
+    (0, 1),  (1, 0),  (1, 1),  (-1, 1), (0, 2),  (2, 0),  (1, 2),
+    (-1, 2), (2, 1),  (-2, 1), (2, 2),  (-2, 2), (0, 3),  (3, 0),
+    (1, 3),  (-1, 3), (3, 1),  (-3, 1), (2, 3),  (-2, 3), (3, 2),
+    (-3, 2), (0, 4),  (4, 0),  (1, 4),  (-1, 4), (4, 1),  (-4, 1),
+    (3, 3),  (-3, 3), (2, 4),  (-2, 4), (4, 2),  (-4, 2), (0, 5),
+    (3, 4),  (-3, 4), (4, 3),  (-4, 3), (5, 0),  (1, 5),  (-1, 5),
+    (5, 1),  (-5, 1), (2, 5),  (-2, 5), (5, 2),  (-5, 2), (4, 4),
+    (-4, 4), (3, 5),  (-3, 5), (5, 3),  (-5, 3), (0, 6),  (6, 0),
+    (1, 6),  (-1, 6), (6, 1),  (-6, 1), (2, 6),  (-2, 6), (6, 2),
+    (-6, 2), (4, 5),  (-4, 5), (5, 4),  (-5, 4), (3, 6),  (-3, 6),
+    (6, 3),  (-6, 3), (0, 7),  (7, 0),  (1, 7),  (-1, 7), (5, 5),
+    (-5, 5), (7, 1),  (-7, 1), (4, 6),  (-4, 6), (6, 4),  (-6, 4),
+    (2, 7),  (-2, 7), (7, 2),  (-7, 2), (3, 7),  (-3, 7), (7, 3),
+    (-7, 3), (5, 6),  (-5, 6), (6, 5),  (-6, 5), (8, 0),  (4, 7),
+    (-4, 7), (7, 4),  (-7, 4), (8, 1),  (8, 2),  (6, 6),  (-6, 6),
+    (8, 3),  (5, 7),  (-5, 7), (7, 5),  (-7, 5), (8, 4),  (6, 7),
+    (-6, 7), (7, 6),  (-7, 6), (8, 5),  (7, 7),  (-7, 7), (8, 6),
+    (8, 7),
+];
+
+fn invalid(msg: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("Invalid VP8L data: {}", msg),
+    )
+}
+
+pub struct VP8LImage {
+    pub width: usize,
+    pub height: usize,
+    pub has_alpha: bool,
+    // ARGB
+    pub pixels: Vec<u32>,
+}
+
+// Only reads the header, used to get the size of the image without decoding it
+pub fn dimensions(bytes: &[u8]) -> Result<(usize, usize, bool)> {
+    let mut reader = BitReader::new(bytes);
+    if reader.read_bits(8)? != SIGNATURE as u32 {
+        return Err(invalid("wrong signature"));
+    }
+
+    let width = reader.read_bits(14)? as usize + 1;
+    let height = reader.read_bits(14)? as usize + 1;
+    let has_alpha = reader.read_bits(1)? == 1;
+
+    if reader.read_bits(3)? != 0 {
+        return Err(invalid("unknown version"));
+    }
+
+    Ok((width, height, has_alpha))
+}
+
+pub fn decode(bytes: &[u8]) -> Result<VP8LImage> {
+    let (width, height, has_alpha) = dimensions(bytes)?;
+
+    // The header is exactly 5 bytes
+    let mut reader = BitReader::new(&bytes[5..]);
+    let pixels = decode_image_stream(&mut reader, width, height, true)?;
+
+    Ok(VP8LImage {
+        width,
+        height,
+        has_alpha,
+        pixels,
+    })
+}
+
+// The ARGB image itself is at level 0, while the transform data, color table and entropy image
+// are sub images with a simpler structure
+fn decode_image_stream(
+    reader: &mut BitReader,
+    width: usize,
+    height: usize,
+    is_level0: bool,
+) -> Result<Vec<u32>> {
+    let mut transforms = Vec::new();
+    let mut xsize = width;
+
+    if is_level0 {
+        let mut seen = [false; 4];
+        while reader.read_bits(1)? == 1 {
+            let transform_type = reader.read_bits(2)? as usize;
+            if seen[transform_type] {
+                return Err(invalid("transform used more than once"));
+            }
+            seen[transform_type] = true;
+
+            let transform = read_transform(reader, transform_type, xsize, height)?;
+
+            // Color indexing packs multiple pixels into one, all further data is narrower
+            if let Transform::ColorIndexing { width_bits, .. } = transform {
+                xsize = subsample_size(xsize, width_bits);
+            }
+
+            transforms.push(transform);
+        }
+    }
+
+    let cache_bits = if reader.read_bits(1)? == 1 {
+        let bits = reader.read_bits(4)?;
+        if !(1..=MAX_CACHE_BITS).contains(&bits) {
+            return Err(invalid("color cache size out of range"));
+        }
+        bits
+    } else {
+        0
+    };
+
+    let (prefix_bits, entropy_image) = if is_level0 && reader.read_bits(1)? == 1 {
+        let bits = reader.read_bits(3)? + 2;
+        let image = decode_image_stream(
+            reader,
+            subsample_size(xsize, bits),
+            subsample_size(height, bits),
+            false,
+        )?;
+        (bits, Some(image))
+    } else {
+        (0, None)
+    };
+
+    let num_groups = match &entropy_image {
+        Some(image) => image.iter().map(|p| (p >> 8) & 0xffff).max().unwrap_or(0) as usize + 1,
+        None => 1,
+    };
+
+    let mut groups = Vec::with_capacity(num_groups);
+    for _ in 0..num_groups {
+        groups.push(PrefixCodeGroup::read(reader, cache_bits)?);
+    }
+
+    let mut pixels = decode_entropy_coded(
+        reader,
+        xsize,
+        height,
+        cache_bits,
+        &groups,
+        prefix_bits,
+        entropy_image.as_deref(),
+    )?;
+
+    for transform in transforms.iter().rev() {
+        pixels = transform.apply_inverse(pixels, height);
+    }
+
+    Ok(pixels)
+}
+
+fn read_transform(
+    reader: &mut BitReader,
+    transform_type: usize,
+    width: usize,
+    height: usize,
+) -> Result<Transform> {
+    Ok(match transform_type {
+        0 | 1 => {
+            let size_bits = reader.read_bits(3)? + 2;
+            let data = decode_image_stream(
+                reader,
+                subsample_size(width, size_bits),
+                subsample_size(height, size_bits),
+                false,
+            )?;
+
+            if transform_type == 0 {
+                Transform::Predictor {
+                    size_bits,
+                    width,
+                    data,
+                }
+            } else {
+                Transform::Color {
+                    size_bits,
+                    width,
+                    data,
+                }
+            }
+        }
+        2 => Transform::SubtractGreen,
+        _ => {
+            let size = reader.read_bits(8)? as usize + 1;
+            let mut table = decode_image_stream(reader, size, 1, false)?;
+
+            // The table is delta coded
+            for i in 1..size {
+                table[i] = add_pixels(table[i], table[i - 1]);
+            }
+
+            let width_bits = match size {
+                1..=2 => 3,
+                3..=4 => 2,
+                5..=16 => 1,
+                _ => 0,
+            };
+
+            Transform::ColorIndexing {
+                width_bits,
+                width,
+                table,
+            }
+        }
+    })
+}
+
+struct PrefixCodeGroup {
+    green: HuffmanCode,
+    red: HuffmanCode,
+    blue: HuffmanCode,
+    alpha: HuffmanCode,
+    distance: HuffmanCode,
+}
+
+impl PrefixCodeGroup {
+    fn read(reader: &mut BitReader, cache_bits: u32) -> Result<PrefixCodeGroup> {
+        let cache_size = if cache_bits > 0 { 1 << cache_bits } else { 0 };
+
+        Ok(PrefixCodeGroup {
+            green: read_code(reader, NUM_LITERAL_CODES + NUM_LENGTH_CODES + cache_size)?,
+            red: read_code(reader, NUM_LITERAL_CODES)?,
+            blue: read_code(reader, NUM_LITERAL_CODES)?,
+            alpha: read_code(reader, NUM_LITERAL_CODES)?,
+            distance: read_code(reader, NUM_DISTANCE_CODES)?,
+        })
+    }
+}
+
+fn read_code(reader: &mut BitReader, alphabet_size: usize) -> Result<HuffmanCode> {
+    let mut lengths = vec![0u8; alphabet_size];
+
+    // Simple codes have only one or two symbols, each with a code length of 1
+    if reader.read_bits(1)? == 1 {
+        let num_symbols = reader.read_bits(1)? + 1;
+        let first_symbol_bits = if reader.read_bits(1)? == 1 { 8 } else { 1 };
+
+        let mut symbols = vec![reader.read_bits(first_symbol_bits)? as usize];
+        if num_symbols == 2 {
+            symbols.push(reader.read_bits(8)? as usize);
+        }
+
+        for symbol in symbols {
+            match lengths.get_mut(symbol) {
+                Some(length) => *length = 1,
+                None => return Err(invalid("symbol out of range")),
+            }
+        }
+
+        return HuffmanCode::from_lengths(&lengths);
+    }
+
+    // Otherwise, the code lengths are themselves prefix coded
+    let mut code_length_lengths = [0u8; 19];
+    let num_code_lengths = reader.read_bits(4)? as usize + 4;
+    for &i in CODE_LENGTH_ORDER.iter().take(num_code_lengths) {
+        code_length_lengths[i] = reader.read_bits(3)? as u8;
+    }
+    let code_length_code = HuffmanCode::from_lengths(&code_length_lengths)?;
+
+    let mut max_symbol = if reader.read_bits(1)? == 1 {
+        let length_bits = 2 + 2 * reader.read_bits(3)?;
+        2 + reader.read_bits(length_bits)? as usize
+    } else {
+        alphabet_size
+    };
+
+    let mut prev_length = 8;
+    let mut symbol = 0;
+    while symbol < alphabet_size {
+        if max_symbol == 0 {
+            break;
+        }
+        max_symbol -= 1;
+
+        let code = code_length_code.decode(reader)?;
+        let (repeat, length) = match code {
+            0..=15 => {
+                lengths[symbol] = code as u8;
+                symbol += 1;
+                if code != 0 {
+                    prev_length = code as u8;
+                }
+                continue;
+            }
+            16 => (3 + reader.read_bits(2)? as usize, prev_length),
+            17 => (3 + reader.read_bits(3)? as usize, 0),
+            _ => (11 + reader.read_bits(7)? as usize, 0),
+        };
+
+        if symbol + repeat > alphabet_size {
+            return Err(invalid("code lengths out of range"));
+        }
+        for l in &mut lengths[symbol..symbol + repeat] {
+            *l = length;
+        }
+        symbol += repeat;
+    }
+
+    HuffmanCode::from_lengths(&lengths)
+}
+
+// Lengths and distances are stored as a prefix code followed by extra bits
+fn prefix_value(prefix: u16, reader: &mut BitReader) -> Result<usize> {
+    let prefix = prefix as u32;
+    if prefix < 4 {
+        return Ok(prefix as usize + 1);
+    }
+
+    let extra_bits = (prefix - 2) >> 1;
+    let offset = (2 + (prefix & 1)) << extra_bits;
+    Ok((offset + reader.read_bits(extra_bits)?) as usize + 1)
+}
+
+fn distance(code: usize, width: usize) -> usize {
+    if code > DISTANCE_MAP.len() {
+        return code - DISTANCE_MAP.len();
+    }
+
+    let (dx, dy) = DISTANCE_MAP[code - 1];
+    let dist = dx as isize + dy as isize * width as isize;
+    dist.max(1) as usize
+}
+
+struct ColorCache {
+    bits: u32,
+    colors: Vec<u32>,
+}
+
+impl ColorCache {
+    fn new(bits: u32) -> ColorCache {
+        ColorCache {
+            bits,
+            colors: vec![0; 1 << bits],
+        }
+    }
+
+    fn insert(&mut self, argb: u32) {
+        let key = (0x1e35a7bd_u32.wrapping_mul(argb) >> (32 - self.bits)) as usize;
+        self.colors[key] = argb;
+    }
+}
+
+fn decode_entropy_coded(
+    reader: &mut BitReader,
+    width: usize,
+    height: usize,
+    cache_bits: u32,
+    groups: &[PrefixCodeGroup],
+    prefix_bits: u32,
+    entropy_image: Option<&[u32]>,
+) -> Result<Vec<u32>> {
+    let total = width * height;
+    let mut pixels = vec![0u32; total];
+    let mut cache = if cache_bits > 0 {
+        Some(ColorCache::new(cache_bits))
+    } else {
+        None
+    };
+    let entropy_width = subsample_size(width, prefix_bits);
+
+    let mut pos = 0;
+    let mut last_cached = 0;
+
+    while pos < total {
+        let group = match entropy_image {
+            Some(image) => {
+                let (x, y) = (pos % width, pos / width);
+                let meta = image[(y >> prefix_bits) * entropy_width + (x >> prefix_bits)];
+                &groups[((meta >> 8) & 0xffff) as usize]
+            }
+            None => &groups[0],
+        };
+
+        let code = group.green.decode(reader)? as usize;
+
+        if code < NUM_LITERAL_CODES {
+            let red = group.red.decode(reader)? as u32;
+            let blue = group.blue.decode(reader)? as u32;
+            let alpha = group.alpha.decode(reader)? as u32;
+            pixels[pos] = (alpha << 24) | (red << 16) | ((code as u32) << 8) | blue;
+            pos += 1;
+        } else if code < NUM_LITERAL_CODES + NUM_LENGTH_CODES {
+            let length = prefix_value((code - NUM_LITERAL_CODES) as u16, reader)?;
+            let dist_code = prefix_value(group.distance.decode(reader)?, reader)?;
+            let dist = distance(dist_code, width);
+
+            if dist > pos || pos + length > total {
+                return Err(invalid("backward reference out of bounds"));
+            }
+
+            for i in pos..pos + length {
+                pixels[i] = pixels[i - dist];
+            }
+            pos += length;
+        } else {
+            let index = code - NUM_LITERAL_CODES - NUM_LENGTH_CODES;
+            pixels[pos] = match &cache {
+                Some(cache) => cache.colors[index],
+                None => return Err(invalid("color cache used without a cache")),
+            };
+            pos += 1;
+        }
+
+        if let Some(cache) = &mut cache {
+            while last_cached < pos {
+                cache.insert(pixels[last_cached]);
+                last_cached += 1;
+            }
+        }
+    }
+
+    Ok(pixels)
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    // Writes bits starting from the least significant bit of each byte
+    pub struct BitWriter {
+        bytes: Vec<u8>,
+        nbits: usize,
+    }
+
+    impl BitWriter {
+        pub fn write(&mut self, value: u32, n: usize) {
+            for i in 0..n {
+                if self.nbits == self.bytes.len() * 8 {
+                    self.bytes.push(0);
+                }
+                self.bytes[self.nbits / 8] |= (((value >> i) & 1) as u8) << (self.nbits % 8);
+                self.nbits += 1;
+            }
+        }
+
+        // Codes only ever have one or two symbols, so they are at most a single bit long
+        fn symbol(&mut self, symbols: &[usize], symbol: usize) {
+            if symbols.len() == 2 {
+                self.write((symbols[1] == symbol) as u32, 1);
+            }
+        }
+    }
+
+    fn simple_code(w: &mut BitWriter, symbols: &[usize]) {
+        w.write(1, 1);
+        w.write(symbols.len() as u32 - 1, 1);
+        w.write(1, 1);
+        for &symbol in symbols {
+            w.write(symbol as u32, 8);
+        }
+    }
+
+    // A normal code whose code lengths are coded with a code length code for just 0 and 1
+    fn normal_code(w: &mut BitWriter, symbols: &[usize], alphabet_size: usize) {
+        w.write(0, 1);
+        w.write(0, 4);
+        for length in [0, 0, 1, 1].iter() {
+            w.write(*length, 3);
+        }
+        w.write(0, 1);
+        for symbol in 0..alphabet_size {
+            w.write(symbols.contains(&symbol) as u32, 1);
+        }
+    }
+
+    // Writes the prefix codes and pixels of an image, each channel may only have two values
+    fn literals(w: &mut BitWriter, pixels: &[u32]) {
+        let mut channels = vec![Vec::new(); 4];
+        for &p in pixels {
+            for (i, shift) in [8, 16, 0, 24].iter().enumerate() {
+                let value = ((p >> shift) & 0xff) as usize;
+                if !channels[i].contains(&value) {
+                    channels[i].push(value);
+                }
+            }
+        }
+
+        for channel in &mut channels {
+            channel.sort_unstable();
+            simple_code(w, channel);
+        }
+        simple_code(w, &[0]);
+
+        for &p in pixels {
+            for (i, shift) in [8, 16, 0, 24].iter().enumerate() {
+                w.symbol(&channels[i], ((p >> shift) & 0xff) as usize);
+            }
+        }
+    }
+
+    fn sub_image(w: &mut BitWriter, pixels: &[u32]) {
+        w.write(0, 1);
+        literals(w, pixels);
+    }
+
+    // Encodes an image without a color cache or entropy image, after writing its transforms
+    pub fn encode(
+        width: usize,
+        height: usize,
+        transforms: impl Fn(&mut BitWriter),
+        pixels: &[u32],
+    ) -> Vec<u8> {
+        let mut w = header(width, height);
+        transforms(&mut w);
+        w.write(0, 1);
+        w.write(0, 1);
+        w.write(0, 1);
+        literals(&mut w, pixels);
+        w.bytes
+    }
+
+    fn header(width: usize, height: usize) -> BitWriter {
+        let mut w = BitWriter {
+            bytes: Vec::new(),
+            nbits: 0,
+        };
+        w.write(SIGNATURE as u32, 8);
+        w.write(width as u32 - 1, 14);
+        w.write(height as u32 - 1, 14);
+        w.write(1, 1);
+        w.write(0, 3);
+        w
+    }
+
+    fn decode_pixels(bytes: &[u8]) -> Vec<u32> {
+        decode(bytes).unwrap().pixels
+    }
+
+    #[test]
+    fn lossless() {
+        let pixels = [0xffff0000, 0xff00ff00, 0xff0000ff, 0x00ffffff];
+        let image = decode(&encode(2, 2, |_| {}, &pixels)).unwrap();
+        assert_eq!((image.width, image.height, image.has_alpha), (2, 2, true));
+        assert_eq!(image.pixels, pixels);
+    }
+
+    #[test]
+    fn predictor_transform() {
+        // Only the bottom right pixel uses the mode, the others predict from the left or top
+        let bytes = encode(
+            2,
+            2,
+            |w| {
+                w.write(1, 1);
+                w.write(0, 2);
+                w.write(0, 3);
+                // Predict from the top
+                sub_image(w, &[2 << 8]);
+            },
+            &[0x00202020, 0x00202020, 0, 0],
+        );
+        assert_eq!(
+            decode_pixels(&bytes),
+            [0xff202020, 0xff404040, 0xff202020, 0xff404040]
+        );
+    }
+
+    #[test]
+    fn color_transform() {
+        let bytes = encode(
+            2,
+            1,
+            |w| {
+                w.write(1, 1);
+                w.write(1, 2);
+                w.write(0, 3);
+                // Adds green to red and subtracts it from blue
+                sub_image(w, &[0x0000e020]);
+            },
+            &[0xff102030, 0xff000000],
+        );
+        assert_eq!(decode_pixels(&bytes), [0xff302010, 0xff000000]);
+    }
+
+    #[test]
+    fn subtract_green() {
+        let bytes = encode(
+            2,
+            1,
+            |w| {
+                w.write(1, 1);
+                w.write(2, 2);
+            },
+            &[0xff102030, 0xff00f0f0],
+        );
+        // The second pixel wraps around
+        assert_eq!(decode_pixels(&bytes), [0xff302050, 0xfff0f0e0]);
+    }
+
+    #[test]
+    fn color_indexing() {
+        let (blue, red) = (0xff0000ff, 0xffff0000);
+        // The two colors each take one bit, so both pixels of a row are packed into one
+        let bytes = encode(
+            2,
+            2,
+            |w| {
+                w.write(1, 1);
+                w.write(3, 2);
+                w.write(1, 8);
+                // The table is delta coded
+                sub_image(w, &[blue, 0x00ff0001]);
+            },
+            &[0x0100, 0x0200],
+        );
+        assert_eq!(decode_pixels(&bytes), [red, blue, blue, red]);
+    }
+
+    #[test]
+    fn repeated_transform() {
+        let bytes = encode(
+            1,
+            1,
+            |w| {
+                w.write(1, 1);
+                w.write(2, 2);
+                w.write(1, 1);
+                w.write(2, 2);
+            },
+            &[0],
+        );
+        assert_eq!(decode(&bytes).err().unwrap().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn backward_reference() {
+        let (a, b) = (0xff112233, 0xff442233);
+        let mut w = header(2, 2);
+        w.write(0, 3);
+        // Green also codes a length of 2, with the distance code for the pixel above
+        let green = [0x22, NUM_LITERAL_CODES + 1];
+        normal_code(&mut w, &green, NUM_LITERAL_CODES + NUM_LENGTH_CODES);
+        simple_code(&mut w, &[0x11, 0x44]);
+        simple_code(&mut w, &[0x33]);
+        simple_code(&mut w, &[0xff]);
+        simple_code(&mut w, &[0]);
+
+        w.write(0, 2);
+        w.write(0, 1);
+        w.write(1, 1);
+        w.write(1, 1);
+        assert_eq!(decode_pixels(&w.bytes), [a, b, a, b]);
+    }
+
+    #[test]
+    fn color_cache() {
+        let color = 0xff112233;
+        let index = (0x1e35a7bd_u32.wrapping_mul(color) >> 31) as usize;
+
+        let mut w = header(2, 1);
+        w.write(0, 1);
+        // A single bit cache
+        w.write(1, 1);
+        w.write(1, 4);
+        w.write(0, 1);
+        let green = [0x22, NUM_LITERAL_CODES + NUM_LENGTH_CODES + index];
+        normal_code(&mut w, &green, NUM_LITERAL_CODES + NUM_LENGTH_CODES + 2);
+        simple_code(&mut w, &[0x11]);
+        simple_code(&mut w, &[0x33]);
+        simple_code(&mut w, &[0xff]);
+        simple_code(&mut w, &[0]);
+
+        w.write(0, 1);
+        w.write(1, 1);
+        assert_eq!(decode_pixels(&w.bytes), [color, color]);
+    }
+}